@@ -0,0 +1,239 @@
+use crate::gitopolis::GitopolisError;
+use crate::gitopolis::GitopolisError::StateError;
+use serde_derive::{Deserialize, Serialize};
+
+/// A named command from the `[aliases]` section of the state file, run in each repo by
+/// `gitopolis run <name>`. Either just the command:
+///
+/// ```toml
+/// [aliases]
+/// st = "git status --short"
+/// ```
+///
+/// or a table with defaults for the options exec takes:
+///
+/// ```toml
+/// [aliases.last]
+/// command = ["git", "log", "-n", "{1}", "--format=%h %s"]
+/// tag = ["backend"]
+/// oneline = true
+/// parallel = 4
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alias {
+	/// The command and its arguments, as given to exec. A single string is run by the shell.
+	pub command: Vec<String>,
+	/// Tag filter used unless one is given on the command line, same as exec's `--tag`.
+	pub tag: Vec<String>,
+	/// Tag expression used unless one is given on the command line, same as exec's `--filter`.
+	pub filter: Option<String>,
+	pub oneline: bool,
+	pub parallel: Option<usize>,
+	pub description: Option<String>,
+}
+
+/// How an alias can be written in the state file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum AliasEntry {
+	Command(Command),
+	Full(FullAlias),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Command {
+	Shell(String),
+	Args(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FullAlias {
+	command: Command,
+	#[serde(default)]
+	tag: Vec<String>,
+	filter: Option<String>,
+	#[serde(default)]
+	oneline: bool,
+	parallel: Option<usize>,
+	description: Option<String>,
+}
+
+impl From<Command> for Vec<String> {
+	fn from(command: Command) -> Self {
+		match command {
+			Command::Shell(command) => vec![command],
+			Command::Args(args) => args,
+		}
+	}
+}
+
+impl From<AliasEntry> for Alias {
+	fn from(entry: AliasEntry) -> Self {
+		match entry {
+			AliasEntry::Command(command) => Alias {
+				command: command.into(),
+				tag: vec![],
+				filter: None,
+				oneline: false,
+				parallel: None,
+				description: None,
+			},
+			AliasEntry::Full(alias) => Alias {
+				command: alias.command.into(),
+				tag: alias.tag,
+				filter: alias.filter,
+				oneline: alias.oneline,
+				parallel: alias.parallel,
+				description: alias.description,
+			},
+		}
+	}
+}
+
+impl Alias {
+	/// The command to run with `args` filled in. `{1}`, `{2}` etc. are replaced with the
+	/// argument in that position and `{@}` with all of them: as separate arguments where it's a
+	/// whole argument on its own, otherwise joined with spaces. Values are put in as they are,
+	/// without quoting. If the command has no placeholders the arguments are added to the end.
+	pub fn expand(&self, name: &str, args: &[String]) -> Result<Vec<String>, GitopolisError> {
+		let mut highest = 0;
+		let mut uses_all = false;
+		for part in &self.command {
+			for placeholder in placeholders(part) {
+				match placeholder {
+					Placeholder::All => uses_all = true,
+					Placeholder::Position(position) => highest = highest.max(position),
+				}
+			}
+		}
+		if !uses_all && highest == 0 {
+			let mut command = self.command.clone();
+			match command.as_mut_slice() {
+				[shell_command] if !args.is_empty() => {
+					shell_command.push(' ');
+					shell_command.push_str(&args.join(" "));
+				}
+				_ => command.extend(args.iter().cloned()),
+			}
+			return Ok(command);
+		}
+		if args.len() < highest || (!uses_all && args.len() > highest) {
+			return Err(StateError {
+				message: format!(
+					"Alias '{name}' takes {highest} arguments, got {}",
+					args.len()
+				),
+			});
+		}
+
+		let mut command = vec![];
+		for part in &self.command {
+			if part == "{@}" {
+				command.extend(args.iter().cloned());
+				continue;
+			}
+			let mut expanded = String::new();
+			let mut rest = part.as_str();
+			while let Some((before, placeholder, after)) = next_placeholder(rest) {
+				expanded.push_str(before);
+				match placeholder {
+					Placeholder::All => expanded.push_str(&args.join(" ")),
+					Placeholder::Position(position) => expanded.push_str(&args[position - 1]),
+				}
+				rest = after;
+			}
+			expanded.push_str(rest);
+			command.push(expanded);
+		}
+		Ok(command)
+	}
+}
+
+enum Placeholder {
+	All,
+	Position(usize),
+}
+
+fn placeholders(part: &str) -> Vec<Placeholder> {
+	let mut found = vec![];
+	let mut rest = part;
+	while let Some((_, placeholder, after)) = next_placeholder(rest) {
+		found.push(placeholder);
+		rest = after;
+	}
+	found
+}
+
+/// The text before the first placeholder in `text`, the placeholder and the text after it.
+/// Braces around anything else are left as they are.
+fn next_placeholder(text: &str) -> Option<(&str, Placeholder, &str)> {
+	let mut search_from = 0;
+	while let Some(open) = text[search_from..].find('{').map(|i| i + search_from) {
+		if let Some(close) = text[open..].find('}').map(|i| i + open) {
+			let placeholder = match &text[open + 1..close] {
+				"@" => Some(Placeholder::All),
+				number => number
+					.parse()
+					.ok()
+					.filter(|position| *position > 0)
+					.map(Placeholder::Position),
+			};
+			if let Some(placeholder) = placeholder {
+				return Some((&text[..open], placeholder, &text[close + 1..]));
+			}
+		}
+		search_from = open + 1;
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn alias(command: &[&str]) -> Alias {
+		Alias::from(AliasEntry::Command(Command::Args(
+			command.iter().map(|part| part.to_string()).collect(),
+		)))
+	}
+
+	fn args(args: &[&str]) -> Vec<String> {
+		args.iter().map(|arg| arg.to_string()).collect()
+	}
+
+	#[test]
+	fn fills_in_positional_arguments() {
+		let last = alias(&["git", "log", "-n", "{1}", "--grep={2}", "{@}"]);
+		assert_eq!(
+			args(&["git", "log", "-n", "3", "--grep=fix", "3", "fix"]),
+			last.expand("last", &args(&["3", "fix"])).unwrap()
+		);
+		let shell = alias(&["git log -n {1} | head -{1}"]);
+		assert_eq!(
+			args(&["git log -n 2 | head -2"]),
+			shell.expand("shell", &args(&["2"])).unwrap()
+		);
+		assert_eq!(
+			"Alias 'shell' takes 1 arguments, got 0",
+			shell.expand("shell", &[]).unwrap_err().message()
+		);
+	}
+
+	#[test]
+	fn appends_arguments_without_placeholders() {
+		assert_eq!(
+			args(&["git", "status", "--short"]),
+			alias(&["git", "status"])
+				.expand("st", &args(&["--short"]))
+				.unwrap()
+		);
+		assert_eq!(
+			args(&["git status --short"]),
+			alias(&["git status"])
+				.expand("st", &args(&["--short"]))
+				.unwrap()
+		);
+	}
+}
@@ -0,0 +1,198 @@
+use crate::gitopolis::{CloneSummary, Gitopolis, GitopolisError};
+use crate::output::{format_records, OutputFormat};
+use crate::repos::Repo;
+use crate::tag_filter::TagFilter;
+use clap::ValueEnum;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// Something `doctor` found out of step between the config and the folders next to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+	/// A configured repo's folder doesn't exist.
+	Missing { path: String },
+	/// A configured repo's folder exists but can't be opened as a git repo.
+	NotARepo { path: String, message: String },
+	/// A git repo found on disk that isn't in the config.
+	Unmanaged { path: String },
+	/// A configured repo's remotes on disk don't match those in the config.
+	RemotesDiffer {
+		path: String,
+		differences: Vec<RemoteDifference>,
+	},
+}
+
+impl Problem {
+	pub fn path(&self) -> &str {
+		match self {
+			Problem::Missing { path }
+			| Problem::NotARepo { path, .. }
+			| Problem::Unmanaged { path }
+			| Problem::RemotesDiffer { path, .. } => path,
+		}
+	}
+}
+
+/// How one remote differs between the config and the repo on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "difference", rename_all = "snake_case")]
+pub enum RemoteDifference {
+	NotInRepo {
+		name: String,
+		url: String,
+	},
+	NotInConfig {
+		name: String,
+		url: String,
+	},
+	UrlDiffers {
+		name: String,
+		config_url: String,
+		repo_url: String,
+	},
+}
+
+/// What `doctor --fix` should do about the problems it finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Fix {
+	/// Remove repos whose folders are missing from the config
+	Prune,
+	/// Clone repos whose folders are missing
+	Clone,
+	/// Add git repos that aren't in the config
+	Add,
+}
+
+/// What `fix` did: how many missing repos it removed from the config, how cloning them went
+/// and how many unmanaged repos it added.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FixSummary {
+	pub pruned: usize,
+	pub cloned: Option<CloneSummary>,
+	pub added: usize,
+}
+
+impl FixSummary {
+	/// How many problems were dealt with. Missing repos that couldn't be cloned, or had no remotes
+	/// to clone from, are still missing.
+	pub fn fixed(&self) -> usize {
+		let cloned = self
+			.cloned
+			.map_or(0, |cloned| cloned.cloned + cloned.skipped);
+		self.pruned + cloned + self.added
+	}
+}
+
+/// The remotes that are only in the config, only in the repo, or have a different url in each.
+pub(crate) fn remote_differences(
+	repo: &Repo,
+	repo_remotes: &BTreeMap<String, String>,
+) -> Vec<RemoteDifference> {
+	let mut differences = vec![];
+	for (name, remote) in &repo.remotes {
+		match repo_remotes.get(name) {
+			None => differences.push(RemoteDifference::NotInRepo {
+				name: name.to_string(),
+				url: remote.url.to_string(),
+			}),
+			Some(url) if *url != remote.url => differences.push(RemoteDifference::UrlDiffers {
+				name: name.to_string(),
+				config_url: remote.url.to_string(),
+				repo_url: url.to_string(),
+			}),
+			Some(_) => {}
+		}
+	}
+	for (name, url) in repo_remotes {
+		if !repo.remotes.contains_key(name) {
+			differences.push(RemoteDifference::NotInConfig {
+				name: name.to_string(),
+				url: url.to_string(),
+			});
+		}
+	}
+	differences
+}
+
+/// The problems found, a line each in the 🏢 style followed by a count, or as records.
+/// `state_file` is how the config is named in the messages.
+pub fn format_problems(problems: &[Problem], state_file: &str, output: OutputFormat) -> String {
+	if output != OutputFormat::Text {
+		return format_records(output, problems);
+	}
+	let mut lines = vec![];
+	for problem in problems {
+		let path = problem.path();
+		match problem {
+			Problem::Missing { .. } => lines.push(format!("🏢 {path}> Folder missing")),
+			Problem::NotARepo { message, .. } => {
+				lines.push(format!("🏢 {path}> Not a git repo. {message}"))
+			}
+			Problem::Unmanaged { .. } => {
+				lines.push(format!("🏢 {path}> Git repo not in {state_file}"))
+			}
+			Problem::RemotesDiffer { differences, .. } => {
+				for difference in differences {
+					lines.push(match difference {
+						RemoteDifference::NotInRepo { name, url } => format!(
+							"🏢 {path}> Remote {name} ({url}) is in {state_file} but not the repo"
+						),
+						RemoteDifference::NotInConfig { name, url } => format!(
+							"🏢 {path}> Remote {name} ({url}) is in the repo but not {state_file}"
+						),
+						RemoteDifference::UrlDiffers {
+							name,
+							config_url,
+							repo_url,
+						} => format!(
+							"🏢 {path}> Remote {name} is {config_url} in {state_file} but {repo_url} in the repo"
+						),
+					});
+				}
+			}
+		}
+	}
+	lines.push(match problems.len() {
+		0 => "No problems found".to_string(),
+		count => format!("{count} problems found"),
+	});
+	lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+/// Apply `fixes` to the problems found: remove or clone the missing repos, and add the unmanaged
+/// ones. `prune` and `clone` both act on missing repos so shouldn't be asked for together.
+pub fn fix(
+	gitopolis: &mut Gitopolis,
+	problems: &[Problem],
+	fixes: &[Fix],
+	parallel: usize,
+) -> Result<FixSummary, GitopolisError> {
+	let paths_with = |wanted: fn(&Problem) -> bool| -> Vec<String> {
+		problems
+			.iter()
+			.filter(|problem| wanted(problem))
+			.map(|problem| problem.path().to_string())
+			.collect()
+	};
+	let missing = paths_with(|problem| matches!(problem, Problem::Missing { .. }));
+	let unmanaged = paths_with(|problem| matches!(problem, Problem::Unmanaged { .. }));
+	let mut summary = FixSummary::default();
+	if fixes.contains(&Fix::Prune) && !missing.is_empty() {
+		gitopolis.remove(&missing)?;
+		summary.pruned = missing.len();
+	}
+	if fixes.contains(&Fix::Clone) && !missing.is_empty() {
+		let repos = gitopolis
+			.list(&TagFilter::all())?
+			.into_iter()
+			.filter(|repo| missing.contains(&repo.path))
+			.collect();
+		summary.cloned = Some(gitopolis.clone(repos, parallel).summary());
+	}
+	if fixes.contains(&Fix::Add) && !unmanaged.is_empty() {
+		gitopolis.add_repos(unmanaged.clone(), &[])?;
+		summary.added = unmanaged.len();
+	}
+	Ok(summary)
+}
@@ -1,54 +1,262 @@
+use crate::gitopolis::GitopolisError;
+use crate::gitopolis::GitopolisError::IoError;
+use crate::observer::{Event, Observer, RepoOutcome};
+use crate::parallel::run_in_order;
 use crate::repos::Repo;
 use std::env;
-use std::io::{BufRead, BufReader, Error, Read};
-use std::process::{Child, Command, ExitStatus, Stdio};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running command is checked for its timeout or the run being cancelled.
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a cancelled command has to exit after being interrupted before it's killed.
+const CANCEL_GRACE: Duration = Duration::from_secs(2);
+
+/// Limits on running the command in each repo.
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+	/// Kill the command, and anything it started, if it's still running after this long.
+	pub timeout: Option<Duration>,
+	/// Set this, e.g. from a Ctrl-C handler, to interrupt the commands running and not start any
+	/// more. Commands that don't exit within a couple of seconds of being interrupted are killed.
+	pub cancel: Arc<AtomicBool>,
+	/// `cancel` is set by the terminal's Ctrl-C, which commands without their own process group
+	/// (those run without a timeout) have had already, so they aren't sent another.
+	pub cancel_from_terminal: bool,
+}
 
-pub fn exec(exec_args: Vec<String>, repos: Vec<Repo>, oneline: bool) {
-	let mut error_count = 0;
-	let mut skipped_count = 0;
-	for repo in &repos {
-		if !exists(&repo.path) {
-			if oneline {
-				println!("{}\tRepo folder missing, skipped.", &repo.path);
-			} else {
-				println!();
-				println!("🏢 {}> Repo folder missing, skipped.", &repo.path);
+/// Which of a command's output streams a line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+	Stdout,
+	Stderr,
+}
+
+/// What happened when running the command in one repo.
+#[derive(Debug)]
+pub enum ExecOutcome {
+	/// The repo folder doesn't exist, so the command wasn't run.
+	Missing,
+	/// The command couldn't be started.
+	Failed(GitopolisError),
+	/// The command ran to completion. `exit_code` is None if it was killed by a signal.
+	Exited {
+		exit_code: Option<i32>,
+		duration: Duration,
+	},
+	/// The command was still running when the timeout ran out, so it was killed.
+	TimedOut { duration: Duration },
+	/// The run was cancelled. `duration` is how long the command had been running when it was
+	/// stopped, None if it hadn't been started.
+	Cancelled { duration: Option<Duration> },
+}
+
+impl ExecOutcome {
+	pub fn success(&self) -> bool {
+		matches!(
+			self,
+			ExecOutcome::Exited {
+				exit_code: Some(0),
+				..
 			}
-			skipped_count += 1;
-			continue;
-		}
-		if oneline {
-			let (output, success) =
-				repo_exec_oneline(&repo.path, &exec_args).expect("Failed to execute command.");
-			match output {
-				Some(output_text) => println!("{}\t{}", &repo.path, output_text),
-				None => println!("{}\t", &repo.path),
+		)
+	}
+}
+
+/// Outcome of running the command in each repo, in the same order as the repo list.
+#[derive(Debug)]
+pub struct ExecReport {
+	pub repos: Vec<(Repo, ExecOutcome)>,
+}
+
+impl ExecReport {
+	/// Commands that couldn't be started or exited with a non-zero status.
+	pub fn error_count(&self) -> usize {
+		self.repos
+			.iter()
+			.filter(|(_, outcome)| {
+				matches!(outcome, ExecOutcome::Failed(_) | ExecOutcome::Exited { .. })
+					&& !outcome.success()
+			})
+			.count()
+	}
+
+	/// Commands that were killed for running longer than the timeout.
+	pub fn timed_out_count(&self) -> usize {
+		self.repos
+			.iter()
+			.filter(|(_, outcome)| matches!(outcome, ExecOutcome::TimedOut { .. }))
+			.count()
+	}
+
+	/// Repos where the command was stopped or never started because the run was cancelled.
+	pub fn cancelled_count(&self) -> usize {
+		self.repos
+			.iter()
+			.filter(|(_, outcome)| matches!(outcome, ExecOutcome::Cancelled { .. }))
+			.count()
+	}
+
+	/// Repos that were skipped because their folder is missing.
+	pub fn skipped_count(&self) -> usize {
+		self.repos
+			.iter()
+			.filter(|(_, outcome)| matches!(outcome, ExecOutcome::Missing))
+			.count()
+	}
+}
+
+/// Run `exec_args` in each repo. `parallel` is the maximum number of repos to run at once;
+/// 1 (or 0) runs them one after another. Output is passed to `observer` rather than printed:
+/// live when running one repo at a time, otherwise as soon as each repo's turn comes round
+/// after it has finished. Repo paths are relative to `root`, the folder containing the state file.
+/// Each command gets `GITOPOLIS_*` environment variables describing its repo, see `repo_env`.
+/// Repos not started by the time `options.cancel` is set are reported as cancelled.
+pub fn exec(
+	root: &Path,
+	exec_args: &[String],
+	repos: Vec<Repo>,
+	options: &ExecOptions,
+	parallel: usize,
+	observer: &dyn Observer,
+) -> ExecReport {
+	let current_dir = env::current_dir().expect("failed to get current working directory");
+	let root = &if root.as_os_str().is_empty() {
+		current_dir
+	} else {
+		current_dir.join(root)
+	};
+	let outcomes = if parallel > 1 {
+		exec_parallel(root, exec_args, &repos, options, parallel, observer)
+	} else {
+		exec_serial(root, exec_args, &repos, options, observer)
+	};
+	ExecReport {
+		repos: repos.into_iter().zip(outcomes).collect(),
+	}
+}
+
+/// Runs repos one at a time, passing on output as it arrives.
+fn exec_serial(
+	root: &Path,
+	exec_args: &[String],
+	repos: &[Repo],
+	options: &ExecOptions,
+	observer: &dyn Observer,
+) -> Vec<ExecOutcome> {
+	let mut outcomes = vec![];
+	for (index, repo) in repos.iter().enumerate() {
+		let location = root.join(&repo.path);
+		let outcome = if options.cancel.load(Ordering::SeqCst) {
+			ExecOutcome::Cancelled { duration: None }
+		} else if exists(&location) {
+			observer.event(Event::RepoStarted { repo });
+			let env = repo_env(root, repo, index, repos.len());
+			run_streaming(&location, exec_args, &env, options, &mut |stream, line| {
+				observer.event(Event::OutputLine { repo, stream, line })
+			})
+		} else {
+			ExecOutcome::Missing
+		};
+		finished(observer, repo, &outcome);
+		outcomes.push(outcome);
+	}
+	outcomes
+}
+
+/// Lines of output from a finished command, stdout first then stderr. None if it wasn't run.
+type BufferedOutput = Option<Vec<(OutputStream, String)>>;
+
+/// Runs repos on a pool of `parallel` worker threads. Each repo's start is passed on as soon as a
+/// worker picks it up, then its output is buffered and passed on as a whole, in the same order
+/// as the repo list.
+fn exec_parallel(
+	root: &Path,
+	exec_args: &[String],
+	repos: &[Repo],
+	options: &ExecOptions,
+	parallel: usize,
+	observer: &dyn Observer,
+) -> Vec<ExecOutcome> {
+	let mut outcomes = vec![];
+	run_in_order(
+		repos,
+		parallel,
+		|index, repo| {
+			if options.cancel.load(Ordering::SeqCst) {
+				return (None, ExecOutcome::Cancelled { duration: None });
 			}
-			if !success {
-				error_count += 1;
+			let location = root.join(&repo.path);
+			if !exists(&location) {
+				return (None, ExecOutcome::Missing);
 			}
-		} else {
-			let exit_status =
-				repo_exec(&repo.path, &exec_args).expect("Failed to execute command.");
-			if !exit_status.success() {
-				error_count += 1
+			observer.event(Event::RepoStarted { repo });
+			run_buffered(
+				&location,
+				exec_args,
+				&repo_env(root, repo, index, repos.len()),
+				options,
+			)
+		},
+		|repo, (output, outcome): (BufferedOutput, ExecOutcome)| {
+			if let Some(lines) = output {
+				for (stream, line) in &lines {
+					observer.event(Event::OutputLine {
+						repo,
+						stream: *stream,
+						line,
+					});
+				}
 			}
-			println!();
-		}
-	}
-	if error_count > 0 || skipped_count > 0 {
-		if error_count > 0 {
-			eprintln!("{error_count} commands exited with non-zero status code");
-		}
-		if skipped_count > 0 {
-			eprintln!("{skipped_count} repos skipped");
-		}
-		std::process::exit(1);
-	}
+			finished(observer, repo, &outcome);
+			outcomes.push(outcome);
+		},
+	);
+	outcomes
 }
 
-fn exists(repo_path: &String) -> bool {
+fn finished(observer: &dyn Observer, repo: &Repo, outcome: &ExecOutcome) {
+	observer.event(Event::RepoFinished {
+		repo,
+		outcome: RepoOutcome::Exec(outcome),
+	});
+}
+
+/// `GITOPOLIS_*` variables set for the command in each repo.
+type RepoEnv = Vec<(&'static str, String)>;
+
+/// Environment variables describing the repo a command is running in, so that shared scripts
+/// can act on its tags or remotes without reading `.gitopolis.toml` themselves.
+/// `index` is zero-based but exposed one-based, alongside the total number of repos being run.
+/// All are always set, empty if not applicable, so values never leak in from a parent gitopolis.
+fn repo_env(root: &Path, repo: &Repo, index: usize, total: usize) -> RepoEnv {
+	let name = Path::new(&repo.path)
+		.file_name()
+		.map(|name| name.to_string_lossy().to_string())
+		.unwrap_or_else(|| repo.path.clone());
+	let origin_url = repo
+		.remotes
+		.get("origin")
+		.map(|remote| remote.url.clone())
+		.unwrap_or_default();
+	vec![
+		("GITOPOLIS_ROOT", root.to_string_lossy().to_string()),
+		("GITOPOLIS_REPO_PATH", repo.path.clone()),
+		("GITOPOLIS_REPO_NAME", name),
+		("GITOPOLIS_REPO_TAGS", repo.tags.join(",")),
+		("GITOPOLIS_REMOTE_ORIGIN_URL", origin_url),
+		("GITOPOLIS_REPO_INDEX", (index + 1).to_string()),
+		("GITOPOLIS_REPO_COUNT", total.to_string()),
+	]
+}
+
+fn exists(repo_path: &Path) -> bool {
 	let mut path = env::current_dir().expect("failed to get current working directory");
 	path.push(repo_path);
 	path.exists() && path.is_dir()
@@ -93,7 +301,7 @@ fn needs_quoting(arg: &str) -> bool {
 /// - Arguments matching `--flag=value` pattern: quote only the value portion
 ///   for better readability (e.g., `--since='One Week'` vs `'--since=One Week'`)
 /// - Values containing single quotes: use double quotes with escaping
-fn format_args_for_display(args: &[String]) -> String {
+pub fn format_args_for_display(args: &[String]) -> String {
 	args.iter()
 		.map(|arg| {
 			if needs_quoting(arg) {
@@ -148,45 +356,31 @@ fn quote_arg(arg: &str) -> String {
 	}
 }
 
-fn repo_exec(path: &str, exec_args: &[String]) -> Result<ExitStatus, Error> {
-	println!();
-	println!("🏢 {}> {}", path, format_args_for_display(exec_args));
-
-	// If single argument, pass directly to shell for interpretation (supports pipes, etc.)
-	// If multiple arguments, pass via positional parameters to avoid quoting issues
+/// Build the shell invocation for running `exec_args` in the repo at `path`.
+///
+/// If single argument, pass directly to shell for interpretation (supports pipes, etc.)
+/// If multiple arguments, pass via positional parameters to avoid quoting issues
+fn shell_command(path: &Path, exec_args: &[String], env: &RepoEnv) -> Command {
 	#[cfg(unix)]
-	let mut child_process: Child = if exec_args.len() == 1 {
-		Command::new("sh")
-			.arg("-c")
-			.arg(&exec_args[0]) // Single arg passed directly for shell interpretation
-			.current_dir(path)
-			.stdin(Stdio::null()) // Prevent interactive prompts/pagers
-			.stdout(Stdio::piped()) // Prevent TTY detection for pagers
-			.stderr(Stdio::piped())
-			.spawn()?
+	let mut command = if exec_args.len() == 1 {
+		let mut command = Command::new("sh");
+		command.arg("-c").arg(&exec_args[0]); // Single arg passed directly for shell interpretation
+		command
 	} else {
-		Command::new("sh")
+		let mut command = Command::new("sh");
+		command
 			.arg("-c")
 			.arg(r#""$@""#) // Execute all positional parameters
 			.arg("--") // $0 placeholder (ignored)
-			.args(exec_args) // These become $1, $2, $3, etc.
-			.current_dir(path)
-			.stdin(Stdio::null()) // Prevent interactive prompts/pagers
-			.stdout(Stdio::piped()) // Prevent TTY detection for pagers
-			.stderr(Stdio::piped())
-			.spawn()?
+			.args(exec_args); // These become $1, $2, $3, etc.
+		command
 	};
 
 	#[cfg(windows)]
-	let mut child_process: Child = if exec_args.len() == 1 {
-		Command::new("cmd")
-			.arg("/C")
-			.arg(&exec_args[0]) // Single arg passed directly for shell interpretation
-			.current_dir(path)
-			.stdin(Stdio::null()) // Prevent interactive prompts/pagers
-			.stdout(Stdio::piped()) // Prevent TTY detection for pagers
-			.stderr(Stdio::piped())
-			.spawn()?
+	let mut command = if exec_args.len() == 1 {
+		let mut command = Command::new("cmd");
+		command.arg("/C").arg(&exec_args[0]); // Single arg passed directly for shell interpretation
+		command
 	} else {
 		// Windows cmd doesn't have an equivalent to sh -c "$@"
 		// We need to join args with proper quoting
@@ -203,17 +397,43 @@ fn repo_exec(path: &str, exec_args: &[String]) -> Result<ExitStatus, Error> {
 			})
 			.collect::<Vec<_>>()
 			.join(" ");
-		Command::new("cmd")
-			.arg("/C")
-			.arg(command_string)
-			.current_dir(path)
-			.stdin(Stdio::null()) // Prevent interactive prompts/pagers
-			.stdout(Stdio::piped()) // Prevent TTY detection for pagers
-			.stderr(Stdio::piped())
-			.spawn()?
+		let mut command = Command::new("cmd");
+		command.arg("/C").arg(command_string);
+		command
 	};
 
-	// Stream stdout and stderr in real-time using threads
+	command
+		.current_dir(path)
+		.envs(env.iter().map(|(key, value)| (key, value)))
+		.stdin(Stdio::null()) // Prevent interactive prompts/pagers
+		.stdout(Stdio::piped()) // Prevent TTY detection for pagers
+		.stderr(Stdio::piped());
+	command
+}
+
+/// Run the command, passing each line of output to `on_line` as it's written.
+fn run_streaming(
+	path: &Path,
+	exec_args: &[String],
+	env: &RepoEnv,
+	options: &ExecOptions,
+	on_line: &mut dyn FnMut(OutputStream, &str),
+) -> ExecOutcome {
+	let mut command = shell_command(path, exec_args, env);
+	// With a timeout the command gets its own process group so that everything it starts can be
+	// killed along with it. Without one it stays in ours, where it can still prompt on the
+	// terminal (e.g. for an ssh passphrase) and gets the terminal's Ctrl-C itself.
+	let own_group = cfg!(unix) && options.timeout.is_some();
+	#[cfg(unix)]
+	if own_group {
+		use std::os::unix::process::CommandExt;
+		command.process_group(0);
+	}
+	let started = Instant::now();
+	let mut child_process = match command.spawn() {
+		Ok(child_process) => child_process,
+		Err(error) => return ExecOutcome::Failed(IoError { inner: error }),
+	};
 	let stdout = child_process
 		.stdout
 		.take()
@@ -222,135 +442,200 @@ fn repo_exec(path: &str, exec_args: &[String]) -> Result<ExitStatus, Error> {
 		.stderr
 		.take()
 		.expect("Failed to capture stderr");
+	let mut running = Running {
+		child: child_process,
+		own_group,
+		interrupted: !own_group && options.cancel_from_terminal,
+		deadline: options.timeout.map(|timeout| started + timeout),
+		stopped: None,
+		killed: false,
+	};
 
-	let stdout_thread = thread::spawn(move || {
-		let reader = BufReader::new(stdout);
-		for line in reader.lines().map_while(Result::ok) {
-			println!("{}", line);
+	// Read both streams on their own threads so neither can block the other
+	let (sender, receiver) = mpsc::channel();
+	let stdout_sender = sender.clone();
+	thread::spawn(move || send_lines(stdout, OutputStream::Stdout, stdout_sender));
+	thread::spawn(move || send_lines(stderr, OutputStream::Stderr, sender));
+	loop {
+		match receiver.recv_timeout(CHECK_INTERVAL) {
+			Ok((stream, line)) => on_line(stream, &line),
+			Err(RecvTimeoutError::Timeout) => {}
+			Err(RecvTimeoutError::Disconnected) => break,
 		}
-	});
-
-	let stderr_thread = thread::spawn(move || {
-		let reader = BufReader::new(stderr);
-		for line in reader.lines().map_while(Result::ok) {
-			eprintln!("{}", line);
+		running.check(&options.cancel);
+		// once it's been killed, anything it left running can keep the output open for ever
+		if running.killed && matches!(running.child.try_wait(), Ok(Some(_)) | Err(_)) {
+			break;
 		}
-	});
-
-	let exit_code = child_process.wait()?;
-
-	// Wait for output threads to finish
-	let _ = stdout_thread.join();
-	let _ = stderr_thread.join();
+	}
 
-	if !exit_code.success() {
-		eprintln!(
-			"Command exited with code {}",
-			exit_code.code().expect("exit code missing")
-		);
+	// the output can be closed before the command exits, so keep checking until it does
+	let exit_status = loop {
+		match running.child.try_wait() {
+			Ok(Some(exit_status)) => break exit_status,
+			Ok(None) => {
+				running.check(&options.cancel);
+				thread::sleep(CHECK_INTERVAL);
+			}
+			Err(error) => return ExecOutcome::Failed(IoError { inner: error }),
+		}
+	};
+	let duration = started.elapsed();
+	#[cfg(unix)]
+	if running.interrupted && interrupted_by(exit_status) {
+		// the terminal's Ctrl-C can stop the command before our own handler has cancelled the
+		// run, so wait for that rather than start the next repo
+		let waiting = Instant::now();
+		while !options.cancel.load(Ordering::SeqCst) && waiting.elapsed() < CANCEL_GRACE {
+			thread::sleep(CHECK_INTERVAL);
+		}
+	}
+	match running.stopped {
+		Some((Stop::TimedOut, _)) => ExecOutcome::TimedOut { duration },
+		Some((Stop::Cancelled, _)) => ExecOutcome::Cancelled {
+			duration: Some(duration),
+		},
+		// it got the terminal's Ctrl-C before we noticed the run was cancelled
+		None if !exit_status.success() && options.cancel.load(Ordering::SeqCst) => {
+			ExecOutcome::Cancelled {
+				duration: Some(duration),
+			}
+		}
+		None => ExecOutcome::Exited {
+			exit_code: exit_status.code(),
+			duration,
+		},
 	}
-	Ok(exit_code)
 }
 
-fn repo_exec_oneline(path: &str, exec_args: &[String]) -> Result<(Option<String>, bool), Error> {
-	// If single argument, pass directly to shell for interpretation (supports pipes, etc.)
-	// If multiple arguments, pass via positional parameters to avoid quoting issues
-	#[cfg(unix)]
-	let mut child_process: Child = if exec_args.len() == 1 {
-		Command::new("sh")
-			.arg("-c")
-			.arg(&exec_args[0]) // Single arg passed directly for shell interpretation
-			.current_dir(path)
-			.stdin(Stdio::null())
-			.stdout(Stdio::piped())
-			.stderr(Stdio::piped())
-			.spawn()?
-	} else {
-		Command::new("sh")
-			.arg("-c")
-			.arg(r#""$@""#) // Execute all positional parameters
-			.arg("--") // $0 placeholder (ignored)
-			.args(exec_args) // These become $1, $2, $3, etc.
-			.current_dir(path)
-			.stdin(Stdio::null())
-			.stdout(Stdio::piped())
-			.stderr(Stdio::piped())
-			.spawn()?
-	};
+/// Why a running command was stopped early.
+#[derive(Clone, Copy)]
+enum Stop {
+	TimedOut,
+	Cancelled,
+}
 
-	#[cfg(windows)]
-	let mut child_process: Child = if exec_args.len() == 1 {
-		Command::new("cmd")
-			.arg("/C")
-			.arg(&exec_args[0]) // Single arg passed directly for shell interpretation
-			.current_dir(path)
-			.stdin(Stdio::null())
-			.stdout(Stdio::piped())
-			.stderr(Stdio::piped())
-			.spawn()?
-	} else {
-		// Windows cmd doesn't have an equivalent to sh -c "$@"
-		// We need to join args with proper quoting
-		let command_string = exec_args
-			.iter()
-			.map(|arg| {
-				// Quote if contains spaces or special chars
-				if arg.contains(' ') || arg.contains('"') || arg.contains('&') || arg.contains('|')
-				{
-					format!("\"{}\"", arg.replace('"', "\"\""))
-				} else {
-					arg.clone()
-				}
-			})
-			.collect::<Vec<_>>()
-			.join(" ");
-		Command::new("cmd")
-			.arg("/C")
-			.arg(command_string)
-			.current_dir(path)
-			.stdin(Stdio::null())
-			.stdout(Stdio::piped())
-			.stderr(Stdio::piped())
-			.spawn()?
-	};
+/// A running command, stopped if it outlives its deadline or the run is cancelled.
+struct Running {
+	child: Child,
+	/// The command leads its own process group, so everything it started can be signalled.
+	own_group: bool,
+	/// The command will have had the terminal's Ctrl-C by the time the run is cancelled.
+	interrupted: bool,
+	deadline: Option<Instant>,
+	/// Why and when it was stopped.
+	stopped: Option<(Stop, Instant)>,
+	killed: bool,
+}
 
-	let mut stdout = String::new();
-	if let Some(mut stdout_pipe) = child_process.stdout.take() {
-		stdout_pipe.read_to_string(&mut stdout)?;
+impl Running {
+	fn check(&mut self, cancel: &AtomicBool) {
+		match self.stopped {
+			None if self
+				.deadline
+				.is_some_and(|deadline| Instant::now() >= deadline) =>
+			{
+				self.stopped = Some((Stop::TimedOut, Instant::now()));
+				self.kill();
+			}
+			None if cancel.load(Ordering::SeqCst) => {
+				self.stopped = Some((Stop::Cancelled, Instant::now()));
+				self.interrupt();
+			}
+			Some((Stop::Cancelled, since)) if !self.killed && since.elapsed() >= CANCEL_GRACE => {
+				self.kill()
+			}
+			_ => {}
+		}
 	}
 
-	let mut stderr = String::new();
-	if let Some(mut stderr_pipe) = child_process.stderr.take() {
-		stderr_pipe.read_to_string(&mut stderr)?;
+	/// Ask the command to stop, as Ctrl-C in a terminal would, unless it's had that already.
+	fn interrupt(&mut self) {
+		if self.interrupted {
+			return;
+		}
+		#[cfg(unix)]
+		self.signal(libc::SIGINT);
+		// there's no interrupting a console program from outside on windows, so it's killed
+		// once the grace period is up
 	}
 
-	let exit_code = child_process.wait()?;
-	let success = exit_code.success();
-
-	// Flatten multi-line output to single line by replacing newlines with spaces
-	let stdout_clean = stdout.trim().replace('\n', " ");
-	let stderr_clean = stderr.trim().replace('\n', " ");
+	fn kill(&mut self) {
+		self.killed = true;
+		#[cfg(unix)]
+		if self.own_group {
+			self.signal(libc::SIGKILL);
+			return;
+		}
+		#[cfg(windows)]
+		{
+			// kill everything it started too
+			let killed = Command::new("taskkill")
+				.args(["/T", "/F", "/PID", &self.child.id().to_string()])
+				.output();
+			if killed.is_ok_and(|output| output.status.success()) {
+				return;
+			}
+		}
+		// it may have exited already, which is fine
+		let _ = self.child.kill();
+	}
 
-	// Combine stdout and stderr, with stderr included when command fails
-	let output = if !success && !stderr_clean.is_empty() {
-		if stdout_clean.is_empty() {
-			stderr_clean
-		} else {
-			format!("{} {}", stdout_clean, stderr_clean)
+	/// Send a signal to the command, and to everything it started if it has its own group.
+	#[cfg(unix)]
+	fn signal(&self, signal: libc::c_int) {
+		let pid = self.child.id() as libc::pid_t;
+		let target = if self.own_group { -pid } else { pid };
+		// fails only if it has exited already
+		unsafe {
+			libc::kill(target, signal);
 		}
-	} else if !stdout_clean.is_empty() {
-		stdout_clean
-	} else {
-		String::new()
-	};
+	}
+}
 
-	if output.is_empty() {
-		Ok((None, success))
-	} else {
-		Ok((Some(output), success))
+/// The command was stopped by SIGINT, as Ctrl-C does.
+#[cfg(unix)]
+fn interrupted_by(exit_status: std::process::ExitStatus) -> bool {
+	use std::os::unix::process::ExitStatusExt;
+	exit_status.signal() == Some(libc::SIGINT)
+}
+
+fn send_lines(pipe: impl Read, stream: OutputStream, sender: mpsc::Sender<(OutputStream, String)>) {
+	let mut reader = BufReader::new(pipe);
+	let mut line = vec![];
+	while reader
+		.read_until(b'\n', &mut line)
+		.is_ok_and(|read| read > 0)
+	{
+		let text = String::from_utf8_lossy(&line);
+		let text = text.trim_end_matches(['\n', '\r']).to_string();
+		if sender.send((stream, text)).is_err() {
+			break;
+		}
+		line.clear();
 	}
 }
 
+/// Run the command capturing all its output.
+fn run_buffered(
+	path: &Path,
+	exec_args: &[String],
+	env: &RepoEnv,
+	options: &ExecOptions,
+) -> (BufferedOutput, ExecOutcome) {
+	let mut stdout = vec![];
+	let mut stderr = vec![];
+	let outcome = run_streaming(path, exec_args, env, options, &mut |stream, line| {
+		let lines = match stream {
+			OutputStream::Stdout => &mut stdout,
+			OutputStream::Stderr => &mut stderr,
+		};
+		lines.push((stream, line.to_string()));
+	});
+	stdout.append(&mut stderr);
+	(Some(stdout), outcome)
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -0,0 +1,272 @@
+use crate::exec::{format_args_for_display, ExecOutcome, OutputStream};
+use crate::observer::{Event, Observer, Operation, RepoOutcome};
+use crate::repos::Repo;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Name of the file in the log folder listing how the command went in each repo.
+pub const INDEX_FILE: &str = "index.tsv";
+
+/// Writes the output of exec to a folder: `<repo path>.log` for each repo the command ran in,
+/// with the command, how it finished and how long it took, and an index of all the repos.
+/// Every event is passed on to `inner` so output is still shown as usual.
+pub struct LogDirObserver {
+	dir: PathBuf,
+	exec_args: Vec<String>,
+	inner: Box<dyn Observer>,
+	state: Mutex<LogState>,
+}
+
+#[derive(Default)]
+struct LogState {
+	started: Option<SystemTime>,
+	stdout: String,
+	stderr: String,
+	/// Index lines for the repos finished so far.
+	index: Vec<String>,
+}
+
+impl LogDirObserver {
+	pub fn new(dir: PathBuf, exec_args: Vec<String>, inner: Box<dyn Observer>) -> Self {
+		Self {
+			dir,
+			exec_args,
+			inner,
+			state: Mutex::new(LogState::default()),
+		}
+	}
+
+	/// Write the repo's log and add it to the index. Returns a warning if the log couldn't be
+	/// written.
+	fn repo_finished(
+		&self,
+		state: &mut LogState,
+		repo: &Repo,
+		outcome: &ExecOutcome,
+	) -> Option<String> {
+		let (result, duration) = describe(outcome);
+		let mut warning = None;
+		let log_file = if ran(outcome) {
+			let log_file = log_path(&repo.path);
+			let log = format!(
+				"repo: {}\ncommand: {}\nresult: {result}\nduration: {}\n\n--- stdout ---\n{}--- stderr ---\n{}",
+				repo.path,
+				format_args_for_display(&self.exec_args),
+				format_duration(duration),
+				state.stdout,
+				state.stderr,
+			);
+			if let Err(error) = write_file(&self.dir.join(&log_file), &log) {
+				warning = Some(format!("Could not write log for {}: {error}", repo.path));
+			}
+			log_file.to_string_lossy().to_string()
+		} else {
+			String::new()
+		};
+		state.index.push(format!(
+			"{}\t{result}\t{}\t{log_file}",
+			repo.path,
+			duration
+				.map(|duration| duration.as_millis().to_string())
+				.unwrap_or_default()
+		));
+		state.stdout.clear();
+		state.stderr.clear();
+		warning
+	}
+
+	/// Write the index of all the repos. Returns a warning if it couldn't be written.
+	fn write_index(&self, state: &LogState) -> Option<String> {
+		let started = state
+			.started
+			.map(|started| humantime::format_rfc3339_seconds(started).to_string())
+			.unwrap_or_default();
+		let index = format!(
+			"# {} started {started}\nrepo\tresult\tduration_ms\tlog\n{}",
+			format_args_for_display(&self.exec_args),
+			state
+				.index
+				.iter()
+				.map(|line| format!("{line}\n"))
+				.collect::<String>()
+		);
+		write_file(&self.dir.join(INDEX_FILE), &index)
+			.err()
+			.map(|error| format!("Could not write log index: {error}"))
+	}
+}
+
+impl Observer for LogDirObserver {
+	fn event(&self, event: Event) {
+		let warning = {
+			let mut state = self.state.lock().expect("log lock poisoned");
+			match &event {
+				Event::OperationStarted {
+					operation: Operation::Exec,
+					..
+				} => {
+					*state = LogState {
+						started: Some(SystemTime::now()),
+						..Default::default()
+					};
+					None
+				}
+				Event::OutputLine { stream, line, .. } => {
+					let output = match stream {
+						OutputStream::Stdout => &mut state.stdout,
+						OutputStream::Stderr => &mut state.stderr,
+					};
+					output.push_str(line);
+					output.push('\n');
+					None
+				}
+				Event::RepoFinished {
+					repo,
+					outcome: RepoOutcome::Exec(outcome),
+				} => self.repo_finished(&mut state, repo, outcome),
+				Event::OperationFinished {
+					operation: Operation::Exec,
+				} => self.write_index(&state),
+				_ => None,
+			}
+		};
+		// passed on for the inner observer to show, as for the operation's own warnings
+		if let Some(message) = &warning {
+			self.inner.event(Event::Warning { message });
+		}
+		self.inner.event(event);
+	}
+}
+
+/// Where a repo's log goes, relative to the log folder: its path with `.log` on the end, kept
+/// inside the folder by replacing any `..` with `_` and dropping any root.
+pub fn log_path(repo_path: &str) -> PathBuf {
+	let mut path: PathBuf = Path::new(repo_path)
+		.components()
+		.filter_map(|component| match component {
+			Component::Normal(name) => Some(name.to_os_string()),
+			Component::ParentDir => Some("_".into()),
+			Component::CurDir | Component::RootDir | Component::Prefix(_) => None,
+		})
+		.collect();
+	if path.as_os_str().is_empty() {
+		path.push("_");
+	}
+	let mut file_name = path.file_name().expect("not empty").to_os_string();
+	file_name.push(".log");
+	path.set_file_name(file_name);
+	path
+}
+
+/// Whether the command was started, so there's a log to write.
+fn ran(outcome: &ExecOutcome) -> bool {
+	!matches!(
+		outcome,
+		ExecOutcome::Missing | ExecOutcome::Cancelled { duration: None }
+	)
+}
+
+/// How the command went, and how long it ran for.
+fn describe(outcome: &ExecOutcome) -> (String, Option<Duration>) {
+	match outcome {
+		ExecOutcome::Missing => ("missing".to_string(), None),
+		ExecOutcome::Failed(error) => (format!("failed to start: {}", error.message()), None),
+		ExecOutcome::Exited {
+			exit_code: Some(exit_code),
+			duration,
+		} => (format!("exit code {exit_code}"), Some(*duration)),
+		ExecOutcome::Exited {
+			exit_code: None,
+			duration,
+		} => ("terminated by a signal".to_string(), Some(*duration)),
+		ExecOutcome::TimedOut { duration } => ("timed out".to_string(), Some(*duration)),
+		ExecOutcome::Cancelled { duration } => match duration {
+			Some(duration) => ("cancelled".to_string(), Some(*duration)),
+			None => ("not started".to_string(), None),
+		},
+	}
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+	match duration {
+		Some(duration) => format!("{:.3}s", duration.as_secs_f64()),
+		None => "-".to_string(),
+	}
+}
+
+fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	#[test]
+	fn log_paths_stay_in_the_log_folder() {
+		assert_eq!(PathBuf::from("repo_a.log"), log_path("repo_a"));
+		assert_eq!(
+			PathBuf::from("group/repo_b.log"),
+			log_path("group/./repo_b/")
+		);
+		assert_eq!(PathBuf::from("_/other/repo.log"), log_path("../other/repo"));
+		assert_eq!(PathBuf::from("tmp/repo.git.log"), log_path("/tmp/repo.git"));
+		assert_eq!(PathBuf::from("_.log"), log_path("."));
+	}
+
+	/// Keeps the warnings passed on to it.
+	struct Warnings(Arc<Mutex<Vec<String>>>);
+
+	impl Observer for Warnings {
+		fn event(&self, event: Event) {
+			if let Event::Warning { message } = event {
+				self.0.lock().unwrap().push(message.to_string());
+			}
+		}
+	}
+
+	#[test]
+	fn write_failures_are_passed_on_as_warnings() {
+		let temp = tempfile::tempdir().unwrap();
+		let not_a_folder = temp.path().join("file");
+		fs::write(&not_a_folder, "").unwrap();
+		let warnings = Arc::new(Mutex::new(vec![]));
+		let observer = LogDirObserver::new(
+			not_a_folder.join("logs"),
+			vec!["true".to_string()],
+			Box::new(Warnings(warnings.clone())),
+		);
+		let repo = Repo {
+			path: "api".to_string(),
+			tags: vec![],
+			remotes: Default::default(),
+		};
+		observer.event(Event::OperationStarted {
+			operation: Operation::Exec,
+			repo_count: 1,
+			parallel: 1,
+		});
+		observer.event(Event::RepoFinished {
+			repo: &repo,
+			outcome: RepoOutcome::Exec(&ExecOutcome::Exited {
+				exit_code: Some(0),
+				duration: Duration::from_millis(5),
+			}),
+		});
+		observer.event(Event::OperationFinished {
+			operation: Operation::Exec,
+		});
+
+		let warnings = warnings.lock().unwrap();
+		assert_eq!(2, warnings.len());
+		assert!(warnings[0].starts_with("Could not write log for api: "));
+		assert!(warnings[1].starts_with("Could not write log index: "));
+	}
+}
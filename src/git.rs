@@ -1,15 +1,136 @@
 use crate::gitopolis::GitopolisError;
 use crate::gitopolis::GitopolisError::{GitError, GitRemoteError};
-use git2::Repository;
+use git2::build::CheckoutBuilder;
+use git2::{
+	Branch, BranchType, Config, Cred, CredentialType, ErrorCode, FetchPrune, ObjectType, Oid,
+	RemoteCallbacks, Repository, RepositoryState, Signature, Status, StatusOptions,
+};
+use serde_derive::Serialize;
 use std::collections::BTreeMap;
-use std::path::Path;
-use std::process::Command;
+use std::io::{BufReader, Read};
+use std::process::{Command, Stdio};
+use std::thread;
 
-pub trait Git {
+/// Git operations, shared between worker threads when working on repos in parallel.
+pub trait Git: Sync {
 	fn read_url(&self, path: String, remote_name: String) -> Result<String, GitopolisError>;
 	fn read_all_remotes(&self, path: String) -> Result<BTreeMap<String, String>, GitopolisError>;
-	fn add_remote(&self, path: &str, remote_name: &str, url: &str);
-	fn clone(&self, path: &str, url: &str) -> Result<(), GitopolisError>;
+	fn add_remote(&self, path: &str, remote_name: &str, url: &str) -> Result<(), GitopolisError>;
+	/// Clone `url` into `path`, passing progress updates to `progress` as git reports them.
+	fn clone(&self, path: &str, url: &str, progress: &dyn Fn(CloneProgress)) -> CloneOutput;
+	fn status(&self, path: &str) -> Result<RepoStatus, GitopolisError>;
+	/// The repo's main line of work: the branch origin's HEAD points to, which clone sets up,
+	/// otherwise a local main or master branch. None if there's no telling.
+	fn default_branch(&self, path: &str) -> Result<Option<String>, GitopolisError>;
+	fn fetch(&self, path: &str, options: &FetchOptions) -> Result<FetchResult, GitopolisError>;
+	/// Move the current branch up to its upstream if that's possible without a merge. Only
+	/// compares with the upstream as it is locally, so fetch first.
+	fn fast_forward(&self, path: &str) -> Result<FastForward, GitopolisError>;
+	fn branches(&self, path: &str) -> Result<Branches, GitopolisError>;
+	/// Create a branch at the current commit and switch to it, like `git switch -c`.
+	fn create_branch(&self, path: &str, name: &str) -> Result<(), GitopolisError>;
+	/// Switch to a local branch, or like `git switch` create one tracking a remote branch of the
+	/// same name if there's no local one. Refuses to overwrite local changes.
+	fn switch_branch(&self, path: &str, name: &str) -> Result<(), GitopolisError>;
+	/// Delete a local branch. Unless `force` is set this refuses if the branch has commits that
+	/// aren't in its upstream (or HEAD if it has none), like `git branch -d`.
+	fn delete_branch(&self, path: &str, name: &str, force: bool) -> Result<(), GitopolisError>;
+	/// Stash uncommitted changes to tracked files, like `git stash push -m <message>`.
+	fn stash(&self, path: &str, message: &str) -> Result<(), GitopolisError>;
+}
+
+/// The branches in a repo, e.g. local "main" and remote "origin/main".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Branches {
+	/// The checked out branch, None if HEAD is detached or has no commits yet.
+	pub current: Option<String>,
+	pub local: Vec<String>,
+	/// Remote-tracking branches, without the remotes' HEAD pointers.
+	pub remote: Vec<String>,
+}
+
+/// What `Git::fast_forward` did to the current branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FastForward {
+	/// Already has everything from its upstream (it may also have local commits).
+	UpToDate,
+	/// Moved from commit `from` to `to` (short hashes), taking in `commits` upstream commits.
+	Updated {
+		from: String,
+		to: String,
+		commits: usize,
+	},
+	/// Has commits of its own as well as being behind, so would need a merge or rebase.
+	Diverged { ahead: usize, behind: usize },
+}
+
+/// Which remotes to fetch from and whether to prune, for `Git::fetch`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FetchOptions {
+	/// Delete remote-tracking branches that no longer exist on the remote.
+	pub prune: bool,
+	/// Fetch from every remote rather than just the current branch's upstream remote (or origin,
+	/// or the first remote if there's no origin).
+	pub all_remotes: bool,
+}
+
+/// What fetching changed in one repo.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FetchResult {
+	/// Remotes fetched from, empty if the repo has none.
+	pub remotes: Vec<String>,
+	pub updated_refs: Vec<UpdatedRef>,
+}
+
+/// A ref changed by a fetch, e.g. "refs/remotes/origin/main", with short commit hashes.
+/// `old` is None for a new ref and `new` is None for a pruned one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpdatedRef {
+	pub name: String,
+	pub old: Option<String>,
+	pub new: Option<String>,
+}
+
+/// A clone progress update from git, e.g. "Receiving objects:  45% (450/1000)".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloneProgress {
+	pub phase: String,
+	pub percent: u32,
+	pub current: u64,
+	pub total: u64,
+}
+
+/// What git printed while cloning (without progress updates), held back so it can be printed
+/// in one piece when cloning in parallel.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CloneOutput {
+	pub stdout: String,
+	pub stderr: String,
+	pub success: bool,
+}
+
+/// Snapshot of the working state of a single repo, as shown by `gitopolis status`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepoStatus {
+	pub head: Head,
+	/// Commits (ahead, behind) the upstream branch, None if the branch has no upstream.
+	pub ahead_behind: Option<(usize, usize)>,
+	pub staged: usize,
+	pub unstaged: usize,
+	pub untracked: usize,
+	pub stashes: usize,
+	/// In-progress operation such as "merge" or "rebase", None if there isn't one.
+	pub in_progress: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "name", rename_all = "lowercase")]
+pub enum Head {
+	Branch(String),
+	/// Branch that has no commits yet.
+	Unborn(String),
+	/// Short commit hash of a detached HEAD.
+	Detached(String),
 }
 
 pub struct GitImpl {}
@@ -48,7 +169,7 @@ impl Git for GitImpl {
 		Ok(remotes)
 	}
 
-	fn add_remote(&self, path: &str, remote_name: &str, url: &str) {
+	fn add_remote(&self, path: &str, remote_name: &str, url: &str) -> Result<(), GitopolisError> {
 		let output = Command::new("git")
 			.current_dir(path)
 			.args(
@@ -63,33 +184,648 @@ impl Git for GitImpl {
 			.output()
 			.expect("Error running git remote add");
 		if !output.status.success() {
-			let stderr =
-				String::from_utf8(output.stderr).expect("Error converting stderr to string");
-			eprintln!("Warning: Failed to add remote {remote_name}: {stderr}");
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			return Err(GitRemoteError {
+				message: format!("Failed to add remote {remote_name}: {}", stderr.trim()),
+				remote: remote_name.to_string(),
+			});
 		}
+		Ok(())
 	}
 
-	fn clone(&self, path: &str, url: &str) -> Result<(), GitopolisError> {
-		if Path::new(path).exists() {
-			println!("🏢 {path}> Already exists, skipped.");
-			return Ok(());
+	fn clone(&self, path: &str, url: &str, progress: &dyn Fn(CloneProgress)) -> CloneOutput {
+		let failed = |error: std::io::Error| CloneOutput {
+			stdout: String::new(),
+			stderr: format!("Error running git clone: {error}"),
+			success: false,
+		};
+		let mut child_process = match Command::new("git")
+			.args(["clone", "--progress", url, path])
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+		{
+			Ok(child_process) => child_process,
+			Err(error) => return failed(error),
+		};
+		let mut stdout_pipe = child_process
+			.stdout
+			.take()
+			.expect("Failed to capture stdout");
+		let stdout_thread = thread::spawn(move || {
+			let mut stdout = String::new();
+			stdout_pipe
+				.read_to_string(&mut stdout)
+				.expect("Error converting stdout to string");
+			stdout
+		});
+		let stderr = read_clone_stderr(
+			child_process
+				.stderr
+				.take()
+				.expect("Failed to capture stderr"),
+			progress,
+		);
+		let exit_status = match child_process.wait() {
+			Ok(exit_status) => exit_status,
+			Err(error) => return failed(error),
+		};
+		CloneOutput {
+			stdout: stdout_thread.join().expect("Failed to read stdout"),
+			stderr,
+			success: exit_status.success(),
 		}
-		println!("🏢 {path}> Cloning {url} ...");
-		let output = Command::new("git")
-			.args(["clone".to_string(), url.to_string(), path.to_string()].to_vec())
-			.output()
-			.expect("Error running git clone");
-		let stdout = String::from_utf8(output.stdout).expect("Error converting stdout to string");
-		let stderr = String::from_utf8(output.stderr).expect("Error converting stderr to string");
-		println!("{stdout}");
-		println!("{stderr}");
+	}
 
-		if !output.status.success() {
+	fn status(&self, path: &str) -> Result<RepoStatus, GitopolisError> {
+		let mut repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+
+		let (head, ahead_behind) = read_head(&repository)?;
+
+		let mut options = StatusOptions::new();
+		options.include_untracked(true).include_ignored(false);
+		let statuses = repository
+			.statuses(Some(&mut options))
+			.map_err(|error| GitError {
+				message: format!("Failed to read status. {}", error.message()),
+			})?;
+		let staged_flags = Status::INDEX_NEW
+			| Status::INDEX_MODIFIED
+			| Status::INDEX_DELETED
+			| Status::INDEX_RENAMED
+			| Status::INDEX_TYPECHANGE;
+		let unstaged_flags = Status::WT_MODIFIED
+			| Status::WT_DELETED
+			| Status::WT_RENAMED
+			| Status::WT_TYPECHANGE
+			| Status::CONFLICTED;
+		let count = |flags: Status| {
+			statuses
+				.iter()
+				.filter(|entry| entry.status().intersects(flags))
+				.count()
+		};
+		let staged = count(staged_flags);
+		let unstaged = count(unstaged_flags);
+		let untracked = count(Status::WT_NEW);
+		drop(statuses); // releases borrow of repository so stashes can be read
+
+		let mut stashes = 0;
+		repository
+			.stash_foreach(|_, _, _| {
+				stashes += 1;
+				true
+			})
+			.map_err(|error| GitError {
+				message: format!("Failed to read stashes. {}", error.message()),
+			})?;
+
+		Ok(RepoStatus {
+			head,
+			ahead_behind,
+			staged,
+			unstaged,
+			untracked,
+			stashes,
+			in_progress: in_progress_operation(repository.state()),
+		})
+	}
+
+	fn default_branch(&self, path: &str) -> Result<Option<String>, GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let origin_head = repository
+			.find_reference("refs/remotes/origin/HEAD")
+			.ok()
+			.and_then(|reference| {
+				reference
+					.symbolic_target()
+					.and_then(|target| target.strip_prefix("refs/remotes/origin/"))
+					.map(str::to_string)
+			});
+		if origin_head.is_some() {
+			return Ok(origin_head);
+		}
+		Ok(["main", "master"]
+			.into_iter()
+			.find(|name| repository.find_branch(name, BranchType::Local).is_ok())
+			.map(str::to_string))
+	}
+
+	fn fetch(&self, path: &str, options: &FetchOptions) -> Result<FetchResult, GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let config = repository.config().map_err(|error| GitError {
+			message: format!("Failed to read git config. {}", error.message()),
+		})?;
+
+		let mut result = FetchResult::default();
+		for remote_name in fetch_remote_names(&repository, options.all_remotes)? {
+			let fetch_error = |error: git2::Error| GitRemoteError {
+				message: format!("Failed to fetch {remote_name}. {}", error.message()),
+				remote: remote_name.clone(),
+			};
+			let mut remote = repository.find_remote(&remote_name).map_err(fetch_error)?;
+			let mut updated_refs = vec![];
+			{
+				let mut credentials = Credentials::default();
+				let mut callbacks = RemoteCallbacks::new();
+				callbacks.credentials(|url, username, allowed| {
+					credentials.next(&config, url, username, allowed)
+				});
+				callbacks.update_tips(|name, old, new| {
+					updated_refs.push(UpdatedRef {
+						name: name.to_string(),
+						old: short_oid(old),
+						new: short_oid(new),
+					});
+					true
+				});
+				let mut fetch_options = git2::FetchOptions::new();
+				fetch_options.remote_callbacks(callbacks);
+				if options.prune {
+					fetch_options.prune(FetchPrune::On);
+				}
+				// no refspecs means the remote's configured ones, as with `git fetch <remote>`
+				remote
+					.fetch(&[] as &[&str], Some(&mut fetch_options), None)
+					.map_err(fetch_error)?;
+			}
+			result.remotes.push(remote_name);
+			result.updated_refs.append(&mut updated_refs);
+		}
+		Ok(result)
+	}
+
+	fn fast_forward(&self, path: &str) -> Result<FastForward, GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let git_error = |action: &str, error: git2::Error| GitError {
+			message: format!("Failed to {action}. {}", error.message()),
+		};
+		let head_ref = repository
+			.head()
+			.map_err(|error| git_error("read HEAD", error))?;
+		if !head_ref.is_branch() {
 			return Err(GitError {
-				message: format!("Failed to clone {} to {}", url, path),
+				message: "HEAD is not on a branch".to_string(),
 			});
 		}
+		let local = head_ref
+			.peel_to_commit()
+			.map_err(|error| git_error("read HEAD commit", error))?;
+		let upstream = Branch::wrap(head_ref)
+			.upstream()
+			.map_err(|error| git_error("find upstream branch", error))?
+			.get()
+			.peel_to_commit()
+			.map_err(|error| git_error("read upstream commit", error))?;
 
-		Ok(())
+		let (ahead, behind) = repository
+			.graph_ahead_behind(local.id(), upstream.id())
+			.map_err(|error| git_error("compare with upstream", error))?;
+		if behind == 0 {
+			return Ok(FastForward::UpToDate);
+		}
+		if ahead > 0 {
+			return Ok(FastForward::Diverged { ahead, behind });
+		}
+
+		// Safe checkout refuses to overwrite local changes, e.g. untracked files in the way
+		repository
+			.checkout_tree(upstream.as_object(), Some(CheckoutBuilder::new().safe()))
+			.map_err(|error| git_error("update working tree", error))?;
+		let from = short_oid(local.id()).unwrap_or_default();
+		let to = short_oid(upstream.id()).unwrap_or_default();
+		repository
+			.head()
+			.and_then(|mut head_ref| {
+				head_ref.set_target(
+					upstream.id(),
+					&format!("pull: Fast-forward {from}..{to} (gitopolis)"),
+				)
+			})
+			.map_err(|error| git_error("move branch", error))?;
+		Ok(FastForward::Updated {
+			from,
+			to,
+			commits: behind,
+		})
+	}
+
+	fn branches(&self, path: &str) -> Result<Branches, GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let list_error = |error: git2::Error| GitError {
+			message: format!("Failed to list branches. {}", error.message()),
+		};
+		let mut branches = Branches {
+			current: repository
+				.head()
+				.ok()
+				.filter(|head| head.is_branch())
+				.and_then(|head| head.shorthand().map(str::to_string)),
+			..Default::default()
+		};
+		for entry in repository.branches(None).map_err(list_error)? {
+			let (branch, branch_type) = entry.map_err(list_error)?;
+			let Some(name) = branch.name().map_err(list_error)?.map(str::to_string) else {
+				continue;
+			};
+			match branch_type {
+				BranchType::Local => branches.local.push(name),
+				BranchType::Remote if !name.ends_with("/HEAD") => branches.remote.push(name),
+				BranchType::Remote => {}
+			}
+		}
+		Ok(branches)
+	}
+
+	fn create_branch(&self, path: &str, name: &str) -> Result<(), GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let error = |error: git2::Error| GitError {
+			message: format!("Failed to create branch {name}. {}", error.message()),
+		};
+		let head = repository
+			.head()
+			.and_then(|head| head.peel_to_commit())
+			.map_err(error)?;
+		let branch = repository.branch(name, &head, false).map_err(error)?;
+		let refname = branch.get().name().unwrap_or_default().to_string();
+		repository.set_head(&refname).map_err(error)
+	}
+
+	fn switch_branch(&self, path: &str, name: &str) -> Result<(), GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let error = |error: git2::Error| GitError {
+			message: format!("Failed to switch to {name}. {}", error.message()),
+		};
+		let branch = match repository.find_branch(name, BranchType::Local) {
+			Ok(branch) => branch,
+			Err(not_found) if not_found.code() == ErrorCode::NotFound => {
+				let upstream = find_remote_branch(&repository, name)?;
+				let commit = upstream.get().peel_to_commit().map_err(error)?;
+				let upstream_name = upstream.name().map_err(error)?.unwrap_or_default();
+				let mut branch = repository.branch(name, &commit, false).map_err(error)?;
+				branch.set_upstream(Some(upstream_name)).map_err(error)?;
+				branch
+			}
+			Err(other) => return Err(error(other)),
+		};
+		let target = branch.get().peel(ObjectType::Commit).map_err(error)?;
+		// Safe checkout refuses to overwrite local changes rather than losing them
+		repository
+			.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))
+			.map_err(error)?;
+		let refname = branch.get().name().unwrap_or_default().to_string();
+		repository.set_head(&refname).map_err(error)
+	}
+
+	fn delete_branch(&self, path: &str, name: &str, force: bool) -> Result<(), GitopolisError> {
+		let repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let error = |error: git2::Error| GitError {
+			message: format!("Failed to delete branch {name}. {}", error.message()),
+		};
+		let mut branch = repository
+			.find_branch(name, BranchType::Local)
+			.map_err(error)?;
+		if branch.is_head() {
+			return Err(GitError {
+				message: format!("Can't delete {name} as it's the current branch"),
+			});
+		}
+		if !force {
+			let tip = branch.get().peel_to_commit().map_err(error)?.id();
+			let merged_into = branch
+				.upstream()
+				.ok()
+				.and_then(|upstream| upstream.get().target())
+				.or_else(|| repository.head().ok().and_then(|head| head.target()));
+			let merged = merged_into.is_some_and(|target| {
+				target == tip || repository.graph_descendant_of(target, tip).unwrap_or(false)
+			});
+			if !merged {
+				return Err(GitError {
+					message: format!(
+						"Branch {name} is not fully merged, use --force to delete it anyway"
+					),
+				});
+			}
+		}
+		branch.delete().map_err(error)
+	}
+
+	fn stash(&self, path: &str, message: &str) -> Result<(), GitopolisError> {
+		let mut repository = Repository::open(path).map_err(|error| GitError {
+			message: format!("Couldn't open git repo. {}", error.message()),
+		})?;
+		let error = |error: git2::Error| GitError {
+			message: format!("Failed to stash changes. {}", error.message()),
+		};
+		// fall back to a placeholder author so stashing works without user.name configured
+		let signature = repository
+			.signature()
+			.or_else(|_| Signature::now("gitopolis", "gitopolis@localhost"))
+			.map_err(error)?;
+		repository
+			.stash_save(&signature, message, None)
+			.map(|_| ())
+			.map_err(error)
+	}
+}
+
+fn read_head(repository: &Repository) -> Result<(Head, Option<(usize, usize)>), GitopolisError> {
+	let head_ref = match repository.head() {
+		Ok(head_ref) => head_ref,
+		Err(error) if error.code() == ErrorCode::UnbornBranch => {
+			// HEAD points at a branch with no commits yet, so read the branch name from HEAD itself
+			let branch = repository
+				.find_reference("HEAD")
+				.ok()
+				.and_then(|head| head.symbolic_target().map(|s| s.to_string()))
+				.unwrap_or_default();
+			let branch = branch.trim_start_matches("refs/heads/").to_string();
+			return Ok((Head::Unborn(branch), None));
+		}
+		Err(error) => {
+			return Err(GitError {
+				message: format!("Failed to read HEAD. {}", error.message()),
+			})
+		}
+	};
+
+	if !head_ref.is_branch() {
+		let commit = head_ref.peel_to_commit().map_err(|error| GitError {
+			message: format!("Failed to read HEAD commit. {}", error.message()),
+		})?;
+		let short_id = commit.id().to_string()[..7].to_string();
+		return Ok((Head::Detached(short_id), None));
+	}
+
+	let branch_name = head_ref.shorthand().unwrap_or("").to_string();
+	let local_oid = head_ref.target();
+	let upstream_oid = Branch::wrap(head_ref)
+		.upstream()
+		.ok()
+		.and_then(|upstream| upstream.get().target());
+	let ahead_behind = match (local_oid, upstream_oid) {
+		(Some(local), Some(upstream)) => Some(
+			repository
+				.graph_ahead_behind(local, upstream)
+				.map_err(|error| GitError {
+					message: format!("Failed to compare with upstream. {}", error.message()),
+				})?,
+		),
+		_ => None,
+	};
+	Ok((Head::Branch(branch_name), ahead_behind))
+}
+
+/// Remotes to fetch from: all of them, or the one the current branch tracks like `git fetch`,
+/// falling back to origin if there is one, otherwise the first.
+fn fetch_remote_names(
+	repository: &Repository,
+	all_remotes: bool,
+) -> Result<Vec<String>, GitopolisError> {
+	let remotes = repository.remotes().map_err(|error| GitError {
+		message: format!("Failed to read remotes. {}", error.message()),
+	})?;
+	let names: Vec<String> = remotes.iter().flatten().map(str::to_string).collect();
+	if all_remotes {
+		return Ok(names);
+	}
+	let upstream_remote = repository
+		.head()
+		.ok()
+		.and_then(|head| head.name().map(str::to_string))
+		.and_then(|branch| repository.branch_upstream_remote(&branch).ok())
+		.and_then(|remote| remote.as_str().map(str::to_string));
+	if let Some(remote) = upstream_remote.filter(|remote| names.contains(remote)) {
+		return Ok(vec![remote]);
+	}
+	if names.iter().any(|name| name == "origin") {
+		return Ok(vec!["origin".to_string()]);
+	}
+	Ok(names.into_iter().take(1).collect())
+}
+
+/// The remote-tracking branch to create a local branch `name` from, preferring origin's if more
+/// than one remote has it.
+fn find_remote_branch<'repo>(
+	repository: &'repo Repository,
+	name: &str,
+) -> Result<Branch<'repo>, GitopolisError> {
+	let not_found = || GitError {
+		message: format!("No local or remote branch named {name}"),
+	};
+	let mut candidates: Vec<Branch> = repository
+		.branches(Some(BranchType::Remote))
+		.map_err(|error| GitError {
+			message: format!("Failed to list branches. {}", error.message()),
+		})?
+		.flatten()
+		.map(|(branch, _)| branch)
+		.filter(|branch| {
+			branch
+				.name()
+				.ok()
+				.flatten()
+				.and_then(|full_name| full_name.split_once('/'))
+				.is_some_and(|(_, branch_name)| branch_name == name)
+		})
+		.collect();
+	if let Some(origin) = candidates
+		.iter()
+		.position(|branch| branch.name().ok().flatten() == Some(&format!("origin/{name}")))
+	{
+		return Ok(candidates.swap_remove(origin));
+	}
+	match candidates.len() {
+		0 => Err(not_found()),
+		1 => Ok(candidates.remove(0)),
+		_ => Err(GitError {
+			message: format!("Branch {name} exists on more than one remote, switch to it with git"),
+		}),
+	}
+}
+
+fn short_oid(oid: Oid) -> Option<String> {
+	if oid.is_zero() {
+		return None;
+	}
+	Some(oid.to_string()[..7].to_string())
+}
+
+/// Supplies credentials when a remote asks for them, the same way the git command line does:
+/// ssh-agent for ssh remotes and the configured credential helpers for https. Each kind is only
+/// offered once, as libgit2 keeps asking for as long as it's given credentials that get rejected.
+#[derive(Default)]
+struct Credentials {
+	tried_username: bool,
+	tried_ssh_agent: bool,
+	tried_helper: bool,
+	tried_default: bool,
+}
+
+impl Credentials {
+	fn next(
+		&mut self,
+		config: &Config,
+		url: &str,
+		username: Option<&str>,
+		allowed: CredentialType,
+	) -> Result<Cred, git2::Error> {
+		let ssh_username = username.unwrap_or("git");
+		if allowed.contains(CredentialType::USERNAME) && !self.tried_username {
+			self.tried_username = true;
+			return Cred::username(ssh_username);
+		}
+		if allowed.contains(CredentialType::SSH_KEY) && !self.tried_ssh_agent {
+			self.tried_ssh_agent = true;
+			return Cred::ssh_key_from_agent(ssh_username);
+		}
+		if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !self.tried_helper {
+			self.tried_helper = true;
+			return Cred::credential_helper(config, url, username);
+		}
+		if allowed.contains(CredentialType::DEFAULT) && !self.tried_default {
+			self.tried_default = true;
+			return Cred::default();
+		}
+		Err(git2::Error::from_str(
+			"Authentication failed, check ssh-agent has a key loaded or a git credential helper is set up",
+		))
+	}
+}
+
+fn in_progress_operation(state: RepositoryState) -> Option<String> {
+	let operation = match state {
+		RepositoryState::Clean => return None,
+		RepositoryState::Merge => "merge",
+		RepositoryState::Revert | RepositoryState::RevertSequence => "revert",
+		RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "cherry-pick",
+		RepositoryState::Bisect => "bisect",
+		RepositoryState::Rebase
+		| RepositoryState::RebaseInteractive
+		| RepositoryState::RebaseMerge => "rebase",
+		RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "am",
+	};
+	Some(operation.to_string())
+}
+
+/// Read git's stderr, passing progress updates to `progress` and returning everything else.
+/// Progress lines are redrawn in place with `\r` so it's split on that as well as newlines.
+fn read_clone_stderr(stderr: impl Read, progress: &dyn Fn(CloneProgress)) -> String {
+	let mut kept = String::new();
+	let mut line = vec![];
+	let mut handle_line = |line: &[u8]| {
+		let text = String::from_utf8_lossy(line);
+		if text.is_empty() {
+			return;
+		}
+		if is_progress_line(&text) {
+			if let Some(update) = parse_clone_progress(&text) {
+				progress(update);
+			}
+		} else {
+			kept.push_str(text.trim_end()); // git pads sideband lines to cover earlier progress
+			kept.push('\n');
+		}
+	};
+	for byte in BufReader::new(stderr).bytes().map_while(Result::ok) {
+		if byte == b'\r' || byte == b'\n' {
+			handle_line(&line);
+			line.clear();
+		} else {
+			line.push(byte);
+		}
+	}
+	handle_line(&line);
+	kept
+}
+
+/// Splits a progress line such as "remote: Counting objects: 100% (5/5), done." into its phase
+/// and the counts that follow.
+fn split_progress_line(line: &str) -> Option<(&str, &str)> {
+	let line = line.strip_prefix("remote: ").unwrap_or(line);
+	let (phase, rest) = line.split_once(": ")?;
+	let rest = rest.trim_start();
+	rest.starts_with(|c: char| c.is_ascii_digit())
+		.then_some((phase, rest))
+}
+
+fn is_progress_line(line: &str) -> bool {
+	split_progress_line(line).is_some()
+}
+
+/// Parse a progress line with a percentage, e.g. "Receiving objects:  45% (450/1000), 1.00 MiB".
+/// Returns None for lines with only a running count such as "Enumerating objects: 5, done."
+fn parse_clone_progress(line: &str) -> Option<CloneProgress> {
+	let (phase, rest) = split_progress_line(line)?;
+	let (percent, rest) = rest.split_once('%')?;
+	let (counts, _) = rest.trim_start().strip_prefix('(')?.split_once(')')?;
+	let (current, total) = counts.split_once('/')?;
+	Some(CloneProgress {
+		phase: phase.to_string(),
+		percent: percent.parse().ok()?,
+		current: current.parse().ok()?,
+		total: total.parse().ok()?,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_clone_progress_lines() {
+		assert_eq!(
+			parse_clone_progress("Receiving objects:  45% (450/1000), 1.20 MiB | 2.00 MiB/s"),
+			Some(CloneProgress {
+				phase: "Receiving objects".to_string(),
+				percent: 45,
+				current: 450,
+				total: 1000,
+			})
+		);
+		assert_eq!(
+			parse_clone_progress("remote: Counting objects: 100% (5/5), done."),
+			Some(CloneProgress {
+				phase: "Counting objects".to_string(),
+				percent: 100,
+				current: 5,
+				total: 5,
+			})
+		);
+		assert_eq!(
+			parse_clone_progress("remote: Enumerating objects: 5, done."),
+			None
+		);
+	}
+
+	#[test]
+	fn read_clone_stderr_keeps_messages_and_reports_progress() {
+		let stderr = "Cloning into 'repo'...\nremote: Enumerating objects: 5, done.\nReceiving objects:  50% (1/2)\rReceiving objects: 100% (2/2), done.\nwarning: remote HEAD refers to nonexistent ref\n";
+		let updates = std::cell::RefCell::new(vec![]);
+		let kept = read_clone_stderr(stderr.as_bytes(), &|update| {
+			updates.borrow_mut().push(update.percent)
+		});
+		assert_eq!(
+			kept,
+			"Cloning into 'repo'...\nwarning: remote HEAD refers to nonexistent ref\n"
+		);
+		assert_eq!(*updates.borrow(), vec![50, 100]);
 	}
 }
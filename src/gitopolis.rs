@@ -1,15 +1,208 @@
-use crate::git::Git;
+use crate::aliases::{Alias, AliasEntry};
+use crate::doctor::{remote_differences, Problem};
+use crate::exec;
+use crate::exec::{ExecOptions, ExecReport};
+use crate::git::{
+	Branches, CloneOutput, CloneProgress, FastForward, FetchOptions, FetchResult, Git, Head,
+	RepoStatus,
+};
 use crate::gitopolis::GitopolisError::*;
-use crate::repos::{Repo, RepoInfo, Repos};
+use crate::layers::{Layer, Layers};
+use crate::observer::{Event, NoObserver, Observer, Operation, RepoOutcome};
+use crate::parallel::run_in_order;
+use crate::repos::{Remote, Repo, RepoInfo, Repos};
+use crate::state_file;
+use crate::state_filter::StateFilter;
 use crate::storage::Storage;
 use crate::tag_filter::TagFilter;
 use log::info;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::io;
+use std::path::{Path, PathBuf};
 
 pub struct Gitopolis {
 	storage: Box<dyn Storage>,
 	git: Box<dyn Git>,
+	observer: Box<dyn Observer>,
+	/// Folder that repo paths in the state are relative to. Empty means the current directory.
+	root: PathBuf,
+	/// Where new repos and tags are saved, if not the default for each.
+	write_layer: Option<Layer>,
+}
+
+/// A repo from the config along with its git status, or the reason it couldn't be read.
+pub type RepoStatusEntry = (Repo, Result<RepoStatus, GitopolisError>);
+
+/// A repo from the config along with the names of the remotes synced, or why it couldn't be.
+pub type RepoSyncEntry = (Repo, Result<Vec<String>, GitopolisError>);
+
+/// What happened when cloning one repo.
+#[derive(Debug)]
+pub enum CloneOutcome {
+	/// The repo has no remotes to clone from, so it was left alone.
+	NoRemotes,
+	/// The repo folder is already there, so it was left alone.
+	AlreadyExists,
+	/// Cloned from `url`. `remote_errors` holds any of the repo's other remotes that couldn't be
+	/// added afterwards.
+	Cloned {
+		url: String,
+		output: CloneOutput,
+		remote_errors: Vec<GitopolisError>,
+	},
+	/// git clone from `url` failed, see `output` for why.
+	Failed { url: String, output: CloneOutput },
+}
+
+/// Outcome of cloning each repo, in the same order as the repo list.
+#[derive(Debug)]
+pub struct CloneReport {
+	pub repos: Vec<(Repo, CloneOutcome)>,
+}
+
+impl CloneReport {
+	pub fn summary(&self) -> CloneSummary {
+		let mut summary = CloneSummary::default();
+		for (_, outcome) in &self.repos {
+			match outcome {
+				CloneOutcome::NoRemotes => summary.no_remotes += 1,
+				CloneOutcome::AlreadyExists => summary.skipped += 1,
+				CloneOutcome::Cloned { .. } => summary.cloned += 1,
+				CloneOutcome::Failed { .. } => summary.failed += 1,
+			}
+		}
+		summary
+	}
+}
+
+/// A repo from the config along with what fetching changed, or why it couldn't be fetched.
+pub type RepoFetchEntry = (Repo, Result<FetchResult, GitopolisError>);
+
+/// Outcome of fetching each repo, in the same order as the repo list.
+#[derive(Debug)]
+pub struct FetchReport {
+	pub repos: Vec<RepoFetchEntry>,
+}
+
+impl FetchReport {
+	pub fn summary(&self) -> FetchSummary {
+		let mut summary = FetchSummary::default();
+		for (_, result) in &self.repos {
+			match result {
+				Ok(fetched) => {
+					summary.fetched += 1;
+					summary.updated_refs += fetched.updated_refs.len();
+				}
+				Err(_) => summary.failed += 1,
+			}
+		}
+		summary
+	}
+}
+
+/// How many repos were fetched or failed, and how many refs were updated across all of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FetchSummary {
+	pub fetched: usize,
+	pub failed: usize,
+	pub updated_refs: usize,
+}
+
+/// A repo from the config along with what pulling did, or why it failed.
+pub type RepoPullEntry = (Repo, Result<PullOutcome, GitopolisError>);
+
+/// What `pull` did to one repo. Anything other than `Updated` or `UpToDate` means the repo was
+/// left alone because fast-forwarding wasn't safe or possible.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum PullOutcome {
+	/// Fast-forwarded from commit `from` to `to` (short hashes), taking in `commits` commits.
+	Updated {
+		branch: String,
+		from: String,
+		to: String,
+		commits: usize,
+	},
+	UpToDate {
+		branch: String,
+	},
+	/// Has commits of its own as well as upstream ones, so needs a merge or rebase.
+	Diverged {
+		branch: String,
+		ahead: usize,
+		behind: usize,
+	},
+	/// Has uncommitted changes, or an operation such as a rebase in progress.
+	Dirty {
+		branch: String,
+	},
+	Detached {
+		commit: String,
+	},
+	/// The branch doesn't track an upstream branch (or has no commits yet).
+	NoUpstream {
+		branch: String,
+	},
+}
+
+/// A repo from the config along with its branches, or why they couldn't be read.
+pub type RepoBranchesEntry = (Repo, Result<Branches, GitopolisError>);
+
+/// A repo from the config along with what a branch operation did, or why it failed.
+pub type RepoBranchEntry = (Repo, Result<BranchOutcome, GitopolisError>);
+
+/// What creating, switching to or deleting a branch did in one repo. `Exists`, `Missing`,
+/// `AlreadyOn`, `Current` and `Dirty` mean the repo was left alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BranchOutcome {
+	Created,
+	/// Switched, having first stashed uncommitted changes if `stashed`.
+	Switched {
+		stashed: bool,
+	},
+	Deleted,
+	/// Not created as a branch with that name already exists.
+	Exists,
+	/// There's no branch with that name to switch to or delete.
+	Missing,
+	/// Not switched as it's already the current branch.
+	AlreadyOn,
+	/// Not deleted as it's the current branch.
+	Current,
+	/// Not switched as there are uncommitted changes and stashing wasn't asked for.
+	Dirty,
+}
+
+impl BranchOutcome {
+	/// True if the repo was changed rather than left alone.
+	pub fn changed(&self) -> bool {
+		matches!(
+			self,
+			BranchOutcome::Created | BranchOutcome::Switched { .. } | BranchOutcome::Deleted
+		)
+	}
+}
+
+/// How many repos were cloned, already existed or had no remotes to clone from (so were left
+/// alone), or failed to clone.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CloneSummary {
+	pub cloned: usize,
+	pub skipped: usize,
+	pub no_remotes: usize,
+	pub failed: usize,
+}
+
+/// What happened to one repo read from a hosting provider when merging it into the state.
+#[derive(Debug, PartialEq)]
+pub enum ImportOutcome {
+	/// Not in the state before, added with its remotes and tags.
+	Added,
+	/// Already in the state, these tags were added to it. Its remotes were left alone.
+	Tagged { tags: Vec<String> },
+	/// Already in the state with all its tags.
+	Unchanged,
 }
 
 #[derive(Debug)]
@@ -18,6 +211,8 @@ pub enum GitopolisError {
 	StateError { message: String },
 	GitRemoteError { message: String, remote: String },
 	IoError { inner: io::Error },
+	FilterError { message: String },
+	ApiError { message: String },
 }
 
 impl GitopolisError {
@@ -27,50 +222,170 @@ impl GitopolisError {
 			StateError { message } => message.to_string(),
 			GitRemoteError { message, remote: _ } => message.to_string(),
 			IoError { inner } => inner.to_string(),
+			FilterError { message } => message.to_string(),
+			ApiError { message } => message.to_string(),
 		}
 	}
 }
 
 impl Gitopolis {
 	pub fn new(storage: Box<dyn Storage>, git: Box<dyn Git>) -> Self {
-		Self { storage, git }
+		Self {
+			storage,
+			git,
+			observer: Box::new(NoObserver),
+			root: PathBuf::new(),
+			write_layer: None,
+		}
+	}
+
+	/// Send live events about work across repos (clone, sync and exec) to `observer`.
+	pub fn with_observer(mut self, observer: Box<dyn Observer>) -> Self {
+		self.observer = observer;
+		self
+	}
+
+	/// Resolve repo paths relative to `root` (normally the folder containing the state file)
+	/// instead of the current directory.
+	pub fn with_root(mut self, root: PathBuf) -> Self {
+		self.root = root;
+		self
+	}
+
+	/// Save new repos and tags to `layer`. By default new repos go in the shared state file and
+	/// tags go in the personal overlay if there is one, otherwise the shared file.
+	pub fn with_write_layer(mut self, layer: Layer) -> Self {
+		self.write_layer = Some(layer);
+		self
 	}
 
 	pub fn add(&mut self, repo_folder: String) -> Result<(), GitopolisError> {
-		let mut repos = self.load()?;
-		let normalized_folder: String = normalize_folder(repo_folder);
-		if repos.repo_index(normalized_folder.to_owned()).is_some() {
-			info!("{normalized_folder} already added, ignoring.");
-			return Ok(());
+		self.add_repos(vec![repo_folder], &[])
+	}
+
+	/// Add several repos, reading each one's remotes from git, and apply `tags` to all of them.
+	/// Saves once at the end. Repos that are already known are left alone apart from tagging.
+	pub fn add_repos(
+		&mut self,
+		repo_folders: Vec<String>,
+		tags: &[String],
+	) -> Result<(), GitopolisError> {
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
+		let known = layers.merged();
+		let repo_layer = self.write_layer.clone().unwrap_or(Layer::Shared);
+		let normalized_folders = normalize_folders(&repo_folders);
+		let mut changed = !tags.is_empty();
+		for normalized_folder in &normalized_folders {
+			if known.repo_index(normalized_folder.to_owned()).is_some() {
+				info!("{normalized_folder} already added, ignoring.");
+				continue;
+			}
+			let remotes = self.git.read_all_remotes(self.resolve(normalized_folder))?;
+			layers
+				.get_mut(&repo_layer)
+				.add(normalized_folder.to_owned(), remotes);
+			changed = true;
+		}
+		let tag_layer = self.tag_layer(&layers);
+		for tag in tags {
+			layers.add_tag(&tag_layer, tag, normalized_folders.clone())?;
+		}
+		if changed {
+			self.save_layers(layers)?;
 		}
-		let remotes = self.git.read_all_remotes(normalized_folder.to_owned())?;
-		repos.add(normalized_folder, remotes);
-		self.save(repos)?;
 		Ok(())
 	}
 
+	/// The aliases from all the layers of the state. An alias in a later layer replaces one of
+	/// the same name in an earlier one, so the personal overlay can override shared aliases.
+	pub fn aliases(&self) -> Result<BTreeMap<String, Alias>, GitopolisError> {
+		let mut aliases = BTreeMap::new();
+		for (_, state) in self.read_state_files()? {
+			aliases.extend(
+				state
+					.aliases
+					.into_iter()
+					.map(|(name, entry)| (name, Alias::from(entry))),
+			);
+		}
+		Ok(aliases)
+	}
+
+	/// Merge repos read from a hosting provider into the state. New repos are added with their
+	/// remotes and tags, repos already there (by path) only get any tags they're missing.
+	/// Saves once at the end.
+	pub fn import(
+		&mut self,
+		repos: Vec<Repo>,
+	) -> Result<Vec<(Repo, ImportOutcome)>, GitopolisError> {
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
+		let known = layers.merged();
+		let repo_layer = self.write_layer.clone().unwrap_or(Layer::Shared);
+		let mut outcomes = vec![];
+		for mut repo in repos {
+			repo.path = normalize_folder(repo.path);
+			let outcome = match known.as_vec().iter().find(|r| r.path == repo.path) {
+				None => {
+					let entry = layers.get_mut(&repo_layer).entry(&repo.path);
+					entry.tags = repo.tags.clone();
+					entry.remotes = repo.remotes.clone();
+					ImportOutcome::Added
+				}
+				Some(existing) => {
+					let tags: Vec<String> = repo
+						.tags
+						.iter()
+						.filter(|tag| !existing.tags.contains(tag))
+						.cloned()
+						.collect();
+					for tag in &tags {
+						layers.add_tag(&repo_layer, tag, vec![repo.path.clone()])?;
+					}
+					match tags.is_empty() {
+						true => ImportOutcome::Unchanged,
+						false => ImportOutcome::Tagged { tags },
+					}
+				}
+			};
+			outcomes.push((repo, outcome));
+		}
+		if outcomes
+			.iter()
+			.any(|(_, outcome)| *outcome != ImportOutcome::Unchanged)
+		{
+			self.save_layers(layers)?;
+		}
+		Ok(outcomes)
+	}
+
 	pub fn remove(&mut self, repo_folders: &[String]) -> Result<(), GitopolisError> {
-		let mut repos = self.load()?;
-		repos.remove(normalize_folders(repo_folders));
-		self.save(repos)
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
+		layers.remove(normalize_folders(repo_folders));
+		self.save_layers(layers)
 	}
 	pub fn add_tag(
 		&mut self,
 		tag_name: &str,
 		repo_folders: &[String],
 	) -> Result<(), GitopolisError> {
-		let mut repos = self.load()?;
-		repos.add_tag(tag_name, normalize_folders(repo_folders))?;
-		self.save(repos)
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
+		let layer = self.tag_layer(&layers);
+		layers.add_tag(&layer, tag_name, normalize_folders(repo_folders))?;
+		self.save_layers(layers)
 	}
 	pub fn remove_tag(
 		&mut self,
 		tag_name: &str,
 		repo_folders: &[String],
 	) -> Result<(), GitopolisError> {
-		let mut repos = self.load()?;
-		repos.remove_tag(tag_name, normalize_folders(repo_folders))?;
-		self.save(repos)
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
+		layers.remove_tag(tag_name, normalize_folders(repo_folders))?;
+		self.save_layers(layers)
 	}
 	/// Filter repos by tag filter with AND/OR logic.
 	pub fn list(&self, filter: &TagFilter) -> Result<Vec<Repo>, GitopolisError> {
@@ -82,48 +397,296 @@ impl Gitopolis {
 			.filter(|repo| filter.matches(&repo.tags))
 			.collect();
 
-		result.sort_by(|a, b| a.path.to_lowercase().cmp(&b.path.to_lowercase()));
+		result.sort_by_key(|a| a.path.to_lowercase());
 		Ok(result)
 	}
+
+	/// The repos matching the tag filter whose git state also matches `state`. When there's a
+	/// state filter, repos whose state can't be read (e.g. missing folders) are left out.
+	pub fn select(
+		&self,
+		filter: &TagFilter,
+		state: &StateFilter,
+	) -> Result<Vec<Repo>, GitopolisError> {
+		let repos = self.list(filter)?;
+		if state.is_all() {
+			return Ok(repos);
+		}
+		Ok(repos
+			.into_iter()
+			.filter(|repo| {
+				self.repo_status(repo)
+					.is_ok_and(|status| self.in_state(repo, &status, state))
+			})
+			.collect())
+	}
 	pub fn read(&self) -> Result<Repos, GitopolisError> {
 		self.load()
 	}
-	pub fn clone(&self, repos: Vec<Repo>) -> usize {
-		let mut error_count = 0;
+	/// Which layer of the state gives each repo each of its tags, by repo path then tag.
+	pub fn tag_origins(&self) -> Result<BTreeMap<String, BTreeMap<String, Layer>>, GitopolisError> {
+		Ok(self.load_layers()?.tag_origins())
+	}
+	/// Clone each repo that isn't already on disk, up to `parallel` at a time, from its origin
+	/// remote if it has one (otherwise its first remote), then add its other remotes.
+	/// Each repo's start is sent to the observer when a worker picks it up, and its outcome in
+	/// list order as soon as it and the repos before it are done.
+	pub fn clone(&self, repos: Vec<Repo>, parallel: usize) -> CloneReport {
+		let git = &*self.git;
+		let observer = &*self.observer;
+		let root = &self.root;
+		let mut outcomes = vec![];
+		observer.event(Event::OperationStarted {
+			operation: Operation::Clone,
+			repo_count: repos.len(),
+			parallel: parallel.max(1),
+		});
 
-		for repo in repos {
-			// Determine which remote to use for cloning (prefer origin)
-			let clone_remote_name = if repo.remotes.contains_key("origin") {
-				"origin"
-			} else {
-				repo.remotes.keys().next().map(|s| s.as_str()).unwrap_or("")
-			};
+		run_in_order(
+			&repos,
+			parallel,
+			|_, repo| {
+				observer.event(Event::RepoStarted { repo });
+				let repo_path = root.join(&repo.path);
+				clone_repo(git, &repo_path, repo, &|progress| {
+					observer.event(Event::CloneProgress {
+						repo,
+						progress: &progress,
+					})
+				})
+			},
+			|repo, outcome| {
+				observer.event(Event::RepoFinished {
+					repo,
+					outcome: RepoOutcome::Clone(&outcome),
+				});
+				outcomes.push(outcome);
+			},
+		);
 
-			if let Some(clone_remote) = repo.remotes.get(clone_remote_name) {
-				// Clone the repo
-				match self.git.clone(repo.path.as_str(), &clone_remote.url) {
-					Ok(()) => {
-						// Add all other remotes
-						for (name, remote) in &repo.remotes {
-							if name != clone_remote_name {
-								self.git.add_remote(&repo.path, name, &remote.url);
-							}
-						}
-					}
-					Err(_) => {
-						eprintln!("Warning: Could not clone {}", repo.path);
-						error_count += 1;
-					}
-				}
-			}
+		observer.event(Event::OperationFinished {
+			operation: Operation::Clone,
+		});
+		CloneReport {
+			repos: repos.into_iter().zip(outcomes).collect(),
 		}
+	}
+	/// Fetch each repo from its remotes with git2, up to `parallel` at a time. Each repo's start is
+	/// sent to the observer when a worker picks it up, and its outcome in list order as soon as it
+	/// and the repos before it are done.
+	pub fn fetch(&self, repos: Vec<Repo>, options: &FetchOptions, parallel: usize) -> FetchReport {
+		let git = &*self.git;
+		let observer = &*self.observer;
+		let root = &self.root;
+		let mut results = vec![];
+		observer.event(Event::OperationStarted {
+			operation: Operation::Fetch,
+			repo_count: repos.len(),
+			parallel: parallel.max(1),
+		});
+
+		run_in_order(
+			&repos,
+			parallel,
+			|_, repo| {
+				observer.event(Event::RepoStarted { repo });
+				git.fetch(&root.join(&repo.path).to_string_lossy(), options)
+			},
+			|repo, result| {
+				observer.event(Event::RepoFinished {
+					repo,
+					outcome: RepoOutcome::Fetch(&result),
+				});
+				results.push(result);
+			},
+		);
 
-		if error_count > 0 {
-			eprintln!("{error_count} repos failed to clone");
-			std::process::exit(1);
+		observer.event(Event::OperationFinished {
+			operation: Operation::Fetch,
+		});
+		FetchReport {
+			repos: repos.into_iter().zip(results).collect(),
 		}
+	}
+	/// Fast-forward the current branch of each repo to its upstream, up to `parallel` at a time.
+	/// Repos with uncommitted changes, no upstream or a detached HEAD are skipped without
+	/// fetching; the rest are fetched from their upstream remote then fast-forwarded if possible,
+	/// never merged. Outcomes are sent to the observer in list order.
+	pub fn pull(&self, repos: Vec<Repo>, parallel: usize) -> Vec<RepoPullEntry> {
+		let git = &*self.git;
+		let observer = &*self.observer;
+		let root = &self.root;
+		let mut results = vec![];
+		observer.event(Event::OperationStarted {
+			operation: Operation::Pull,
+			repo_count: repos.len(),
+			parallel: parallel.max(1),
+		});
+
+		run_in_order(
+			&repos,
+			parallel,
+			|_, repo| {
+				observer.event(Event::RepoStarted { repo });
+				pull_repo(git, &root.join(&repo.path).to_string_lossy())
+			},
+			|repo, result| {
+				observer.event(Event::RepoFinished {
+					repo,
+					outcome: RepoOutcome::Pull(&result),
+				});
+				results.push(result);
+			},
+		);
+
+		observer.event(Event::OperationFinished {
+			operation: Operation::Pull,
+		});
+		repos.into_iter().zip(results).collect()
+	}
+	/// Read the local and remote branches of each repo.
+	pub fn branches(&self, repos: Vec<Repo>) -> Vec<RepoBranchesEntry> {
+		repos
+			.into_iter()
+			.map(|repo| {
+				let branches = self.git.branches(&self.resolve(&repo.path));
+				(repo, branches)
+			})
+			.collect()
+	}
 
-		error_count
+	/// Create branch `name` at the current commit of each repo and switch to it. Repos that
+	/// already have a branch with that name are left alone.
+	pub fn create_branch(&self, repos: Vec<Repo>, name: &str) -> Vec<RepoBranchEntry> {
+		self.each_branch_repo(repos, |repo_path, branches| {
+			if branches.local.iter().any(|branch| branch == name) {
+				return Ok(BranchOutcome::Exists);
+			}
+			self.git.create_branch(repo_path, name)?;
+			Ok(BranchOutcome::Created)
+		})
+	}
+
+	/// Switch each repo to branch `name`, creating it from a remote branch of the same name if
+	/// needed. Repos without the branch are left alone, as are repos with uncommitted changes
+	/// unless `stash` is set, in which case the changes are stashed first.
+	pub fn switch_branch(&self, repos: Vec<Repo>, name: &str, stash: bool) -> Vec<RepoBranchEntry> {
+		self.each_branch_repo(repos, |repo_path, branches| {
+			if branches.current.as_deref() == Some(name) {
+				return Ok(BranchOutcome::AlreadyOn);
+			}
+			let on_remote = branches
+				.remote
+				.iter()
+				.any(|branch| branch.split_once('/').map(|(_, branch)| branch) == Some(name));
+			if !on_remote && !branches.local.iter().any(|branch| branch == name) {
+				return Ok(BranchOutcome::Missing);
+			}
+			let status = self.git.status(repo_path)?;
+			if let Some(operation) = status.in_progress {
+				return Err(GitError {
+					message: format!("Can't switch branch with a {operation} in progress"),
+				});
+			}
+			let dirty = status.staged > 0 || status.unstaged > 0;
+			if dirty && !stash {
+				return Ok(BranchOutcome::Dirty);
+			}
+			if dirty {
+				let message = format!("gitopolis: before switching to {name}");
+				self.git.stash(repo_path, &message)?;
+			}
+			self.git.switch_branch(repo_path, name)?;
+			Ok(BranchOutcome::Switched { stashed: dirty })
+		})
+	}
+
+	/// Delete local branch `name` from each repo that has it, unless it's the current branch.
+	/// Unmerged branches are only deleted if `force` is set.
+	pub fn delete_branch(&self, repos: Vec<Repo>, name: &str, force: bool) -> Vec<RepoBranchEntry> {
+		self.each_branch_repo(repos, |repo_path, branches| {
+			if branches.current.as_deref() == Some(name) {
+				return Ok(BranchOutcome::Current);
+			}
+			if !branches.local.iter().any(|branch| branch == name) {
+				return Ok(BranchOutcome::Missing);
+			}
+			self.git.delete_branch(repo_path, name, force)?;
+			Ok(BranchOutcome::Deleted)
+		})
+	}
+
+	/// Run a branch operation on each repo in turn, given its location and current branches.
+	fn each_branch_repo(
+		&self,
+		repos: Vec<Repo>,
+		operation: impl Fn(&str, Branches) -> Result<BranchOutcome, GitopolisError>,
+	) -> Vec<RepoBranchEntry> {
+		self.observer.event(Event::OperationStarted {
+			operation: Operation::Branch,
+			repo_count: repos.len(),
+			parallel: 1,
+		});
+		let entries = repos
+			.into_iter()
+			.map(|repo| {
+				self.observer.event(Event::RepoStarted { repo: &repo });
+				let repo_path = self.resolve(&repo.path);
+				let result = self
+					.git
+					.branches(&repo_path)
+					.and_then(|branches| operation(&repo_path, branches));
+				self.observer.event(Event::RepoFinished {
+					repo: &repo,
+					outcome: RepoOutcome::Branch(&result),
+				});
+				(repo, result)
+			})
+			.collect();
+		self.observer.event(Event::OperationFinished {
+			operation: Operation::Branch,
+		});
+		entries
+	}
+
+	/// Read the working state of each repo matching the filters. Repos that can't be read (e.g.
+	/// missing folders) get an error in their entry rather than failing the whole listing, unless
+	/// there's a state filter, which they're left out by.
+	pub fn status(
+		&self,
+		filter: &TagFilter,
+		state: &StateFilter,
+	) -> Result<Vec<RepoStatusEntry>, GitopolisError> {
+		Ok(self
+			.list(filter)?
+			.into_iter()
+			.map(|repo| {
+				let status = self.repo_status(&repo);
+				(repo, status)
+			})
+			.filter(|(repo, status)| match status {
+				Ok(status) => self.in_state(repo, status, state),
+				Err(_) => state.is_all(),
+			})
+			.collect())
+	}
+
+	/// Read the working state of one repo.
+	pub fn repo_status(&self, repo: &Repo) -> Result<RepoStatus, GitopolisError> {
+		self.git.status(&self.resolve(&repo.path))
+	}
+
+	fn in_state(&self, repo: &Repo, status: &RepoStatus, state: &StateFilter) -> bool {
+		// only looked up when needed, as it's another read of the repo
+		let default_branch = match state.not_on_default_branch {
+			true => self
+				.git
+				.default_branch(&self.resolve(&repo.path))
+				.ok()
+				.flatten(),
+			false => None,
+		};
+		state.matches(status, default_branch.as_deref())
 	}
 	pub fn tags(&self) -> Result<Vec<String>, GitopolisError> {
 		let repos = self.load()?;
@@ -138,70 +701,168 @@ impl Gitopolis {
 		Ok(flat)
 	}
 
-	pub fn sync_read_remotes(&mut self, filter: &TagFilter) -> Result<(), GitopolisError> {
-		let mut repos = self.load()?;
+	/// Replace the remotes in the config with those in each repo matching the filter, then save.
+	/// Repos whose remotes can't be read are left as they were, with the error in their entry.
+	pub fn sync_read_remotes(
+		&mut self,
+		filter: &TagFilter,
+	) -> Result<Vec<RepoSyncEntry>, GitopolisError> {
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
 		let repo_list = self.list(filter)?;
-		let mut error_count = 0;
+		let mut entries = vec![];
+		self.observer.event(Event::OperationStarted {
+			operation: Operation::SyncReadRemotes,
+			repo_count: repo_list.len(),
+			parallel: 1,
+		});
 
 		for repo in repo_list {
-			match self.git.read_all_remotes(repo.path.clone()) {
-				Ok(remotes) => {
-					// Find the repo in the mutable repos structure and update its remotes
-					if let Some(repo_mut) = repos.find_repo(repo.path.clone()) {
-						repo_mut.remotes.clear();
-						for (name, url) in remotes {
-							repo_mut.add_remote(name, url);
-						}
-						info!("Updated {} with remotes from git", repo.path);
-					}
-				}
-				Err(_) => {
-					eprintln!("Warning: Could not read remotes from {}", repo.path);
-					error_count += 1;
-				}
-			}
+			self.observer.event(Event::RepoStarted { repo: &repo });
+			let result = self
+				.git
+				.read_all_remotes(self.resolve(&repo.path))
+				.and_then(|remotes| {
+					let configured = remotes
+						.iter()
+						.map(|(name, url)| {
+							let remote = Remote {
+								name: name.to_string(),
+								url: url.to_string(),
+							};
+							(name.to_string(), remote)
+						})
+						.collect();
+					layers.set_remotes(&repo.path, configured)?;
+					info!("Updated {} with remotes from git", repo.path);
+					Ok(remotes.into_keys().collect())
+				});
+			self.observer.event(Event::RepoFinished {
+				repo: &repo,
+				outcome: RepoOutcome::Sync(&result),
+			});
+			entries.push((repo, result));
 		}
 
-		self.save(repos)?;
+		self.save_layers(layers)?;
+		self.observer.event(Event::OperationFinished {
+			operation: Operation::SyncReadRemotes,
+		});
+		Ok(entries)
+	}
 
-		if error_count > 0 {
-			eprintln!("{error_count} repos failed to sync");
-			std::process::exit(1);
-		}
+	/// Add remotes from the config that are missing from each repo matching the filter. Each
+	/// entry lists the remotes added, or the first error hit for that repo.
+	pub fn sync_write_remotes(
+		&self,
+		filter: &TagFilter,
+	) -> Result<Vec<RepoSyncEntry>, GitopolisError> {
+		let repo_list = self.list(filter)?;
+		self.observer.event(Event::OperationStarted {
+			operation: Operation::SyncWriteRemotes,
+			repo_count: repo_list.len(),
+			parallel: 1,
+		});
+		let entries = repo_list
+			.into_iter()
+			.map(|repo| {
+				self.observer.event(Event::RepoStarted { repo: &repo });
+				let result = self.write_remotes(&repo);
+				self.observer.event(Event::RepoFinished {
+					repo: &repo,
+					outcome: RepoOutcome::Sync(&result),
+				});
+				(repo, result)
+			})
+			.collect();
+		self.observer.event(Event::OperationFinished {
+			operation: Operation::SyncWriteRemotes,
+		});
+		Ok(entries)
+	}
 
-		Ok(())
+	/// Run `exec_args` in each repo, up to `parallel` at a time, with output sent to the observer.
+	/// See `exec::exec`.
+	pub fn exec(
+		&self,
+		exec_args: &[String],
+		repos: Vec<Repo>,
+		options: &ExecOptions,
+		parallel: usize,
+	) -> ExecReport {
+		self.observer.event(Event::OperationStarted {
+			operation: Operation::Exec,
+			repo_count: repos.len(),
+			parallel: parallel.max(1),
+		});
+		let report = exec::exec(
+			&self.root,
+			exec_args,
+			repos,
+			options,
+			parallel,
+			&*self.observer,
+		);
+		self.observer.event(Event::OperationFinished {
+			operation: Operation::Exec,
+		});
+		report
 	}
 
-	pub fn sync_write_remotes(&self, filter: &TagFilter) -> Result<(), GitopolisError> {
-		let repo_list = self.list(filter)?;
-		let mut error_count = 0;
+	fn write_remotes(&self, repo: &Repo) -> Result<Vec<String>, GitopolisError> {
+		let repo_path = self.resolve(&repo.path);
+		// Get current remotes from git
+		let current_remotes = self.git.read_all_remotes(repo_path.clone())?;
 
-		for repo in repo_list {
-			// Get current remotes from git
-			let current_remotes = match self.git.read_all_remotes(repo.path.clone()) {
-				Ok(remotes) => remotes,
-				Err(_) => {
-					eprintln!("Warning: Could not write remotes to {}", repo.path);
-					error_count += 1;
-					continue;
-				}
-			};
+		// Add any missing remotes from config
+		let mut added = vec![];
+		for (name, remote) in &repo.remotes {
+			if !current_remotes.contains_key(name) {
+				self.git.add_remote(&repo_path, name, &remote.url)?;
+				info!("Added remote {} to {}", name, repo.path);
+				added.push(name.to_string());
+			}
+		}
+		Ok(added)
+	}
 
-			// Add any missing remotes from config
-			for (name, remote) in &repo.remotes {
-				if !current_remotes.contains_key(name) {
-					self.git.add_remote(&repo.path, name, &remote.url);
-					info!("Added remote {} to {}", name, repo.path);
+	/// Compare the config with what's on disk: configured repos whose folders are missing or
+	/// aren't git repos, or whose remotes differ from the config, and `found_folders` (repos
+	/// found on disk, relative to the root) that aren't in the config. Sorted by path.
+	pub fn doctor(&self, found_folders: &[String]) -> Result<Vec<Problem>, GitopolisError> {
+		let repos = self.list(&TagFilter::all())?;
+		let mut problems = vec![];
+		for repo in &repos {
+			let path = repo.path.to_string();
+			let repo_path = self.resolve(&repo.path);
+			if !Path::new(&repo_path).is_dir() {
+				problems.push(Problem::Missing { path });
+				continue;
+			}
+			match self.git.read_all_remotes(repo_path) {
+				Err(error) => problems.push(Problem::NotARepo {
+					path,
+					message: error.message(),
+				}),
+				Ok(remotes) => {
+					let differences = remote_differences(repo, &remotes);
+					if !differences.is_empty() {
+						problems.push(Problem::RemotesDiffer { path, differences });
+					}
 				}
 			}
 		}
-
-		if error_count > 0 {
-			eprintln!("{error_count} repos failed to sync");
-			std::process::exit(1);
+		let managed: Vec<String> = repos
+			.iter()
+			.map(|repo| normalize_folder(repo.path.to_string()))
+			.collect();
+		for folder in normalize_folders(found_folders) {
+			if !managed.contains(&folder) {
+				problems.push(Problem::Unmanaged { path: folder });
+			}
 		}
-
-		Ok(())
+		problems.sort_by_key(|problem| problem.path().to_lowercase());
+		Ok(problems)
 	}
 
 	pub fn show(&self, repo_path: &str) -> Result<RepoInfo, GitopolisError> {
@@ -223,12 +884,16 @@ impl Gitopolis {
 		})
 	}
 
+	/// Clone `url` into `target_dir` (or a folder named after the repo) and add it with `tags`.
+	/// A folder that's already there is added as it is. If the clone fails the repo isn't added;
+	/// the outcome holds git's output saying why. Progress is sent to the observer as a clone of
+	/// a single repo.
 	pub fn clone_and_add(
 		&mut self,
 		url: &str,
 		target_dir: Option<&str>,
 		tags: &[String],
-	) -> Result<String, GitopolisError> {
+	) -> Result<(String, CloneOutcome), GitopolisError> {
 		// Use target_dir if provided, otherwise extract from URL
 		let folder_name = match target_dir {
 			Some(dir) => dir.to_string(),
@@ -238,87 +903,282 @@ impl Gitopolis {
 		};
 
 		// Clone the repository
-		self.git.clone(&folder_name, url)?;
-
-		// Add the repository to gitopolis
-		self.add(folder_name.clone())?;
-
-		// Add tags if any were specified
-		if !tags.is_empty() {
-			for tag in tags {
-				self.add_tag(tag.as_str(), std::slice::from_ref(&folder_name))?;
+		let repo_path = self.resolve(&folder_name);
+		let repo = Repo {
+			path: folder_name.clone(),
+			tags: tags.to_vec(),
+			remotes: BTreeMap::new(),
+		};
+		self.observer.event(Event::OperationStarted {
+			operation: Operation::Clone,
+			repo_count: 1,
+			parallel: 1,
+		});
+		self.observer.event(Event::RepoStarted { repo: &repo });
+		let outcome = if Path::new(&repo_path).exists() {
+			CloneOutcome::AlreadyExists
+		} else {
+			let output = self.git.clone(&repo_path, url, &|progress| {
+				self.observer.event(Event::CloneProgress {
+					repo: &repo,
+					progress: &progress,
+				})
+			});
+			let url = url.to_string();
+			if output.success {
+				CloneOutcome::Cloned {
+					url,
+					output,
+					remote_errors: vec![],
+				}
+			} else {
+				CloneOutcome::Failed { url, output }
 			}
+		};
+		self.observer.event(Event::RepoFinished {
+			repo: &repo,
+			outcome: RepoOutcome::Clone(&outcome),
+		});
+		self.observer.event(Event::OperationFinished {
+			operation: Operation::Clone,
+		});
+		if let CloneOutcome::Failed { .. } = outcome {
+			return Ok((folder_name, outcome));
 		}
 
-		Ok(folder_name)
+		// Add the repository to gitopolis along with any tags specified
+		self.add_repos(vec![folder_name.clone()], tags)?;
+
+		Ok((folder_name, outcome))
 	}
 
 	pub fn move_repo(&mut self, old_path: &str, new_path: &str) -> Result<(), GitopolisError> {
-		let mut repos = self.load()?;
+		let _lock = self.storage.lock()?;
+		let mut layers = self.load_layers()?;
 		let normalized_old = normalize_folder(old_path.to_string());
 		let normalized_new = normalize_folder(new_path.to_string());
 
-		// Find the repo in the config
-		let repo = repos
-			.as_vec()
-			.iter()
-			.find(|r| r.path == normalized_old)
-			.ok_or_else(|| StateError {
+		// Check the repo is in the config, and in a file we can change, before moving anything
+		if layers
+			.merged()
+			.repo_index(normalized_old.to_string())
+			.is_none()
+		{
+			return Err(StateError {
 				message: format!("Repo '{}' not found", normalized_old),
-			})?
-			.clone();
+			});
+		}
+		if let Some(layer) = layers.included_in(&normalized_old) {
+			return Err(StateError {
+				message: format!(
+					"Repo '{normalized_old}' comes from {layer}, which gitopolis doesn't change"
+				),
+			});
+		}
 
 		// Create parent directories if they don't exist
-		if let Some(parent) = std::path::Path::new(&normalized_new).parent() {
+		let new_location = self.resolve(&normalized_new);
+		if let Some(parent) = std::path::Path::new(&new_location).parent() {
 			if !parent.as_os_str().is_empty() {
 				std::fs::create_dir_all(parent).map_err(|e| IoError { inner: e })?;
 			}
 		}
 
 		// Move the actual folder on the filesystem
-		std::fs::rename(&normalized_old, &normalized_new).map_err(|e| IoError { inner: e })?;
+		std::fs::rename(self.resolve(&normalized_old), new_location)
+			.map_err(|e| IoError { inner: e })?;
 
 		// Update the config: remove old entry and add new one with same tags/remotes
-		repos.remove(vec![normalized_old]);
-		repos.add_with_tags_and_remotes(normalized_new, repo.tags, repo.remotes);
+		layers.rename(&normalized_old, &normalized_new)?;
 
-		self.save(repos)?;
-		Ok(())
+		self.save_layers(layers)
+	}
+
+	/// Location of a repo on disk, for a path as stored in the state file.
+	fn resolve(&self, repo_path: &str) -> String {
+		self.root.join(repo_path).to_string_lossy().to_string()
+	}
+
+	/// Where tags are saved unless told otherwise: the personal overlay if there is one.
+	fn tag_layer(&self, layers: &Layers) -> Layer {
+		match &self.write_layer {
+			Some(layer) => layer.clone(),
+			None if layers.has(&Layer::Local) => Layer::Local,
+			None => Layer::Shared,
+		}
 	}
 
-	fn save(&self, repos: Repos) -> Result<(), GitopolisError> {
-		let state_toml = serialize(&repos)?;
-		self.storage.save(state_toml);
+	/// Save each layer gitopolis can change by editing the existing file in place, so hand-written
+	/// comments survive. Files whose contents haven't changed are left alone, and the personal
+	/// overlay is only created once something is put in it. Callers that loaded `layers` hold the
+	/// storage lock from before loading until after saving.
+	fn save_layers(&self, layers: Layers) -> Result<(), GitopolisError> {
+		for (layer, repos) in layers.into_vec() {
+			let existing = match layer {
+				Layer::Shared if self.storage.exists() => Some(self.storage.read()?),
+				Layer::Shared => None,
+				Layer::Local => self.storage.read_layer(&layer)?,
+				Layer::Include(_) => continue,
+			};
+			let state_toml = state_file::update(existing.as_deref().unwrap_or(""), &repos)?;
+			if existing.as_ref() == Some(&state_toml) {
+				continue;
+			}
+			match layer {
+				Layer::Shared => self.storage.save(state_toml)?,
+				_ => self.storage.save_layer(&layer, state_toml)?,
+			}
+		}
 		Ok(())
 	}
 
+	/// The repos from all the layers of the state merged together.
 	fn load(&self) -> Result<Repos, GitopolisError> {
-		if !self.storage.exists() {
-			return Ok(Repos::new());
-		}
+		Ok(self.load_layers()?.merged())
+	}
 
-		let state_toml = self.storage.read();
+	/// Read the shared state file, the files it and the personal overlay include, then the
+	/// personal overlay. Included files can't include further files.
+	fn load_layers(&self) -> Result<Layers, GitopolisError> {
+		let layers = self
+			.read_state_files()?
+			.into_iter()
+			.map(|(layer, state)| (layer, Repos::new_with_repos(state.repos)))
+			.collect();
+		Ok(Layers::new(layers))
+	}
+
+	/// Parse the shared state file, the files it and the personal overlay include, then the
+	/// personal overlay, in that order.
+	fn read_state_files(&self) -> Result<Vec<(Layer, StateFile)>, GitopolisError> {
+		let shared_toml = if self.storage.exists() {
+			self.storage.read()?
+		} else {
+			String::new()
+		};
+		let shared = parse(&shared_toml)?;
+		let mut includes = shared.include.clone();
+		let local = match self.storage.read_layer(&Layer::Local)? {
+			Some(local_toml) => {
+				let local = parse(&local_toml)?;
+				includes.extend(local.include.iter().cloned());
+				Some(local)
+			}
+			None => None,
+		};
 
-		parse(&state_toml)
+		let mut files = vec![(Layer::Shared, shared)];
+		for include in includes {
+			let layer = Layer::Include(include);
+			let include_toml = self.storage.read_layer(&layer)?.ok_or_else(|| StateError {
+				message: format!("Included state file {layer} not found"),
+			})?;
+			let included = parse(&include_toml)?;
+			if !included.include.is_empty() {
+				return Err(StateError {
+					message: format!("Included state file {layer} can't include other files"),
+				});
+			}
+			files.push((layer, included));
+		}
+		if let Some(local) = local {
+			files.push((Layer::Local, local));
+		}
+		Ok(files)
 	}
 }
 
-fn serialize(repos: &Repos) -> Result<String, GitopolisError> {
-	toml::to_string(&repos).map_err(|error| StateError {
-		message: format!("Failed to generate toml for repo list. {error}"),
+/// Check a repo is safe to fast-forward, then fetch and fast-forward it. Runs on a worker thread.
+fn pull_repo(git: &dyn Git, repo_path: &str) -> Result<PullOutcome, GitopolisError> {
+	let status = git.status(repo_path)?;
+	let branch = match status.head {
+		Head::Branch(branch) => branch,
+		Head::Unborn(branch) => return Ok(PullOutcome::NoUpstream { branch }),
+		Head::Detached(commit) => return Ok(PullOutcome::Detached { commit }),
+	};
+	if status.staged > 0 || status.unstaged > 0 || status.in_progress.is_some() {
+		return Ok(PullOutcome::Dirty { branch });
+	}
+	if status.ahead_behind.is_none() {
+		return Ok(PullOutcome::NoUpstream { branch });
+	}
+
+	git.fetch(repo_path, &FetchOptions::default())?;
+	Ok(match git.fast_forward(repo_path)? {
+		FastForward::UpToDate => PullOutcome::UpToDate { branch },
+		FastForward::Updated { from, to, commits } => PullOutcome::Updated {
+			branch,
+			from,
+			to,
+			commits,
+		},
+		FastForward::Diverged { ahead, behind } => PullOutcome::Diverged {
+			branch,
+			ahead,
+			behind,
+		},
 	})
 }
 
-fn parse(state_toml: &str) -> Result<Repos, GitopolisError> {
-	let mut named_container: BTreeMap<String, Vec<Repo>> =
-		toml::from_str(state_toml).map_err(|error| StateError {
-			message: format!("Failed to parse state data as valid TOML. {error}"),
-		})?;
+/// Clone a repo from its origin remote, or its first remote if it has no origin, then add the
+/// rest of its remotes. Runs on a worker thread, so output is returned rather than printed.
+fn clone_repo(
+	git: &dyn Git,
+	repo_path: &Path,
+	repo: &Repo,
+	progress: &dyn Fn(CloneProgress),
+) -> CloneOutcome {
+	let clone_remote_name = if repo.remotes.contains_key("origin") {
+		"origin"
+	} else {
+		repo.remotes.keys().next().map(|s| s.as_str()).unwrap_or("")
+	};
+	let Some(clone_remote) = repo.remotes.get(clone_remote_name) else {
+		return CloneOutcome::NoRemotes;
+	};
+	if repo_path.exists() {
+		return CloneOutcome::AlreadyExists;
+	}
 
-	let repos = named_container
-		.remove("repos") // [re]move this rather than taking a ref so that ownership moves with it (borrow checker)
-		.expect("Failed to read 'repos' entry from state TOML");
-	Ok(Repos::new_with_repos(repos))
+	let repo_path = repo_path.to_string_lossy();
+	let url = clone_remote.url.clone();
+	let output = git.clone(&repo_path, &url, progress);
+	if !output.success {
+		return CloneOutcome::Failed { url, output };
+	}
+	let remote_errors = repo
+		.remotes
+		.iter()
+		.filter(|(name, _)| name.as_str() != clone_remote_name)
+		.filter_map(|(name, remote)| git.add_remote(&repo_path, name, &remote.url).err())
+		.collect();
+	CloneOutcome::Cloned {
+		url,
+		output,
+		remote_errors,
+	}
+}
+
+/// The contents of one state file. Unknown top-level keys are an error, so that a misspelled
+/// table such as `[[repo]]` is reported rather than loading as no repos. Unknown keys inside a
+/// repo's entry, such as notes added by hand, are ignored.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+	/// Other state files to merge in, relative to the main state file's folder.
+	#[serde(default)]
+	include: Vec<String>,
+	#[serde(default)]
+	repos: Vec<Repo>,
+	/// Named commands for `gitopolis run`.
+	#[serde(default)]
+	aliases: BTreeMap<String, AliasEntry>,
+}
+
+fn parse(state_toml: &str) -> Result<StateFile, GitopolisError> {
+	toml::from_str(state_toml).map_err(|error| StateError {
+		message: format!("Failed to parse state data as valid TOML. {error}"),
+	})
 }
 
 fn normalize_folders(repo_folders: &[String]) -> Vec<String> {
@@ -345,7 +1205,7 @@ fn normalize_folder(repo_folder: String) -> String {
 /// - https://dev.azure.com/org/project/_git/myrepo -> myrepo
 /// - source_repo -> source_repo
 /// - C:\path\to\repo.git -> repo (Windows)
-fn extract_repo_name_from_url(url: &str) -> Option<String> {
+pub fn extract_repo_name_from_url(url: &str) -> Option<String> {
 	// Split by /, :, and \ (for Windows paths)
 	let parts: Vec<&str> = url.split(&['/', ':', '\\'][..]).collect();
 
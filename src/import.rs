@@ -0,0 +1,429 @@
+use crate::gitopolis::GitopolisError;
+use crate::gitopolis::GitopolisError::ApiError;
+use crate::repos::{Remote, Repo};
+use base64::Engine;
+use log::info;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+/// Where to read a list of repos from.
+#[derive(Debug, Clone)]
+pub enum Source {
+	/// The repos of a GitHub organisation.
+	GitHubOrg { org: String },
+	/// The repos a GitHub user owns.
+	GitHubUser { user: String },
+	/// The projects in a GitLab group and its subgroups.
+	GitLab { group: String },
+	/// The repos in an Azure DevOps project.
+	Azure {
+		organization: String,
+		project: String,
+	},
+}
+
+impl Source {
+	fn default_api_url(&self) -> &'static str {
+		match self {
+			Source::GitHubOrg { .. } | Source::GitHubUser { .. } => "https://api.github.com",
+			Source::GitLab { .. } => "https://gitlab.com/api/v4",
+			Source::Azure { .. } => "https://dev.azure.com",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+	/// Base URL of the provider's API, for self-hosted servers. Defaults to the public service.
+	pub api_url: Option<String>,
+	/// Personal access token, needed for private repos.
+	pub token: Option<String>,
+	/// Use https clone URLs for the origin remote instead of ssh ones.
+	pub https: bool,
+	/// Extra tags to add to every repo, on top of the ones describing it.
+	pub tags: Vec<String>,
+}
+
+/// Read all the repos from `source`, following the API's paging, as repos with an origin remote
+/// and tags describing them (visibility, language, topics, archived) plus any extra tags from
+/// the options, sorted by path.
+pub fn fetch_repos(source: &Source, options: &ImportOptions) -> Result<Vec<Repo>, GitopolisError> {
+	let api_url = options
+		.api_url
+		.as_deref()
+		.unwrap_or(source.default_api_url())
+		.trim_end_matches('/');
+	let client = Client::new(source, options.token.as_deref())?;
+	let mut repos: Vec<Repo> = match source {
+		Source::GitHubOrg { org } => {
+			let url = format!("{api_url}/orgs/{}/repos?per_page=100", encode(org));
+			github_repos(client.get_all(&url)?, options.https)
+		}
+		Source::GitHubUser { user } => {
+			let url = format!("{api_url}/users/{}/repos?per_page=100", encode(user));
+			github_repos(client.get_all(&url)?, options.https)
+		}
+		Source::GitLab { group } => {
+			let url = format!(
+				"{api_url}/groups/{}/projects?per_page=100&include_subgroups=true",
+				encode(group)
+			);
+			gitlab_repos(client.get_all(&url)?, group, options.https)
+		}
+		Source::Azure {
+			organization,
+			project,
+		} => {
+			let url = format!(
+				"{api_url}/{}/{}/_apis/git/repositories?api-version=7.1",
+				encode(organization),
+				encode(project)
+			);
+			azure_repos(client.get_all(&url)?, options.https)
+		}
+	};
+	repos.retain(|repo| {
+		let relative = !repo.path.is_empty()
+			&& Path::new(&repo.path)
+				.components()
+				.all(|component| matches!(component, Component::Normal(_)));
+		if !relative {
+			info!("Skipping '{}', not a folder inside this one", repo.path);
+		}
+		relative
+	});
+	for repo in &mut repos {
+		for tag in &options.tags {
+			if !repo.tags.contains(tag) {
+				repo.tags.push(tag.clone());
+			}
+		}
+		repo.tags.sort();
+	}
+	repos.sort_by_key(|repo| repo.path.to_lowercase());
+	Ok(repos)
+}
+
+#[derive(Deserialize)]
+struct GitHubRepo {
+	name: String,
+	clone_url: String,
+	ssh_url: String,
+	#[serde(default)]
+	private: bool,
+	visibility: Option<String>,
+	#[serde(default)]
+	archived: bool,
+	#[serde(default)]
+	fork: bool,
+	language: Option<String>,
+	#[serde(default)]
+	topics: Vec<String>,
+}
+
+fn github_repos(repos: Vec<GitHubRepo>, https: bool) -> Vec<Repo> {
+	repos
+		.into_iter()
+		.map(|repo| {
+			let visibility = repo.visibility.unwrap_or_else(|| {
+				match repo.private {
+					true => "private",
+					false => "public",
+				}
+				.to_string()
+			});
+			let mut tags = vec![visibility];
+			tags.extend(repo.language);
+			tags.extend(repo.topics);
+			tags.extend(flag("archived", repo.archived));
+			tags.extend(flag("fork", repo.fork));
+			let url = if https { repo.clone_url } else { repo.ssh_url };
+			new_repo(repo.name, url, tags)
+		})
+		.collect()
+}
+
+#[derive(Deserialize)]
+struct GitLabProject {
+	path_with_namespace: String,
+	http_url_to_repo: String,
+	ssh_url_to_repo: String,
+	visibility: Option<String>,
+	#[serde(default)]
+	archived: bool,
+	#[serde(default)]
+	topics: Vec<String>,
+}
+
+/// Projects are put in folders relative to the group, so subgroups become subfolders.
+fn gitlab_repos(projects: Vec<GitLabProject>, group: &str, https: bool) -> Vec<Repo> {
+	let group = group.trim_matches('/');
+	projects
+		.into_iter()
+		.map(|project| {
+			let full_path = project.path_with_namespace;
+			let path = match strip_group(&full_path, group) {
+				Some(relative) => relative.to_string(),
+				None => full_path,
+			};
+			let mut tags: Vec<String> = project.visibility.into_iter().collect();
+			tags.extend(project.topics);
+			tags.extend(flag("archived", project.archived));
+			let url = match https {
+				true => project.http_url_to_repo,
+				false => project.ssh_url_to_repo,
+			};
+			new_repo(path, url, tags)
+		})
+		.collect()
+}
+
+/// `path` without the leading `group/`, or None if it isn't in the group. Compared a character at
+/// a time ignoring case, as GitLab paths are, so the rest is cut at the right place even when a
+/// character's lowercase form is a different length.
+fn strip_group<'a>(path: &'a str, group: &str) -> Option<&'a str> {
+	let mut chars = path.chars();
+	for expected in group.chars() {
+		let actual = chars.next()?;
+		if !actual.to_lowercase().eq(expected.to_lowercase()) {
+			return None;
+		}
+	}
+	chars.as_str().strip_prefix('/')
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureRepo {
+	name: String,
+	remote_url: String,
+	ssh_url: String,
+	#[serde(default)]
+	is_disabled: bool,
+	project: Option<AzureProject>,
+}
+
+#[derive(Deserialize)]
+struct AzureProject {
+	visibility: Option<String>,
+}
+
+fn azure_repos(repos: Vec<AzureRepo>, https: bool) -> Vec<Repo> {
+	repos
+		.into_iter()
+		.map(|repo| {
+			let mut tags: Vec<String> = repo
+				.project
+				.and_then(|project| project.visibility)
+				.into_iter()
+				.collect();
+			tags.extend(flag("disabled", repo.is_disabled));
+			let url = if https { repo.remote_url } else { repo.ssh_url };
+			new_repo(repo.name, url, tags)
+		})
+		.collect()
+}
+
+fn flag(tag: &str, set: bool) -> Option<String> {
+	set.then(|| tag.to_string())
+}
+
+/// Tags are lower case without spaces so they can be typed in tag filters, e.g. "Jupyter
+/// Notebook" becomes "jupyter-notebook".
+fn new_repo(path: String, url: String, tags: Vec<String>) -> Repo {
+	let mut tags: Vec<String> = tags
+		.iter()
+		.map(|tag| tag.trim().to_lowercase().replace([' ', ','], "-"))
+		.filter(|tag| !tag.is_empty())
+		.collect();
+	tags.sort();
+	tags.dedup();
+	let mut remotes = BTreeMap::new();
+	remotes.insert(
+		"origin".to_string(),
+		Remote {
+			name: "origin".to_string(),
+			url,
+		},
+	);
+	Repo {
+		path,
+		tags,
+		remotes,
+	}
+}
+
+/// A page of results: a plain list for GitHub and GitLab, wrapped in `value` for Azure DevOps.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Page<T> {
+	List(Vec<T>),
+	Wrapped { value: Vec<T> },
+}
+
+struct Client {
+	agent: ureq::Agent,
+	auth: Option<(&'static str, String)>,
+}
+
+impl Client {
+	fn new(source: &Source, token: Option<&str>) -> Result<Self, GitopolisError> {
+		let tls = native_tls::TlsConnector::new().map_err(|error| ApiError {
+			message: format!("Failed to set up TLS: {error}"),
+		})?;
+		let agent = ureq::AgentBuilder::new()
+			.tls_connector(Arc::new(tls))
+			.user_agent(concat!("gitopolis/", env!("CARGO_PKG_VERSION")))
+			.build();
+		let auth = token.map(|token| match source {
+			Source::GitHubOrg { .. } | Source::GitHubUser { .. } => {
+				("Authorization", format!("Bearer {token}"))
+			}
+			Source::GitLab { .. } => ("PRIVATE-TOKEN", token.to_string()),
+			Source::Azure { .. } => {
+				let basic = base64::engine::general_purpose::STANDARD.encode(format!(":{token}"));
+				("Authorization", format!("Basic {basic}"))
+			}
+		});
+		Ok(Self { agent, auth })
+	}
+
+	/// Get `url` and every page after it.
+	fn get_all<T: DeserializeOwned>(&self, url: &str) -> Result<Vec<T>, GitopolisError> {
+		let mut items = vec![];
+		let mut next = Some(url.to_string());
+		while let Some(page_url) = next {
+			let mut request = self.agent.get(&page_url);
+			if let Some((header, value)) = &self.auth {
+				request = request.set(header, value);
+			}
+			let response = request
+				.call()
+				.map_err(|error| api_error(&page_url, error))?;
+			next = next_page(url, &response).filter(|next_url| *next_url != page_url);
+			let page: Page<T> =
+				serde_json::from_reader(response.into_reader()).map_err(|error| ApiError {
+					message: format!("Unexpected response from {page_url}: {error}"),
+				})?;
+			items.extend(match page {
+				Page::List(list) => list,
+				Page::Wrapped { value } => value,
+			});
+		}
+		Ok(items)
+	}
+}
+
+/// The next page from a `Link: <url>; rel="next"` header (GitHub and GitLab) or a continuation
+/// token (Azure DevOps).
+fn next_page(first_url: &str, response: &ureq::Response) -> Option<String> {
+	if let Some(link) = response.header("link") {
+		return next_link(link);
+	}
+	response
+		.header("x-ms-continuationtoken")
+		.map(|token| format!("{first_url}&continuationToken={}", encode(token)))
+}
+
+fn next_link(link_header: &str) -> Option<String> {
+	link_header.split(',').find_map(|link| {
+		let (url, params) = link.split_once(';')?;
+		params
+			.split(';')
+			.any(|param| param.trim().replace(' ', "") == "rel=\"next\"")
+			.then(|| {
+				url.trim()
+					.trim_start_matches('<')
+					.trim_end_matches('>')
+					.to_string()
+			})
+	})
+}
+
+fn api_error(url: &str, error: ureq::Error) -> GitopolisError {
+	let message = match error {
+		ureq::Error::Status(status, response) => {
+			let status_text = response.status_text().to_string();
+			let body = response.into_string().unwrap_or_default();
+			let detail = serde_json::from_str::<serde_json::Value>(&body)
+				.ok()
+				.and_then(|json| {
+					json.get("message")
+						.and_then(|m| m.as_str().map(String::from))
+				})
+				.unwrap_or(body);
+			format!("{url} returned {status} {status_text}: {}", detail.trim())
+		}
+		ureq::Error::Transport(transport) => format!("Failed to get {url}: {transport}"),
+	};
+	ApiError { message }
+}
+
+/// Percent-encode a URL path segment or query value, e.g. a GitLab group "team/sub".
+fn encode(segment: &str) -> String {
+	segment
+		.bytes()
+		.map(|byte| match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+				(byte as char).to_string()
+			}
+			_ => format!("%{byte:02X}"),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finds_next_page_in_link_header() {
+		let link = r#"<https://api.github.com/organizations/1/repos?page=1>; rel="prev", <https://api.github.com/organizations/1/repos?page=3>; rel="next", <https://api.github.com/organizations/1/repos?page=5>; rel="last""#;
+		assert_eq!(
+			Some("https://api.github.com/organizations/1/repos?page=3".to_string()),
+			next_link(link)
+		);
+		assert_eq!(None, next_link(r#"<https://x/?page=1>; rel="first""#));
+	}
+
+	#[test]
+	fn gitlab_projects_are_relative_to_the_group() {
+		let project = |path: &str| GitLabProject {
+			path_with_namespace: path.to_string(),
+			http_url_to_repo: format!("https://gitlab.com/{path}.git"),
+			ssh_url_to_repo: format!("git@gitlab.com:{path}.git"),
+			visibility: Some("internal".to_string()),
+			archived: true,
+			topics: vec!["Web App".to_string()],
+		};
+		let repos = gitlab_repos(
+			vec![project("Team/api"), project("team/tools/cli")],
+			"team",
+			false,
+		);
+		let paths: Vec<&str> = repos.iter().map(|repo| repo.path.as_str()).collect();
+		assert_eq!(vec!["api", "tools/cli"], paths);
+		assert_eq!(vec!["archived", "internal", "web-app"], repos[0].tags);
+		assert_eq!(
+			"git@gitlab.com:Team/api.git",
+			repos[0].remotes["origin"].url
+		);
+
+		let repos = gitlab_repos(
+			vec![
+				project("Ⱥ/api"),
+				project("Équipe/web"),
+				project("teams/cli"),
+			],
+			"ⱥ",
+			false,
+		);
+		let paths: Vec<&str> = repos.iter().map(|repo| repo.path.as_str()).collect();
+		assert_eq!(vec!["api", "Équipe/web", "teams/cli"], paths);
+		assert_eq!("web", strip_group("Équipe/web", "équipe").unwrap());
+		assert_eq!(None, strip_group("team", "team"));
+	}
+}
@@ -0,0 +1,390 @@
+use crate::gitopolis::GitopolisError;
+use crate::gitopolis::GitopolisError::StateError;
+use crate::repos::{Remote, Repo, Repos};
+use log::info;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// One of the files the state is stored in.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+	/// The main state file, `.gitopolis.toml`, usually shared with the team.
+	Shared,
+	/// A file listed in an `include = [...]` entry, relative to the main state file's folder.
+	/// Gitopolis only reads these.
+	Include(String),
+	/// The personal overlay next to the main state file, `.gitopolis.local.toml`.
+	Local,
+}
+
+impl Layer {
+	pub fn is_writable(&self) -> bool {
+		!matches!(self, Layer::Include(_))
+	}
+}
+
+impl fmt::Display for Layer {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Layer::Shared => write!(f, "shared"),
+			Layer::Include(path) => write!(f, "{path}"),
+			Layer::Local => write!(f, "local"),
+		}
+	}
+}
+
+/// The repos from each layer of the state, in order of precedence: the shared file, then any
+/// included files, then the personal overlay. Repos are merged by path; tags from all layers
+/// are combined and a remote in a later layer replaces one with the same name in an earlier one.
+#[derive(Debug, Clone)]
+pub struct Layers {
+	layers: Vec<(Layer, Repos)>,
+}
+
+impl Layers {
+	pub fn new(layers: Vec<(Layer, Repos)>) -> Self {
+		Self { layers }
+	}
+
+	pub fn into_vec(self) -> Vec<(Layer, Repos)> {
+		self.layers
+	}
+
+	pub fn has(&self, layer: &Layer) -> bool {
+		self.layers.iter().any(|(existing, _)| existing == layer)
+	}
+
+	/// All the layers combined into the single list of repos the rest of gitopolis works with.
+	pub fn merged(&self) -> Repos {
+		let mut merged: Vec<Repo> = vec![];
+		for (_, repos) in &self.layers {
+			for repo in repos.as_vec() {
+				match merged
+					.iter_mut()
+					.find(|existing| existing.path == repo.path)
+				{
+					None => merged.push(repo.clone()),
+					Some(existing) => {
+						for tag in &repo.tags {
+							if !existing.tags.contains(tag) {
+								existing.tags.push(tag.to_string());
+							}
+						}
+						existing.tags.sort_by_key(|tag| tag.to_lowercase());
+						for (name, remote) in &repo.remotes {
+							existing.remotes.insert(name.to_string(), remote.clone());
+						}
+					}
+				}
+			}
+		}
+		merged.sort_by_key(|repo| repo.path.to_lowercase());
+		Repos::new_with_repos(merged)
+	}
+
+	/// The first layer that gives each repo each of its tags, by repo path then tag.
+	pub fn tag_origins(&self) -> BTreeMap<String, BTreeMap<String, Layer>> {
+		let mut origins: BTreeMap<String, BTreeMap<String, Layer>> = BTreeMap::new();
+		for (layer, repos) in &self.layers {
+			for repo in repos.as_vec() {
+				let repo_origins = origins.entry(repo.path.to_string()).or_default();
+				for tag in &repo.tags {
+					repo_origins
+						.entry(tag.to_string())
+						.or_insert_with(|| layer.clone());
+				}
+			}
+		}
+		origins
+	}
+
+	/// The repos in `layer`, adding the layer (empty) if it isn't there yet.
+	pub fn get_mut(&mut self, layer: &Layer) -> &mut Repos {
+		let index = match self
+			.layers
+			.iter()
+			.position(|(existing, _)| existing == layer)
+		{
+			Some(index) => index,
+			None => {
+				let index = match layer {
+					Layer::Shared => 0,
+					Layer::Include(_) => (self.layers.iter())
+						.position(|(existing, _)| *existing == Layer::Local)
+						.unwrap_or(self.layers.len()),
+					Layer::Local => self.layers.len(),
+				};
+				self.layers.insert(index, (layer.clone(), Repos::new()));
+				index
+			}
+		};
+		&mut self.layers[index].1
+	}
+
+	/// The first included file that has an entry for a repo, if any.
+	pub fn included_in(&self, repo_folder: &str) -> Option<&Layer> {
+		self.layers
+			.iter()
+			.find(|(layer, repos)| {
+				!layer.is_writable() && repos.repo_index(repo_folder.to_string()).is_some()
+			})
+			.map(|(layer, _)| layer)
+	}
+
+	/// Tag repos in `layer`, adding entries for them to that layer if needed. Repos that already
+	/// have the tag from any layer are left alone.
+	pub fn add_tag(
+		&mut self,
+		layer: &Layer,
+		tag_name: &str,
+		repo_folders: Vec<String>,
+	) -> Result<(), GitopolisError> {
+		let merged = self.merged();
+		for repo_folder in repo_folders {
+			let repo = merged
+				.as_vec()
+				.iter()
+				.find(|repo| repo.path == repo_folder)
+				.ok_or_else(|| StateError {
+					message: format!("Repo '{repo_folder}' not found"),
+				})?;
+			if repo.tags.iter().any(|tag| tag == tag_name) {
+				continue;
+			}
+			let entry = self.get_mut(layer).entry(&repo_folder);
+			entry.tags.push(tag_name.to_string());
+			entry.tags.sort_by_key(|tag| tag.to_lowercase());
+		}
+		Ok(())
+	}
+
+	/// Remove a tag from repos in every layer gitopolis can change. Fails if a repo would still
+	/// have the tag from an included file.
+	pub fn remove_tag(
+		&mut self,
+		tag_name: &str,
+		repo_folders: Vec<String>,
+	) -> Result<(), GitopolisError> {
+		for repo_folder in repo_folders {
+			if self.merged().repo_index(repo_folder.to_string()).is_none() {
+				return Err(StateError {
+					message: format!("Repo '{repo_folder}' not found"),
+				});
+			}
+			for repo in self.writable_entries_mut(&repo_folder) {
+				repo.tags.retain(|tag| tag != tag_name);
+			}
+			self.remove_empty_overlay_entry(&repo_folder);
+			let origins = self.tag_origins();
+			if let Some(layer) = origins
+				.get(&repo_folder)
+				.and_then(|tags| tags.get(tag_name))
+			{
+				return Err(StateError {
+					message: format!(
+						"Tag '{tag_name}' on {repo_folder} comes from {layer}, which gitopolis doesn't change"
+					),
+				});
+			}
+		}
+		Ok(())
+	}
+
+	/// Remove repos from every layer gitopolis can change.
+	pub fn remove(&mut self, repo_folders: Vec<String>) {
+		for repo_folder in repo_folders {
+			let mut removed = false;
+			for (layer, repos) in &mut self.layers {
+				if layer.is_writable() && repos.repo_index(repo_folder.to_string()).is_some() {
+					repos.remove(vec![repo_folder.to_string()]);
+					removed = true;
+				}
+			}
+			match self.included_in(&repo_folder) {
+				Some(layer) => info!("{repo_folder} is still included from {layer}"),
+				None if !removed => info!("Repo already absent, skipped: {repo_folder}"),
+				None => {}
+			}
+		}
+	}
+
+	/// Change a repo's path in every layer that has it.
+	pub fn rename(&mut self, old_path: &str, new_path: &str) -> Result<(), GitopolisError> {
+		if let Some(layer) = self.included_in(old_path) {
+			return Err(StateError {
+				message: format!(
+					"Repo '{old_path}' comes from {layer}, which gitopolis doesn't change"
+				),
+			});
+		}
+		for (layer, repos) in &mut self.layers {
+			if !layer.is_writable() {
+				continue;
+			}
+			if let Some(repo) = repos.find_repo(old_path.to_string()) {
+				let repo = repo.clone();
+				repos.remove(vec![old_path.to_string()]);
+				repos.add_with_tags_and_remotes(new_path.to_string(), repo.tags, repo.remotes);
+			}
+		}
+		Ok(())
+	}
+
+	/// Replace a repo's remotes. They're stored in the first layer gitopolis can change that
+	/// already has remotes for the repo (or failing that has the repo at all), and removed from
+	/// the others so they don't override them.
+	pub fn set_remotes(
+		&mut self,
+		repo_folder: &str,
+		remotes: BTreeMap<String, Remote>,
+	) -> Result<(), GitopolisError> {
+		let writable_with = |has: fn(&Repo) -> bool| {
+			self.layers.iter().position(|(layer, repos)| {
+				layer.is_writable()
+					&& repos
+						.as_vec()
+						.iter()
+						.any(|repo| repo.path == repo_folder && has(repo))
+			})
+		};
+		let owner = writable_with(|repo| !repo.remotes.is_empty())
+			.or_else(|| writable_with(|_| true))
+			.ok_or_else(|| StateError {
+				message: match self.included_in(repo_folder) {
+					Some(layer) => format!(
+						"Repo '{repo_folder}' comes from {layer}, which gitopolis doesn't change"
+					),
+					None => format!("Repo '{repo_folder}' not found"),
+				},
+			})?;
+		for (index, (layer, repos)) in self.layers.iter_mut().enumerate() {
+			if !layer.is_writable() {
+				continue;
+			}
+			if let Some(repo) = repos.find_repo(repo_folder.to_string()) {
+				repo.remotes = match index == owner {
+					true => remotes.clone(),
+					false => BTreeMap::new(),
+				};
+			}
+		}
+		self.remove_empty_overlay_entry(repo_folder);
+		Ok(())
+	}
+
+	/// Drop the overlay's entry for a repo once it no longer adds anything to the repo's entry in
+	/// another layer.
+	fn remove_empty_overlay_entry(&mut self, repo_folder: &str) {
+		let in_other_layer = self.layers.iter().any(|(layer, repos)| {
+			*layer != Layer::Local && repos.repo_index(repo_folder.to_string()).is_some()
+		});
+		let Some((_, local)) = self
+			.layers
+			.iter_mut()
+			.find(|(layer, _)| *layer == Layer::Local)
+		else {
+			return;
+		};
+		let empty = local
+			.find_repo(repo_folder.to_string())
+			.is_some_and(|repo| repo.tags.is_empty() && repo.remotes.is_empty());
+		if empty && in_other_layer {
+			local.remove(vec![repo_folder.to_string()]);
+		}
+	}
+
+	fn writable_entries_mut(&mut self, repo_folder: &str) -> Vec<&mut Repo> {
+		self.layers
+			.iter_mut()
+			.filter(|(layer, _)| layer.is_writable())
+			.filter_map(|(_, repos)| repos.find_repo(repo_folder.to_string()))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Repos holding a single repo, with an origin remote if `origin` is given.
+	fn repos(path: &str, tags: &[&str], origin: Option<&str>) -> Repos {
+		let remotes = origin
+			.map(|url| {
+				let remote = Remote {
+					name: "origin".to_string(),
+					url: url.to_string(),
+				};
+				("origin".to_string(), remote)
+			})
+			.into_iter()
+			.collect();
+		let tags = tags.iter().map(|tag| tag.to_string()).collect();
+		let mut repos = Repos::new();
+		repos.add_with_tags_and_remotes(path.to_string(), tags, remotes);
+		repos
+	}
+
+	#[test]
+	fn merges_tags_and_remotes_across_layers() {
+		let layers = Layers::new(vec![
+			(
+				Layer::Shared,
+				repos("api", &["team"], Some("git://team/api")),
+			),
+			(
+				Layer::Include("../extra.toml".to_string()),
+				repos("tools", &["extra"], None),
+			),
+			(Layer::Local, repos("api", &["mine"], Some("git://me/api"))),
+		]);
+
+		let merged = layers.merged().into_vec();
+		assert_eq!(2, merged.len());
+		assert_eq!(vec!["mine", "team"], merged[0].tags);
+		assert_eq!("git://me/api", merged[0].remotes["origin"].url);
+		assert_eq!("tools", merged[1].path);
+
+		let origins = layers.tag_origins();
+		assert_eq!(Layer::Local, origins["api"]["mine"]);
+		assert_eq!(Layer::Shared, origins["api"]["team"]);
+		assert_eq!(
+			Layer::Include("../extra.toml".to_string()),
+			origins["tools"]["extra"]
+		);
+	}
+
+	#[test]
+	fn tags_go_to_the_chosen_layer_and_included_ones_stay() {
+		let mut layers = Layers::new(vec![
+			(Layer::Shared, repos("api", &[], None)),
+			(
+				Layer::Include("extra.toml".to_string()),
+				repos("tools", &["extra"], None),
+			),
+		]);
+
+		layers
+			.add_tag(
+				&Layer::Local,
+				"mine",
+				vec!["api".to_string(), "tools".to_string()],
+			)
+			.unwrap();
+		let local = layers.get_mut(&Layer::Local).clone().into_vec();
+		assert_eq!(
+			vec!["api", "tools"],
+			local.iter().map(|repo| &repo.path).collect::<Vec<_>>()
+		);
+		assert!(local.iter().all(|repo| repo.tags == vec!["mine"]));
+
+		let error = layers
+			.remove_tag("extra", vec!["tools".to_string()])
+			.expect_err("included tag removed");
+		assert_eq!(
+			"Tag 'extra' on tools comes from extra.toml, which gitopolis doesn't change",
+			error.message()
+		);
+	}
+}
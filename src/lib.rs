@@ -1,8 +1,21 @@
 extern crate core;
 
+pub mod aliases;
+pub mod doctor;
 pub mod exec;
+pub mod exec_log;
 pub mod git;
 pub mod gitopolis;
+pub mod import;
+pub mod layers;
+pub mod observer;
+pub mod output;
+pub mod parallel;
 pub mod repos;
+pub mod scan;
+pub mod state_file;
+pub mod state_filter;
 pub mod storage;
+pub mod table;
 pub mod tag_filter;
+pub mod ui;
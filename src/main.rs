@@ -1,12 +1,40 @@
-use clap::{Parser, Subcommand};
-use gitopolis::exec::exec;
-use gitopolis::git::GitImpl;
-use gitopolis::gitopolis::Gitopolis;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::engine::{ArgValueCandidates, ArgValueCompleter, CompletionCandidate};
+use clap_complete::env::Shells;
+use clap_complete::CompleteEnv;
+use gitopolis::aliases::Alias;
+use gitopolis::doctor::{fix, format_problems, Fix};
+use gitopolis::exec::{format_args_for_display, ExecOptions};
+use gitopolis::exec_log::LogDirObserver;
+use gitopolis::git::{FetchOptions, GitImpl};
+use gitopolis::gitopolis::{
+	extract_repo_name_from_url, CloneOutcome, Gitopolis, GitopolisError, RepoPullEntry,
+	RepoStatusEntry, RepoSyncEntry,
+};
+use gitopolis::import::{fetch_repos, ImportOptions, Source};
+use gitopolis::layers::Layer;
+use gitopolis::observer::Observer;
+use gitopolis::output::{
+	format_branch_outcome, format_clone_summary, format_import_outcomes, print_json,
+	print_ndjson_line, print_records, ConsoleObserver, GroupRepos, OutputFormat,
+};
 use gitopolis::repos::Repo;
-use gitopolis::storage::StorageImpl;
+use gitopolis::scan::find_git_repos;
+use gitopolis::state_filter::StateFilter;
+use gitopolis::storage::{find_state_file, StorageImpl};
+use gitopolis::table::{format_branches, format_pull, format_status};
 use gitopolis::tag_filter::TagFilter;
+use gitopolis::ui;
 use log::LevelFilter;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
 use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 /// A CLI tool for managing multiple git repositories
 /// License: A-GPL v3.0
@@ -14,55 +42,145 @@ use std::io::Write;
 #[derive(Parser)]
 #[clap(author, version, subcommand_required = true, verbatim_doc_comment)]
 struct Args {
+	/// Path to the state file. By default the nearest .gitopolis.toml in the current folder or any parent folder is used.
+	#[arg(long, global = true, env = "GITOPOLIS_CONFIG", value_name = "PATH")]
+	config: Option<PathBuf>,
+	/// Keep a copy of the previous state file as .gitopolis.toml.bak each time it's changed.
+	#[arg(long, global = true, env = "GITOPOLIS_BACKUP")]
+	backup: bool,
+	/// Save new repos and tags to the personal overlay (.gitopolis.local.toml next to the state file), creating it if needed.
+	#[arg(long, global = true, conflicts_with = "shared")]
+	local: bool,
+	/// Save tags to the shared state file even when there's a personal overlay.
+	#[arg(long, global = true)]
+	shared: bool,
 	#[clap(subcommand)]
 	command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-	/// Add one or more git repos to manage.
+	/// Add one or more git repos to manage. Use --scan to find and add all the repos in a folder tree.
 	Add {
-		#[clap(required = true)]
+		#[clap(
+			required_unless_present = "scan",
+			conflicts_with = "scan",
+			value_hint = ValueHint::DirPath
+		)]
 		repo_folders: Vec<String>,
+		/// Search this folder (default: current folder) and all its subfolders for git repos and add them all.
+		#[arg(
+			long,
+			value_name = "DIR",
+			num_args = 0..=1,
+			default_missing_value = ".",
+			value_hint = ValueHint::DirPath
+		)]
+		scan: Option<String>,
+		/// How many levels of subfolders to search when scanning.
+		#[arg(long, value_name = "N", requires = "scan")]
+		max_depth: Option<usize>,
+		/// When scanning, also look for repos inside other repos (e.g. vendored or nested checkouts).
+		#[arg(long, requires = "scan")]
+		nested: bool,
+		/// Tag all the added repos with these tags. Supports comma-separated tag lists (e.g., "tag1,tag2").
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+		tag: Vec<String>,
 	},
 	/// Remove one or more git repos from gitopolis's list. Leaves actual repo on filesystem alone.
 	Remove {
-		#[clap(required = true)]
+		#[clap(required = true, add = ArgValueCandidates::new(complete_repo_folders))]
 		repo_folders: Vec<String>,
 	},
 	/// Show list of repos gitopolis knows about. Use "long" to see tags and urls (tab separated format).
 	List {
 		/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
 		/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
-		#[arg(short, long)]
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
 		tag: Vec<String>,
+		/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION")]
+		filter: Option<String>,
+		#[command(flatten)]
+		state: StateArgs,
 		#[clap(short, long)]
 		long: bool,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
 	},
 	/// Run any shell command. E.g. `gitopolis exec -- git pull`. Double-dash separator indicates end of gitopolis's arguments and prevents arguments to your commands being interpreted by gitopolis.
 	Exec {
 		/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
 		/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
-		#[arg(short, long)]
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
 		tag: Vec<String>,
+		/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION")]
+		filter: Option<String>,
+		#[command(flatten)]
+		state: StateArgs,
 		#[arg(long)]
 		oneline: bool,
+		/// Print each distinct output once, with the repos that gave it, most common first, so the odd ones out stand out. Each repo's output is put on one line as for --oneline.
+		#[arg(long, conflicts_with = "output")]
+		group: bool,
+		/// With --group, show how many repos gave each output instead of listing them.
+		#[arg(long, requires = "group")]
+		count: bool,
+		/// Run the command in up to this many repos at once. Output from each repo is held back until it finishes so it isn't interleaved.
+		#[arg(short = 'j', long, value_name = "N", default_value_t = 1)]
+		parallel: usize,
+		/// Kill the command in a repo, and anything it started, if it's still running after this long, e.g. 30s, 5m or 1h30m. Timed out repos are reported and make the exit code non-zero.
+		#[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+		timeout: Option<Duration>,
+		/// Also write each repo's output, with the command, result and duration, to <DIR>/<repo path>.log, and a summary of all the repos to <DIR>/index.tsv.
+		#[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+		log_dir: Option<PathBuf>,
+		/// Output format. Structured formats capture each repo's output and write one record per repo with its path, tags, command, exit code, duration, stdout and stderr.
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with = "oneline")]
+		output: OutputFormat,
 		exec_args: Vec<String>,
 	},
+	/// Run a named command from the [aliases] section of the config in each repo, with the alias's tag filter, oneline mode and parallelism unless they're given here. Arguments after the alias name fill in its {1}, {2}... placeholders, or are added to the end of the command if it has none.
+	Run {
+		/// Name of the alias, see `gitopolis aliases`
+		#[arg(add = ArgValueCandidates::new(complete_aliases))]
+		alias: String,
+		/// Arguments for the alias's command
+		#[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+		args: Vec<String>,
+		#[command(flatten)]
+		options: RunOptions,
+	},
+	/// Print a script that sets up tab completion for your shell, including repo folders, tags and aliases from the config. E.g. add `source <(gitopolis completions bash)` to ~/.bashrc
+	Completions {
+		#[arg(value_enum)]
+		shell: CompletionShell,
+	},
+	/// List the named commands in the [aliases] section of the config, for use with `gitopolis run`.
+	Aliases {
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
+	},
 	/// Add/remove repo tags. Use tags to organise repos and allow running commands against subsets of the repo list. Supports comma-separated tag lists (e.g., "tag1,tag2,tag3").
 	Tag {
 		/// Remove this tag from these repo_folders.
 		#[clap(short, long)]
 		remove: bool,
-		#[clap(required = true)]
+		#[clap(required = true, add = ArgValueCompleter::new(complete_tags))]
 		tag: String,
-		#[clap(required = true)]
+		#[clap(required = true, add = ArgValueCandidates::new(complete_repo_folders))]
 		repo_folders: Vec<String>,
 	},
 	/// List known tags. Use "long" to list repos per tag.
 	Tags {
 		#[clap(short, long)]
 		long: bool,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
 	},
 	/// Clone repository from URL and add to gitopolis, or clone all configured repos from .gitopolis.toml.
 	/// This command behaves in two very different ways depending on whether a remote url was provided:
@@ -78,8 +196,60 @@ enum Commands {
 		/// When cloning without URL from existing config,
 		/// filters repos to clone from configuration by tags; comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar),
 		/// multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
-		#[arg(short, long)]
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
 		tag: Vec<String>,
+		/// When cloning without URL from existing config, filter repos to clone by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION", conflicts_with = "url")]
+		filter: Option<String>,
+		/// When cloning without URL from existing config, clone up to this many repos at once. Output from each repo is held back until it finishes so it isn't interleaved.
+		#[arg(
+			short = 'j',
+			long,
+			value_name = "N",
+			default_value_t = 1,
+			conflicts_with = "url"
+		)]
+		parallel: usize,
+	},
+	/// Add the repos of a GitHub organisation or user, GitLab group or Azure DevOps project to the config, with an origin remote and tags for their visibility, language, topics and whether they're archived. Repos already in the config just get any tags they're missing. Run clone afterwards to get them.
+	Import {
+		#[clap(subcommand)]
+		source: ImportSource,
+	},
+	/// Fetch from the remotes of each repo, authenticating with ssh-agent or git credential helpers like git does, then summarise the refs updated and any repos that failed.
+	Fetch {
+		/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
+		/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+		tag: Vec<String>,
+		/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION")]
+		filter: Option<String>,
+		/// Remove remote-tracking branches that no longer exist on the remote
+		#[arg(long)]
+		prune: bool,
+		/// Fetch from every remote of each repo instead of just origin (or its first remote if it has no origin)
+		#[arg(long)]
+		all_remotes: bool,
+		/// Fetch up to this many repos at once. Output from each repo is held back until it finishes so it isn't interleaved.
+		#[arg(short = 'j', long, value_name = "N", default_value_t = 4)]
+		parallel: usize,
+	},
+	/// Fast-forward the current branch of each repo to its upstream. Repos with uncommitted changes, a detached HEAD, no upstream or local commits of their own are left alone rather than merged; the outcome for each repo is shown in a table.
+	Pull {
+		/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
+		/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+		tag: Vec<String>,
+		/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION")]
+		filter: Option<String>,
+		/// Pull up to this many repos at once.
+		#[arg(short = 'j', long, value_name = "N", default_value_t = 4)]
+		parallel: usize,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
 	},
 	/// Sync remotes between git repositories and .gitopolis.toml configuration
 	Sync {
@@ -91,19 +261,255 @@ enum Commands {
 		write_remotes: bool,
 		/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
 		/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
-		#[arg(short, long)]
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+		tag: Vec<String>,
+		/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION")]
+		filter: Option<String>,
+	},
+	/// Show a summary of the git state of each repo: branch, commits ahead/behind upstream, staged/unstaged/untracked file counts, stashes and any in-progress merge/rebase.
+	Status {
+		/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
+		/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
+		#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
 		tag: Vec<String>,
+		/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+		#[arg(long, value_name = "EXPRESSION")]
+		filter: Option<String>,
+		#[command(flatten)]
+		state: StateArgs,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
+	},
+	/// Open a full-screen view of the repos with their tags and live status. Type a tag expression to filter, select repos and fetch, pull or run a command in them with each repo's output shown as it runs.
+	Ui {
+		#[command(flatten)]
+		repos: RepoFilterArgs,
+		/// Fetch, pull or run commands in up to this many repos at once.
+		#[arg(short = 'j', long, value_name = "N", default_value_t = 4)]
+		parallel: usize,
 	},
 	/// Show detailed information about a repository including tags and remotes
 	Show {
-		#[clap(required = true)]
+		#[clap(required = true, add = ArgValueCandidates::new(complete_repo_folders))]
 		repo_folder: String,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
 	},
 	/// Move a repository to a new location, updating gitopolis configuration
 	Move {
 		#[clap(subcommand)]
 		entity: MoveEntity,
 	},
+	/// Create, switch to, delete or list branches across repos
+	Branch {
+		#[clap(subcommand)]
+		action: BranchAction,
+	},
+	/// Check the config against what's on disk: repos whose folders are missing or aren't git repos, repos whose remotes differ from the config, and git repos next to the config that aren't in it. Exits with an error if any problems are left.
+	#[command(alias = "reconcile")]
+	Doctor {
+		/// Fix problems found. "prune" removes missing repos from the config, "clone" clones them again instead, "add" adds unmanaged repos. Can be given more than once.
+		#[arg(long, value_enum, value_name = "FIX", conflicts_with = "output")]
+		fix: Vec<Fix>,
+		/// How many levels of subfolders to search for unmanaged repos.
+		#[arg(long, value_name = "N")]
+		max_depth: Option<usize>,
+		/// When fixing with clone, clone up to this many repos at once.
+		#[arg(short = 'j', long, value_name = "N", default_value_t = 1)]
+		parallel: usize,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
+	},
+}
+
+#[derive(Subcommand)]
+enum BranchAction {
+	/// Create a branch at the current commit of each repo and switch to it. Repos that already have the branch are skipped.
+	Create {
+		name: String,
+		#[command(flatten)]
+		repos: RepoFilterArgs,
+	},
+	/// Switch each repo to a branch, creating it from a remote branch of the same name if needed. Repos that don't have the branch are skipped.
+	Switch {
+		name: String,
+		#[command(flatten)]
+		repos: RepoFilterArgs,
+		/// Stash uncommitted changes before switching instead of skipping repos that have them
+		#[arg(long)]
+		stash: bool,
+	},
+	/// Delete a local branch from each repo that has it, unless it's the current branch.
+	Delete {
+		name: String,
+		#[command(flatten)]
+		repos: RepoFilterArgs,
+		/// Delete the branch even if it has commits that aren't in its upstream or the current branch
+		#[arg(long)]
+		force: bool,
+	},
+	/// Show which repos have which local and remote branches
+	List {
+		/// Only show branches with these names. Supports * wildcards.
+		names: Vec<String>,
+		#[command(flatten)]
+		repos: RepoFilterArgs,
+		/// Output format
+		#[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+		output: OutputFormat,
+	},
+}
+
+#[derive(Subcommand)]
+enum ImportSource {
+	/// Import the repos of a GitHub organisation or user
+	Github {
+		/// Organisation to import the repos of
+		#[arg(long, required_unless_present = "user", conflicts_with = "user")]
+		org: Option<String>,
+		/// User to import the repos of instead of an organisation
+		#[arg(long)]
+		user: Option<String>,
+		/// Personal access token, needed for private repos
+		#[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+		token: Option<String>,
+		#[command(flatten)]
+		options: ImportArgs,
+	},
+	/// Import the projects of a GitLab group and its subgroups, which become subfolders
+	Gitlab {
+		/// Group to import the projects of, e.g. "my-team" or "my-team/backend"
+		#[arg(long)]
+		group: String,
+		/// Personal access token, needed for private projects
+		#[arg(long, env = "GITLAB_TOKEN", hide_env_values = true)]
+		token: Option<String>,
+		#[command(flatten)]
+		options: ImportArgs,
+	},
+	/// Import the repos of an Azure DevOps project
+	Azure {
+		/// Organisation the project is in
+		#[arg(long)]
+		org: String,
+		/// Project to import the repos of
+		#[arg(long)]
+		project: String,
+		/// Personal access token with code read access
+		#[arg(long, env = "AZURE_DEVOPS_TOKEN", hide_env_values = true)]
+		token: Option<String>,
+		#[command(flatten)]
+		options: ImportArgs,
+	},
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum CompletionShell {
+	Bash,
+	Elvish,
+	Fish,
+	Powershell,
+	Zsh,
+}
+
+/// Exec options for `run`, each replacing the alias's own setting when given.
+#[derive(clap::Args)]
+struct RunOptions {
+	/// Filter by tags instead of the alias's tag filter. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
+	/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
+	#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+	tag: Vec<String>,
+	/// Filter by a tag expression instead of the alias's one, e.g. "(rust or go) and not archived".
+	#[arg(long, value_name = "EXPRESSION")]
+	filter: Option<String>,
+	#[command(flatten)]
+	state: StateArgs,
+	#[arg(long)]
+	oneline: bool,
+	/// Print each distinct output once with the repos that gave it, as for exec.
+	#[arg(long, conflicts_with = "output")]
+	group: bool,
+	/// With --group, show how many repos gave each output instead of listing them.
+	#[arg(long, requires = "group")]
+	count: bool,
+	/// Run the command in up to this many repos at once instead of the alias's setting (default 1).
+	#[arg(short = 'j', long, value_name = "N")]
+	parallel: Option<usize>,
+	/// Kill the command in a repo if it's still running after this long, as for exec.
+	#[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+	timeout: Option<Duration>,
+	/// Also write each repo's output to a log file in this folder, as for exec.
+	#[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+	log_dir: Option<PathBuf>,
+	/// Output format, as for exec.
+	#[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with = "oneline")]
+	output: OutputFormat,
+}
+
+/// Options shared by all the import sources.
+#[derive(clap::Args)]
+struct ImportArgs {
+	/// Base URL of the API, for self-hosted servers such as GitHub Enterprise (e.g. https://github.example.com/api/v3). Defaults to the public service.
+	#[arg(long, value_name = "URL")]
+	api_url: Option<String>,
+	/// Use https URLs for the origin remote instead of ssh ones
+	#[arg(long)]
+	https: bool,
+	/// Also add these tags to every imported repo
+	#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+	tag: Vec<String>,
+}
+
+/// Which repos a branch or ui command works on.
+#[derive(clap::Args)]
+struct RepoFilterArgs {
+	/// Filter by tags. Comma-separated tags use AND logic (e.g., "foo,bar" = foo AND bar).
+	/// Multiple --tag flags use OR logic (e.g., "--tag foo,bar --tag baz" = (foo AND bar) OR baz).
+	#[arg(short, long, add = ArgValueCompleter::new(complete_tags))]
+	tag: Vec<String>,
+	/// Filter by a tag expression, e.g. "(rust or go) and not archived". Supports and/or/not (or &, |, !), parentheses and * wildcards in tag names. Combined with any --tag filter using AND logic.
+	#[arg(long, value_name = "EXPRESSION")]
+	filter: Option<String>,
+}
+
+/// Which repos a command works on by their git state, combined with any tag filter.
+#[derive(clap::Args)]
+struct StateArgs {
+	/// Only repos with uncommitted changes to tracked files or a merge/rebase in progress. Untracked files don't count.
+	#[arg(long, conflicts_with = "clean")]
+	dirty: bool,
+	/// Only repos without uncommitted changes to tracked files or a merge/rebase in progress.
+	#[arg(long)]
+	clean: bool,
+	/// Only repos whose branch has commits that aren't in its upstream.
+	#[arg(long)]
+	ahead: bool,
+	/// Only repos whose branch is behind its upstream, as of the last fetch.
+	#[arg(long)]
+	behind: bool,
+	/// Only repos on this branch. Supports * wildcards, e.g. "feature/*".
+	#[arg(long, value_name = "BRANCH")]
+	on_branch: Option<String>,
+	/// Only repos on a branch other than their default one (where origin/HEAD points, else main or master), or with a detached HEAD.
+	#[arg(long)]
+	not_on_default_branch: bool,
+}
+
+impl From<&StateArgs> for StateFilter {
+	fn from(args: &StateArgs) -> Self {
+		StateFilter {
+			dirty: args.dirty,
+			clean: args.clean,
+			ahead: args.ahead,
+			behind: args.behind,
+			on_branch: args.on_branch.clone(),
+			not_on_default_branch: args.not_on_default_branch,
+		}
+	}
 }
 
 #[derive(Subcommand)]
@@ -111,6 +517,7 @@ enum MoveEntity {
 	/// Move a repository to a new location
 	Repo {
 		/// Current path of the repository
+		#[arg(add = ArgValueCandidates::new(complete_repo_folders))]
 		old_path: String,
 		/// New path for the repository
 		new_path: String,
@@ -118,60 +525,107 @@ enum MoveEntity {
 }
 
 fn main() {
+	// answers the shell's requests for completions, set up by the `completions` command
+	CompleteEnv::with_factory(Args::command)
+		.var(COMPLETE_VAR)
+		.complete();
+
 	env_logger::builder()
 		.format(|buf, record| writeln!(buf, "{}", record.args())) // turn off log decorations https://docs.rs/env_logger/0.9.0/env_logger/#using-a-custom-format
 		.filter(None, LevelFilter::Info) // turn on log output
 		.init();
 
-	match &Args::parse_from(wild::args()).command {
-		Some(Commands::Add { repo_folders }) => add(repo_folders.to_owned()),
+	let args = Args::parse_from(wild::args());
+	let location = &Location::find(&args);
+	match &args.command {
+		Some(Commands::Add {
+			repo_folders,
+			scan: None,
+			tag: tag_args,
+			..
+		}) => add(location, repo_folders, tag_args),
+		Some(Commands::Add {
+			scan: Some(scan_folder),
+			max_depth,
+			nested,
+			tag: tag_args,
+			..
+		}) => add_scan(location, scan_folder, *max_depth, *nested, tag_args),
 		Some(Commands::Remove { repo_folders }) => {
-			init_gitopolis()
-				.remove(repo_folders)
-				.expect("Failed to remove repository");
+			init_gitopolis(location)
+				.remove(&location.repo_folders(repo_folders))
+				.unwrap_or_else(exit_with_error);
 		}
 		Some(Commands::List {
 			tag: tag_args,
+			filter,
+			state,
 			long,
+			output,
 		}) => {
-			let filter = TagFilter::from_cli_args(tag_args);
-			list(
-				init_gitopolis()
-					.list(&filter)
-					.expect("Failed to list repositories"),
-				*long,
-			)
+			let filter = tag_filter(tag_args, filter);
+			let gitopolis = init_gitopolis(location);
+			let repos = gitopolis
+				.select(&filter, &state.into())
+				.unwrap_or_else(exit_with_error);
+			let tag_origins = match long {
+				true => gitopolis.tag_origins().unwrap_or_else(exit_with_error),
+				false => BTreeMap::new(),
+			};
+			list(repos, &tag_origins, *long, *output)
 		}
 		Some(Commands::Clone {
 			url,
 			target_dir,
 			tag: tag_args,
-		}) => clone(url, target_dir, tag_args),
+			filter,
+			parallel,
+		}) => clone(location, url, target_dir, tag_args, filter, *parallel),
 		Some(Commands::Exec {
 			tag: tag_args,
+			filter,
+			state,
 			oneline,
+			group,
+			count,
+			parallel,
+			timeout,
+			log_dir,
+			output,
 			exec_args,
-		}) => {
-			let filter = TagFilter::from_cli_args(tag_args);
-			exec(
-				exec_args.to_owned(),
-				init_gitopolis()
-					.list(&filter)
-					.expect("Failed to list repositories for exec"),
-				*oneline,
-			);
-		}
+		}) => exec(
+			location,
+			exec_args,
+			&tag_filter(tag_args, filter),
+			&state.into(),
+			console(exec_args, *oneline, *group, *count, *output),
+			&ExecOptions {
+				timeout: *timeout,
+				cancel_from_terminal: true,
+				..Default::default()
+			},
+			*parallel,
+			log_dir,
+		),
+		Some(Commands::Run {
+			alias,
+			args,
+			options,
+		}) => run(location, alias, args, options),
+		Some(Commands::Aliases { output }) => list_aliases(location, *output),
+		Some(Commands::Completions { shell }) => completions(*shell),
 		Some(Commands::Tag {
 			tag: tag_name,
 			repo_folders,
 			remove,
 		}) => {
 			let tags: Vec<&str> = tag_name.split(',').map(|s| s.trim()).collect();
+			let repo_folders = &location.repo_folders(repo_folders);
 			for tag in tags {
 				let result = if *remove {
-					init_gitopolis().remove_tag(tag, repo_folders)
+					init_gitopolis(location).remove_tag(tag, repo_folders)
 				} else {
-					init_gitopolis().add_tag(tag, repo_folders)
+					init_gitopolis(location).add_tag(tag, repo_folders)
 				};
 				if let Err(error) = result {
 					eprintln!("Error: {}", error.message());
@@ -179,32 +633,97 @@ fn main() {
 				}
 			}
 		}
-		Some(Commands::Tags { long }) => list_tags(*long),
+		Some(Commands::Tags { long, output }) => list_tags(location, *long, *output),
+		Some(Commands::Fetch {
+			tag: tag_args,
+			filter,
+			prune,
+			all_remotes,
+			parallel,
+		}) => {
+			let filter = tag_filter(tag_args, filter);
+			let gitopolis = init_gitopolis(location);
+			let repos = gitopolis.list(&filter).unwrap_or_else(exit_with_error);
+			let options = FetchOptions {
+				prune: *prune,
+				all_remotes: *all_remotes,
+			};
+			let summary = gitopolis.fetch(repos, &options, *parallel).summary();
+			println!(
+				"{} fetched, {} failed, {} refs updated",
+				summary.fetched, summary.failed, summary.updated_refs
+			);
+			if summary.failed > 0 {
+				eprintln!("{} repos failed to fetch", summary.failed);
+				std::process::exit(1);
+			}
+		}
+		Some(Commands::Pull {
+			tag: tag_args,
+			filter,
+			parallel,
+			output,
+		}) => {
+			let filter = tag_filter(tag_args, filter);
+			let gitopolis = init_gitopolis(location);
+			let repos = gitopolis.list(&filter).unwrap_or_else(exit_with_error);
+			pull(gitopolis.pull(repos, *parallel), *output);
+		}
 		Some(Commands::Sync {
 			read_remotes,
 			write_remotes,
 			tag: tag_args,
+			filter,
 		}) => {
-			let filter = TagFilter::from_cli_args(tag_args);
+			let filter = tag_filter(tag_args, filter);
 			if *read_remotes {
-				init_gitopolis()
+				let entries = init_gitopolis(location)
 					.sync_read_remotes(&filter)
-					.expect("Sync read failed");
+					.unwrap_or_else(exit_with_error);
+				exit_if_sync_failed(&entries);
 			} else if *write_remotes {
-				init_gitopolis()
+				let entries = init_gitopolis(location)
 					.sync_write_remotes(&filter)
-					.expect("Sync write failed");
+					.unwrap_or_else(exit_with_error);
+				exit_if_sync_failed(&entries);
 			} else {
 				eprintln!("Error: Must specify either --read-remotes or --write-remotes");
 				std::process::exit(1);
 			}
 		}
-		Some(Commands::Show { repo_folder }) => {
-			show(repo_folder);
+		Some(Commands::Ui { repos, parallel }) => {
+			let filter = tag_filter(&repos.tag, &repos.filter);
+			if let Err(error) = ui::run(init_gitopolis(location), filter, *parallel) {
+				eprintln!("Error: {}", error.message());
+				std::process::exit(1);
+			}
+		}
+		Some(Commands::Status {
+			tag: tag_args,
+			filter,
+			state,
+			output,
+		}) => {
+			let filter = tag_filter(tag_args, filter);
+			status(
+				init_gitopolis(location)
+					.status(&filter, &state.into())
+					.unwrap_or_else(exit_with_error),
+				*output,
+			);
+		}
+		Some(Commands::Show {
+			repo_folder,
+			output,
+		}) => {
+			show(location, repo_folder, *output);
 		}
 		Some(Commands::Move { entity }) => match entity {
 			MoveEntity::Repo { old_path, new_path } => {
-				match init_gitopolis().move_repo(old_path, new_path) {
+				match init_gitopolis(location).move_repo(
+					&location.repo_folder(old_path),
+					&location.repo_folder(new_path),
+				) {
 					Ok(_) => {
 						eprintln!("Moved {} to {}", old_path, new_path);
 					}
@@ -215,12 +734,408 @@ fn main() {
 				}
 			}
 		},
+		Some(Commands::Import { source }) => import(location, source),
+		Some(Commands::Branch { action }) => branch(location, action),
+		Some(Commands::Doctor {
+			fix,
+			max_depth,
+			parallel,
+			output,
+		}) => doctor(location, fix, *max_depth, *parallel, *output),
 		None => {
 			panic!("no command") // this doesn't happen because help shows instead
 		}
 	}
 }
 
+/// Console output for exec in the layout chosen on the command line.
+fn console(
+	exec_args: &[String],
+	oneline: bool,
+	group: bool,
+	count: bool,
+	output: OutputFormat,
+) -> ConsoleObserver {
+	let console = ConsoleObserver::exec(exec_args.to_owned(), oneline, output);
+	match (group, count) {
+		(false, _) => console,
+		(true, false) => console.with_grouping(GroupRepos::List),
+		(true, true) => console.with_grouping(GroupRepos::Count),
+	}
+}
+
+#[allow(clippy::too_many_arguments)]
+fn exec(
+	location: &Location,
+	exec_args: &[String],
+	filter: &TagFilter,
+	state: &StateFilter,
+	console: ConsoleObserver,
+	options: &ExecOptions,
+	parallel: usize,
+	log_dir: &Option<PathBuf>,
+) {
+	let mut observer: Box<dyn Observer> = Box::new(console);
+	if let Some(log_dir) = log_dir {
+		if let Err(error) = fs::create_dir_all(log_dir) {
+			eprintln!(
+				"Error: Couldn't create log folder {}: {error}",
+				log_dir.display()
+			);
+			std::process::exit(1);
+		}
+		observer = Box::new(LogDirObserver::new(
+			log_dir.clone(),
+			exec_args.to_owned(),
+			observer,
+		));
+	}
+	let gitopolis = init_gitopolis(location).with_observer(observer);
+	let repos = gitopolis
+		.select(filter, state)
+		.unwrap_or_else(exit_with_error);
+	let cancel = options.cancel.clone();
+	ctrlc::set_handler(move || {
+		// the first Ctrl-C stops the commands and reports what finished, a second gives up waiting
+		if cancel.swap(true, Ordering::SeqCst) {
+			std::process::exit(130);
+		}
+	})
+	.expect("Failed to set Ctrl-C handler");
+	let report = gitopolis.exec(exec_args, repos, options, parallel);
+	let error_count = report.error_count();
+	let timed_out_count = report.timed_out_count();
+	let skipped_count = report.skipped_count();
+	let cancelled_count = report.cancelled_count();
+	if error_count > 0 {
+		eprintln!("{error_count} commands exited with non-zero status code");
+	}
+	if timed_out_count > 0 {
+		eprintln!("{timed_out_count} commands timed out");
+	}
+	if skipped_count > 0 {
+		eprintln!("{skipped_count} repos skipped");
+	}
+	if cancelled_count > 0 {
+		eprintln!("Cancelled, {cancelled_count} repos not finished");
+		std::process::exit(130);
+	}
+	if error_count > 0 || timed_out_count > 0 || skipped_count > 0 {
+		std::process::exit(1);
+	}
+}
+
+fn run(location: &Location, name: &str, args: &[String], options: &RunOptions) {
+	let aliases = init_gitopolis(location).aliases().unwrap_or_else(|error| {
+		eprintln!("Error: {}", error.message());
+		std::process::exit(1);
+	});
+	let Some(alias) = aliases.get(name) else {
+		eprintln!(
+			"Error: No alias '{name}' in {}, see `gitopolis aliases`",
+			location.state_file.display()
+		);
+		std::process::exit(1);
+	};
+	let exec_args = alias.expand(name, args).unwrap_or_else(|error| {
+		eprintln!("Error: {}", error.message());
+		std::process::exit(1);
+	});
+	let filter = match options.tag.is_empty() && options.filter.is_none() {
+		true => tag_filter(&alias.tag, &alias.filter),
+		false => tag_filter(&options.tag, &options.filter),
+	};
+	let output = options.output;
+	let oneline = (options.oneline || alias.oneline) && output == OutputFormat::Text;
+	let parallel = options.parallel.or(alias.parallel).unwrap_or(1);
+	exec(
+		location,
+		&exec_args,
+		&filter,
+		&(&options.state).into(),
+		console(&exec_args, oneline, options.group, options.count, output),
+		&ExecOptions {
+			timeout: options.timeout,
+			cancel_from_terminal: true,
+			..Default::default()
+		},
+		parallel,
+		&options.log_dir,
+	);
+}
+
+fn list_aliases(location: &Location, output: OutputFormat) {
+	let aliases = init_gitopolis(location)
+		.aliases()
+		.unwrap_or_else(exit_with_error);
+	if output != OutputFormat::Text {
+		let records: Vec<AliasRecord> = aliases
+			.into_iter()
+			.map(|(name, alias)| AliasRecord { name, alias })
+			.collect();
+		print_records(output, &records);
+		return;
+	}
+	for (name, alias) in aliases {
+		let mut options = vec![];
+		options.extend(alias.tag.iter().map(|tag| format!("--tag {tag}")));
+		options.extend(alias.filter.map(|filter| format!("--filter '{filter}'")));
+		options.extend(alias.oneline.then(|| "--oneline".to_string()));
+		options.extend(alias.parallel.map(|parallel| format!("-j {parallel}")));
+		let mut line = format!("{name}\t{}", format_args_for_display(&alias.command));
+		if !options.is_empty() {
+			line.push_str(&format!("\t{}", options.join(" ")));
+		}
+		if let Some(description) = alias.description {
+			line.push_str(&format!("\t# {description}"));
+		}
+		println!("{line}");
+	}
+}
+
+#[derive(Serialize)]
+struct AliasRecord {
+	name: String,
+	#[serde(flatten)]
+	alias: Alias,
+}
+
+fn completions(shell: CompletionShell) {
+	let name = shell
+		.to_possible_value()
+		.expect("no skipped shells")
+		.get_name()
+		.to_string();
+	let shells = Shells::builtins();
+	let completer = shells
+		.completer(&name)
+		.expect("shell supported by clap_complete");
+	// call back into this executable, by its full path if it wasn't found on the PATH
+	let program = PathBuf::from(env::args_os().next().unwrap_or_else(|| "gitopolis".into()));
+	let program = match program.components().count() > 1 {
+		true => env::current_dir().unwrap_or_default().join(program),
+		false => program,
+	};
+	completer
+		.write_registration(
+			COMPLETE_VAR,
+			"gitopolis",
+			"gitopolis",
+			&program.to_string_lossy(),
+			&mut std::io::stdout(),
+		)
+		.expect("Failed to write completion script");
+}
+
+/// The config completions are read from: the one a command run here would use, apart from any
+/// `--config` option, which isn't known until the command line is finished. None without one, as
+/// completing mustn't create it.
+fn completion_context() -> Option<(Location, Gitopolis)> {
+	let location = Location::with_config(env::var_os("GITOPOLIS_CONFIG").map(PathBuf::from));
+	if !location.state_file.exists() {
+		return None;
+	}
+	let gitopolis = init_gitopolis(&location);
+	Some((location, gitopolis))
+}
+
+fn complete_repo_folders() -> Vec<CompletionCandidate> {
+	let Some((location, gitopolis)) = completion_context() else {
+		return vec![];
+	};
+	gitopolis
+		.list(&TagFilter::all())
+		.unwrap_or_default()
+		.iter()
+		.map(|repo| CompletionCandidate::new(location.folder_from_here(&repo.path)))
+		.collect()
+}
+
+/// Complete the last tag of a comma-separated list, leaving out tags already in it.
+fn complete_tags(current: &OsStr) -> Vec<CompletionCandidate> {
+	let Some((_, gitopolis)) = completion_context() else {
+		return vec![];
+	};
+	let current = current.to_string_lossy();
+	let (chosen, partial) = match current.rfind(',') {
+		Some(comma) => current.split_at(comma + 1),
+		None => ("", current.as_ref()),
+	};
+	let already: Vec<&str> = chosen.split(',').map(str::trim).collect();
+	gitopolis
+		.tags()
+		.unwrap_or_default()
+		.into_iter()
+		.filter(|tag| tag.starts_with(partial.trim_start()) && !already.contains(&tag.as_str()))
+		.map(|tag| CompletionCandidate::new(format!("{chosen}{tag}")))
+		.collect()
+}
+
+fn complete_aliases() -> Vec<CompletionCandidate> {
+	let Some((_, gitopolis)) = completion_context() else {
+		return vec![];
+	};
+	gitopolis
+		.aliases()
+		.unwrap_or_default()
+		.into_iter()
+		.map(|(name, alias)| {
+			CompletionCandidate::new(name)
+				.help(alias.description.map(|description| description.into()))
+		})
+		.collect()
+}
+
+fn import(location: &Location, source: &ImportSource) {
+	let (source, token, args) = match source {
+		ImportSource::Github {
+			org: Some(org),
+			token,
+			options,
+			..
+		} => (Source::GitHubOrg { org: org.clone() }, token, options),
+		ImportSource::Github {
+			user,
+			token,
+			options,
+			..
+		} => (
+			Source::GitHubUser {
+				user: user.clone().unwrap_or_default(),
+			},
+			token,
+			options,
+		),
+		ImportSource::Gitlab {
+			group,
+			token,
+			options,
+		} => (
+			Source::GitLab {
+				group: group.clone(),
+			},
+			token,
+			options,
+		),
+		ImportSource::Azure {
+			org,
+			project,
+			token,
+			options,
+		} => (
+			Source::Azure {
+				organization: org.clone(),
+				project: project.clone(),
+			},
+			token,
+			options,
+		),
+	};
+	let options = ImportOptions {
+		api_url: args.api_url.clone(),
+		token: token.clone(),
+		https: args.https,
+		tags: flatten_tags(&args.tag)
+			.into_iter()
+			.filter(|tag| !tag.is_empty())
+			.collect(),
+	};
+	let repos = fetch_repos(&source, &options).unwrap_or_else(exit_with_error);
+	let outcomes = init_gitopolis(location)
+		.import(repos)
+		.unwrap_or_else(exit_with_error);
+	print!("{}", format_import_outcomes(&outcomes));
+}
+
+fn branch(location: &Location, action: &BranchAction) {
+	let gitopolis = init_gitopolis(location);
+	let list = |repos: &RepoFilterArgs| {
+		gitopolis
+			.list(&tag_filter(&repos.tag, &repos.filter))
+			.unwrap_or_else(exit_with_error)
+	};
+	let (name, entries) = match action {
+		BranchAction::Create { name, repos } => (name, gitopolis.create_branch(list(repos), name)),
+		BranchAction::Switch { name, repos, stash } => {
+			(name, gitopolis.switch_branch(list(repos), name, *stash))
+		}
+		BranchAction::Delete { name, repos, force } => {
+			(name, gitopolis.delete_branch(list(repos), name, *force))
+		}
+		BranchAction::List {
+			names,
+			repos,
+			output,
+		} => {
+			let entries = gitopolis.branches(list(repos));
+			let error_count = entries.iter().filter(|(_, result)| result.is_err()).count();
+			print!("{}", format_branches(entries, names, *output));
+			if error_count > 0 {
+				eprintln!("{error_count} repos could not be read");
+				std::process::exit(1);
+			}
+			return;
+		}
+	};
+	let (mut changed, mut failed) = (0, 0);
+	for (repo, result) in &entries {
+		match result {
+			Ok(outcome) => {
+				changed += usize::from(outcome.changed());
+				println!("🏢 {}> {}", repo.path, format_branch_outcome(name, outcome));
+			}
+			Err(error) => {
+				failed += 1;
+				eprintln!("Warning: {}: {}", repo.path, error.message());
+			}
+		}
+	}
+	let skipped = entries.len() - changed - failed;
+	println!("{changed} changed, {skipped} skipped, {failed} failed");
+	if failed > 0 {
+		eprintln!("{failed} repos failed");
+		std::process::exit(1);
+	}
+}
+
+fn doctor(
+	location: &Location,
+	fixes: &[Fix],
+	max_depth: Option<usize>,
+	parallel: usize,
+	output: OutputFormat,
+) {
+	if fixes.contains(&Fix::Prune) && fixes.contains(&Fix::Clone) {
+		eprintln!("Error: --fix prune and --fix clone both act on missing repos, choose one");
+		std::process::exit(1);
+	}
+	let scan_folder = match location.root.as_os_str().is_empty() {
+		true => Path::new("."),
+		false => location.root.as_path(),
+	};
+	let found = scan(location, scan_folder, max_depth, false);
+	let mut gitopolis = init_gitopolis(location);
+	let problems = gitopolis.doctor(&found).unwrap_or_else(exit_with_error);
+	let state_file = location.state_file.display().to_string();
+	print!("{}", format_problems(&problems, &state_file, output));
+	let summary = fix(&mut gitopolis, &problems, fixes, parallel).unwrap_or_else(exit_with_error);
+	if summary.pruned > 0 {
+		println!("Removed {} missing repos from {state_file}", summary.pruned);
+	}
+	if let Some(cloned) = summary.cloned {
+		println!("{}", format_clone_summary(&cloned));
+		if cloned.failed > 0 {
+			eprintln!("{} repos failed to clone", cloned.failed);
+		}
+	}
+
+	let remaining = problems.len() - summary.fixed();
+	if remaining > 0 {
+		eprintln!("{remaining} problems left");
+		std::process::exit(1);
+	}
+}
+
 /// Clone repository/repositories with dual behavior depending on URL presence.
 ///
 /// # Behavior
@@ -242,22 +1157,40 @@ fn main() {
 /// * `url` - Optional git URL to clone from
 /// * `target_dir` - Optional target directory name (only used when URL is provided)
 /// * `tag_args` - Tag arguments for either applying (with URL) or filtering (without URL)
-fn clone(url: &Option<String>, target_dir: &Option<String>, tag_args: &[String]) {
+fn clone(
+	location: &Location,
+	url: &Option<String>,
+	target_dir: &Option<String>,
+	tag_args: &[String],
+	filter_expression: &Option<String>,
+	parallel: usize,
+) {
 	match url {
-		Some(git_url) => clone_from_url(git_url, target_dir, tag_args),
+		Some(git_url) => clone_from_url(location, git_url, target_dir, tag_args),
 		None => {
 			// Clone from .gitopolis.toml with tag filtering
-			let gitopolis = init_gitopolis();
-			let filter = TagFilter::from_cli_args(tag_args);
-			gitopolis.clone(
-				gitopolis
-					.list(&filter)
-					.expect("Failed to list repositories for cloning"),
-			);
+			let gitopolis = init_gitopolis(location);
+			let filter = tag_filter(tag_args, filter_expression);
+			let repos = gitopolis.list(&filter).unwrap_or_else(exit_with_error);
+			let summary = gitopolis.clone(repos, parallel).summary();
+			println!("{}", format_clone_summary(&summary));
+			if summary.failed > 0 {
+				eprintln!("{} repos failed to clone", summary.failed);
+				std::process::exit(1);
+			}
 		}
 	}
 }
 
+/// Exit with an error if any repos failed to sync. The console observer already warned about each.
+fn exit_if_sync_failed(entries: &[RepoSyncEntry]) {
+	let error_count = entries.iter().filter(|(_, result)| result.is_err()).count();
+	if error_count > 0 {
+		eprintln!("{error_count} repos failed to sync");
+		std::process::exit(1);
+	}
+}
+
 /// Clone a single repository from a URL and add it to gitopolis.
 ///
 /// All tags from tag_args are flattened (comma-separated tags are split)
@@ -273,15 +1206,26 @@ fn clone(url: &Option<String>, target_dir: &Option<String>, tag_args: &[String])
 /// # Example
 ///
 /// `--tag foo,bar --tag baz` results in repo having tags: [foo, bar, baz]
-fn clone_from_url(git_url: &str, target_dir: &Option<String>, tag_args: &[String]) {
-	let mut gitopolis = init_gitopolis();
+fn clone_from_url(
+	location: &Location,
+	git_url: &str,
+	target_dir: &Option<String>,
+	tag_args: &[String],
+) {
+	let mut gitopolis = init_gitopolis(location);
+	// Clone into the current folder even if the state file is further up the tree
+	let target_dir = target_dir
+		.clone()
+		.or_else(|| extract_repo_name_from_url(git_url))
+		.map(|dir| location.repo_folder(&dir));
 	// Flatten all tags - when cloning a single repo, all tags are applied (no AND/OR logic)
-	let tags: Vec<String> = tag_args
-		.iter()
-		.flat_map(|s| s.split(',').map(|t| t.trim().to_string()))
-		.collect();
+	let tags = flatten_tags(tag_args);
 	match gitopolis.clone_and_add(git_url, target_dir.as_deref(), &tags) {
-		Ok(folder_name) => {
+		Ok((folder_name, outcome)) => {
+			if let CloneOutcome::Failed { .. } = outcome {
+				eprintln!("Error: Failed to clone {} to {}", git_url, folder_name);
+				std::process::exit(1);
+			}
 			println!("Successfully cloned and added {}", folder_name);
 		}
 		Err(error) => {
@@ -293,20 +1237,248 @@ fn clone_from_url(git_url: &str, target_dir: &Option<String>, tag_args: &[String
 
 const STATE_FILE: &str = ".gitopolis.toml";
 
-fn init_gitopolis() -> Gitopolis {
-	Gitopolis::new(
-		Box::new(StorageImpl { path: STATE_FILE }),
+/// Set by the completion scripts when they ask gitopolis for completions.
+const COMPLETE_VAR: &str = "GITOPOLIS_COMPLETE";
+
+/// Where the state file is, and the folder that the repo paths stored in it are relative to.
+struct Location {
+	state_file: PathBuf,
+	/// Path from the current directory to the folder containing the state file.
+	/// Empty when that is the current directory.
+	root: PathBuf,
+	/// Back up the state file before changing it.
+	backup: bool,
+	/// Which layer of the state to save new repos and tags to, if not the default.
+	write_layer: Option<Layer>,
+}
+
+impl Location {
+	/// Use the explicitly configured state file if there is one, otherwise search up from the
+	/// current directory, falling back to a new file in the current directory.
+	fn find(args: &Args) -> Self {
+		let write_layer = match (args.local, args.shared) {
+			(true, _) => Some(Layer::Local),
+			(_, true) => Some(Layer::Shared),
+			_ => None,
+		};
+		Self {
+			backup: args.backup,
+			write_layer,
+			..Self::with_config(args.config.clone())
+		}
+	}
+
+	/// The given state file, or the one found from the current directory, with the default save
+	/// options.
+	fn with_config(config: Option<PathBuf>) -> Self {
+		let state_file = match config {
+			Some(path) => path,
+			None => {
+				let current_dir =
+					env::current_dir().expect("failed to get current working directory");
+				match find_state_file(&current_dir, STATE_FILE) {
+					Some(found) if found.parent() != Some(current_dir.as_path()) => found,
+					_ => PathBuf::from(STATE_FILE),
+				}
+			}
+		};
+		let root = state_file
+			.parent()
+			.map(|parent| parent.to_path_buf())
+			.unwrap_or_default();
+		Self {
+			state_file,
+			root,
+			backup: false,
+			write_layer: None,
+		}
+	}
+
+	/// Convert a folder given on the command line, relative to the current directory, into a
+	/// path relative to the state file's folder as stored in the state file.
+	fn repo_folder(&self, folder: &str) -> String {
+		if self.root.as_os_str().is_empty() {
+			return folder.to_string();
+		}
+		let current_dir = env::current_dir().expect("failed to get current working directory");
+		let root = normalize_path(&current_dir.join(&self.root));
+		match normalize_path(&current_dir.join(folder)).strip_prefix(&root) {
+			Ok(relative) => to_repo_folder(relative),
+			Err(_) => {
+				eprintln!(
+					"Error: {} is not inside {}, the folder containing {}",
+					folder,
+					root.display(),
+					self.state_file.display()
+				);
+				std::process::exit(1);
+			}
+		}
+	}
+
+	/// Whether a folder given relative to the current directory is the state file's folder.
+	fn is_root(&self, folder: &Path) -> bool {
+		let Ok(current_dir) = env::current_dir() else {
+			return false;
+		};
+		normalize_path(&current_dir.join(folder)) == normalize_path(&current_dir.join(&self.root))
+	}
+
+	fn repo_folders(&self, folders: &[String]) -> Vec<String> {
+		folders.iter().map(|f| self.repo_folder(f)).collect()
+	}
+
+	/// The reverse of `repo_folder`: a path from the state file as it would be typed in the
+	/// current directory, e.g. `../api` from inside a sibling repo.
+	fn folder_from_here(&self, repo_folder: &str) -> String {
+		if self.root.as_os_str().is_empty() {
+			return repo_folder.to_string();
+		}
+		let Ok(current_dir) = env::current_dir() else {
+			return repo_folder.to_string();
+		};
+		let target = normalize_path(&current_dir.join(&self.root).join(repo_folder));
+		let mut up = PathBuf::new();
+		for base in current_dir.ancestors() {
+			if let Ok(rest) = target.strip_prefix(base) {
+				let folder = to_repo_folder(&up.join(rest));
+				return match folder.is_empty() {
+					true => ".".to_string(),
+					false => folder,
+				};
+			}
+			up.push("..");
+		}
+		repo_folder.to_string()
+	}
+}
+
+/// Resolve `.` and `..` in a path without touching the filesystem.
+fn normalize_path(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				normalized.pop();
+			}
+			other => normalized.push(other),
+		}
+	}
+	normalized
+}
+
+/// Path as stored in the state file, always with `/` separators so the file can be shared
+/// between operating systems.
+fn to_repo_folder(path: &Path) -> String {
+	path.components()
+		.map(|c| c.as_os_str().to_string_lossy())
+		.collect::<Vec<_>>()
+		.join("/")
+}
+
+fn init_gitopolis(location: &Location) -> Gitopolis {
+	let gitopolis = Gitopolis::new(
+		Box::new(StorageImpl {
+			path: location.state_file.clone(),
+			backup: location.backup,
+		}),
 		Box::new(GitImpl {}),
 	)
+	.with_root(location.root.clone())
+	.with_observer(Box::new(ConsoleObserver::default()));
+	match &location.write_layer {
+		Some(layer) => gitopolis.with_write_layer(layer.clone()),
+		None => gitopolis,
+	}
 }
 
-fn add(repo_folders: Vec<String>) {
-	for repo_folder in repo_folders {
-		init_gitopolis().add(repo_folder).expect("Add failed");
+fn add(location: &Location, repo_folders: &[String], tag_args: &[String]) {
+	let tags = flatten_tags(tag_args);
+	for repo_folder in location.repo_folders(repo_folders) {
+		init_gitopolis(location)
+			.add_repos(vec![repo_folder], &tags)
+			.unwrap_or_else(exit_with_error);
 	}
 }
 
-fn list(repos: Vec<Repo>, long: bool) {
+fn add_scan(
+	location: &Location,
+	scan_folder: &str,
+	max_depth: Option<usize>,
+	nested: bool,
+	tag_args: &[String],
+) {
+	let repo_folders = scan(location, Path::new(scan_folder), max_depth, nested);
+	if repo_folders.is_empty() {
+		eprintln!("No git repos found in {scan_folder}");
+		return;
+	}
+	if let Err(error) = init_gitopolis(location).add_repos(repo_folders, &flatten_tags(tag_args)) {
+		eprintln!("Error: {}", error.message());
+		std::process::exit(1);
+	}
+}
+
+/// Print the error from an operation and exit, for use with `unwrap_or_else`.
+fn exit_with_error<T>(error: GitopolisError) -> T {
+	eprintln!("Error: {}", error.message());
+	std::process::exit(1);
+}
+
+/// The git repos found in `folder` and below, as repo folders relative to the state file's
+/// folder. That folder itself is left out even if it's a repo, as it can't be one of the repos,
+/// and is searched inside so the repos below it are still found.
+fn scan(location: &Location, folder: &Path, max_depth: Option<usize>, nested: bool) -> Vec<String> {
+	find_git_repos(folder, max_depth, nested, location.is_root(folder))
+		.iter()
+		.map(|path| {
+			// scanning "." finds "./foo", store as "foo" like a plain `add foo` would
+			let path = path.strip_prefix(".").unwrap_or(path);
+			location.repo_folder(&to_repo_folder(path))
+		})
+		.filter(|repo_folder| !repo_folder.is_empty())
+		.collect()
+}
+
+/// Build the repo filter from --tag and --filter args, exiting if the expression is invalid.
+fn tag_filter(tag_args: &[String], filter_expression: &Option<String>) -> TagFilter {
+	let filter = TagFilter::from_cli_args(tag_args);
+	match filter_expression {
+		None => filter,
+		Some(expression) => match TagFilter::parse(expression) {
+			Ok(parsed) => filter.and(parsed),
+			Err(error) => {
+				eprintln!("Error: {}", error.message());
+				std::process::exit(1);
+			}
+		},
+	}
+}
+
+/// Split comma-separated tags from all --tag args into one list, for applying (not filtering).
+fn flatten_tags(tag_args: &[String]) -> Vec<String> {
+	tag_args
+		.iter()
+		.flat_map(|s| s.split(',').map(|t| t.trim().to_string()))
+		.collect()
+}
+
+/// List repos. With `long`, tags that don't come from the shared state file are followed by
+/// where they do come from, e.g. `mine (local)`.
+fn list(
+	repos: Vec<Repo>,
+	tag_origins: &BTreeMap<String, BTreeMap<String, Layer>>,
+	long: bool,
+	output: OutputFormat,
+) {
+	if output != OutputFormat::Text {
+		print_records(output, &repos);
+		if repos.is_empty() {
+			std::process::exit(2);
+		}
+		return;
+	}
 	if repos.is_empty() {
 		println!("No repos");
 		std::process::exit(2);
@@ -319,37 +1491,75 @@ fn list(repos: Vec<Repo>, long: bool) {
 				.map(|(name, remote)| format!("{}={}", name, remote.url))
 				.collect::<Vec<_>>()
 				.join(",");
-			println!("{}\t{}\t{}", repo.path, repo.tags.join(","), remotes_str);
+			let tags_str = repo
+				.tags
+				.iter()
+				.map(|tag| {
+					let origin = tag_origins.get(&repo.path).and_then(|tags| tags.get(tag));
+					match origin {
+						None | Some(Layer::Shared) => tag.to_string(),
+						Some(layer) => format!("{tag} ({layer})"),
+					}
+				})
+				.collect::<Vec<_>>()
+				.join(",");
+			println!("{}\t{}\t{}", repo.path, tags_str, remotes_str);
 		} else {
 			println!("{}", repo.path);
 		}
 	}
 }
 
-fn list_tags(long: bool) {
-	let gitopolis = &init_gitopolis();
-	if long {
-		for tag in gitopolis.tags().expect("Failed to get tags") {
+/// Structured output record for `tags --long`
+#[derive(Serialize)]
+struct TagRecord {
+	tag: String,
+	repos: Vec<String>,
+}
+
+fn list_tags(location: &Location, long: bool, output: OutputFormat) {
+	let gitopolis = &init_gitopolis(location);
+	if output != OutputFormat::Text {
+		let tags = gitopolis.tags().unwrap_or_else(exit_with_error);
+		if long {
+			let records: Vec<TagRecord> = tags
+				.into_iter()
+				.map(|tag| {
+					let filter = TagFilter::from_cli_args(std::slice::from_ref(&tag));
+					let repos = gitopolis
+						.list(&filter)
+						.unwrap_or_else(exit_with_error)
+						.into_iter()
+						.map(|r| r.path)
+						.collect();
+					TagRecord { tag, repos }
+				})
+				.collect();
+			print_records(output, &records);
+		} else {
+			print_records(output, &tags);
+		}
+	} else if long {
+		for tag in gitopolis.tags().unwrap_or_else(exit_with_error) {
 			println!("{tag}");
 			let filter = TagFilter::from_cli_args(std::slice::from_ref(&tag));
-			for r in gitopolis
-				.list(&filter)
-				.expect("Failed to list repositories for tag")
-			{
+			for r in gitopolis.list(&filter).unwrap_or_else(exit_with_error) {
 				println!("\t{}", r.path);
 			}
 			println!();
 		}
 	} else {
-		for tag in gitopolis.tags().expect("Failed to get tags") {
+		for tag in gitopolis.tags().unwrap_or_else(exit_with_error) {
 			println!("{tag}");
 		}
 	}
 }
 
-fn show(repo_folder: &str) {
-	let gitopolis = init_gitopolis();
-	match gitopolis.show(repo_folder) {
+fn show(location: &Location, repo_folder: &str, output: OutputFormat) {
+	let gitopolis = init_gitopolis(location);
+	match gitopolis.show(&location.repo_folder(repo_folder)) {
+		Ok(repo_info) if output == OutputFormat::Json => print_json(&repo_info),
+		Ok(repo_info) if output == OutputFormat::Ndjson => print_ndjson_line(&repo_info),
 		Ok(repo_info) => {
 			println!("Tags:");
 			if repo_info.tags.is_empty() {
@@ -376,3 +1586,27 @@ fn show(repo_folder: &str) {
 		}
 	}
 }
+
+fn status(entries: Vec<RepoStatusEntry>, output: OutputFormat) {
+	if entries.is_empty() && output == OutputFormat::Text {
+		println!("No repos");
+		std::process::exit(2);
+	}
+	let error_count = entries.iter().filter(|(_, result)| result.is_err()).count();
+	print!("{}", format_status(entries, output));
+	if error_count > 0 {
+		if output == OutputFormat::Text {
+			eprintln!("{error_count} repos could not be read");
+		}
+		std::process::exit(1);
+	}
+}
+
+fn pull(entries: Vec<RepoPullEntry>, output: OutputFormat) {
+	let error_count = entries.iter().filter(|(_, result)| result.is_err()).count();
+	print!("{}", format_pull(entries, output));
+	if error_count > 0 {
+		eprintln!("{error_count} repos failed to pull");
+		std::process::exit(1);
+	}
+}
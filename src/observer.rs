@@ -0,0 +1,81 @@
+use crate::exec::{ExecOutcome, OutputStream};
+use crate::git::{CloneProgress, FetchResult};
+use crate::gitopolis::{BranchOutcome, CloneOutcome, GitopolisError, PullOutcome};
+use crate::repos::Repo;
+
+/// Receives live feedback while `Gitopolis` works across repos, e.g. to draw progress in a UI.
+/// Injected with `Gitopolis::with_observer`; the CLI's console output is one implementation.
+///
+/// Clone, fetch, pull, and exec with `parallel` above 1, work on a pool of threads: `RepoStarted`
+/// is sent from a worker as it picks up each repo, so repos can start in any order and several can
+/// be in progress at once, and `CloneProgress` is sent from workers as git reports it. Each repo's
+/// output and `RepoFinished` are sent from the calling thread in list order. Other operations, and
+/// exec one repo at a time, send every event from the calling thread with each repo's events
+/// together.
+pub trait Observer: Sync {
+	fn event(&self, event: Event);
+}
+
+/// The operations that report their progress to an `Observer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+	Clone,
+	SyncReadRemotes,
+	SyncWriteRemotes,
+	Exec,
+	Fetch,
+	Pull,
+	/// Creating, switching to or deleting a branch.
+	Branch,
+}
+
+pub enum Event<'a> {
+	/// An operation is about to work through `repo_count` repos, up to `parallel` at a time.
+	OperationStarted {
+		operation: Operation,
+		repo_count: usize,
+		parallel: usize,
+	},
+	/// Work on a repo has begun. Not sent for repos that are skipped, e.g. missing folders.
+	RepoStarted { repo: &'a Repo },
+	/// A line of output from the command running in a repo, without its line ending.
+	OutputLine {
+		repo: &'a Repo,
+		stream: OutputStream,
+		line: &'a str,
+	},
+	/// A clone progress update from git.
+	CloneProgress {
+		repo: &'a Repo,
+		progress: &'a CloneProgress,
+	},
+	RepoFinished {
+		repo: &'a Repo,
+		outcome: RepoOutcome<'a>,
+	},
+	/// All repos are done. The full report is returned by the operation itself.
+	OperationFinished { operation: Operation },
+	/// Something went wrong that doesn't stop the operation, e.g. a log file that couldn't be
+	/// written.
+	Warning { message: &'a str },
+}
+
+/// What happened to one repo, depending on the operation.
+pub enum RepoOutcome<'a> {
+	Clone(&'a CloneOutcome),
+	/// The names of the remotes synced, or why the repo couldn't be synced.
+	Sync(&'a Result<Vec<String>, GitopolisError>),
+	Exec(&'a ExecOutcome),
+	/// What fetching changed, or why the repo couldn't be fetched.
+	Fetch(&'a Result<FetchResult, GitopolisError>),
+	/// What pulling did, or why it failed.
+	Pull(&'a Result<PullOutcome, GitopolisError>),
+	Branch(&'a Result<BranchOutcome, GitopolisError>),
+}
+
+/// Ignores all events, for when nobody is watching.
+pub struct NoObserver;
+
+impl Observer for NoObserver {
+	fn event(&self, _event: Event) {}
+}
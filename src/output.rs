@@ -0,0 +1,506 @@
+use crate::exec::{format_args_for_display, ExecOutcome, OutputStream};
+use crate::git::{CloneOutput, FetchResult};
+use crate::gitopolis::{BranchOutcome, CloneOutcome, CloneSummary, ImportOutcome};
+use crate::observer::{Event, Observer, Operation, RepoOutcome};
+use crate::repos::Repo;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How results are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+	/// Human readable text
+	#[default]
+	Text,
+	/// A single JSON document
+	Json,
+	/// Newline delimited JSON, one record per line
+	Ndjson,
+}
+
+/// Write a list of records as a pretty printed JSON array.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) {
+	println!(
+		"{}",
+		serde_json::to_string_pretty(value).expect("Failed to generate json")
+	);
+}
+
+/// Write a single record as one line of JSON.
+pub fn print_ndjson_line<T: Serialize + ?Sized>(value: &T) {
+	println!(
+		"{}",
+		serde_json::to_string(value).expect("Failed to generate json")
+	);
+}
+
+/// Write records as either a JSON array or one JSON line per record.
+pub fn print_records<T: Serialize>(format: OutputFormat, records: &[T]) {
+	print!("{}", format_records(format, records));
+}
+
+/// Records as either a pretty printed JSON array or one JSON line per record, each line ending
+/// with a newline.
+pub fn format_records<T: Serialize>(format: OutputFormat, records: &[T]) -> String {
+	let json = |value: serde_json::Result<String>| value.expect("Failed to generate json");
+	match format {
+		OutputFormat::Ndjson => records
+			.iter()
+			.map(|record| format!("{}\n", json(serde_json::to_string(record))))
+			.collect(),
+		_ => format!("{}\n", json(serde_json::to_string_pretty(records))),
+	}
+}
+
+/// Machine readable result of running the command in one repo.
+#[derive(Serialize)]
+struct ExecRecord {
+	path: String,
+	tags: Vec<String>,
+	command: Vec<String>,
+	/// True if the repo folder was missing so the command wasn't run.
+	skipped: bool,
+	/// True if the command was killed for running longer than the timeout.
+	timed_out: bool,
+	/// True if the command was stopped because the run was cancelled.
+	cancelled: bool,
+	/// None if skipped, timed out, cancelled, the command couldn't be started or it was killed by
+	/// a signal.
+	exit_code: Option<i32>,
+	duration_ms: u128,
+	stdout: String,
+	stderr: String,
+}
+
+/// How grouped exec output shows the repos that gave each output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupRepos {
+	List,
+	Count,
+}
+
+/// The CLI's console output, written as events arrive: 🏢 headers with command output streamed
+/// under them, a live progress line while cloning, and warnings for repos that failed.
+/// Exec output can instead be one line per repo (`oneline`), one line per distinct output once
+/// all repos are done (`with_grouping`) or one record per repo (json/ndjson).
+pub struct ConsoleObserver {
+	exec_args: Vec<String>,
+	oneline: bool,
+	group: Option<GroupRepos>,
+	format: OutputFormat,
+	/// Only draw the clone progress line on a terminal so redirected output isn't cluttered with
+	/// partial lines.
+	progress_enabled: bool,
+	/// Also held while drawing progress so output never lands mid-line.
+	state: Mutex<ConsoleState>,
+}
+
+#[derive(Default)]
+struct ConsoleState {
+	operation: Option<Operation>,
+	repo_count: usize,
+	parallel: usize,
+	finished_count: usize,
+	stdout: String,
+	stderr: String,
+	records: Vec<ExecRecord>,
+	/// Repos that gave each output so far, when grouping.
+	groups: BTreeMap<String, Vec<String>>,
+	/// Exec repos started in parallel whose 🏢 header is held back until their output arrives,
+	/// so it lands above that output rather than when they started.
+	held_headers: BTreeSet<String>,
+}
+
+impl Default for ConsoleObserver {
+	fn default() -> Self {
+		Self::exec(vec![], false, OutputFormat::Text)
+	}
+}
+
+impl ConsoleObserver {
+	/// Output for running `exec_args`, in the given layout.
+	pub fn exec(exec_args: Vec<String>, oneline: bool, format: OutputFormat) -> Self {
+		Self {
+			exec_args,
+			oneline,
+			group: None,
+			format,
+			progress_enabled: io::stderr().is_terminal(),
+			state: Mutex::new(ConsoleState::default()),
+		}
+	}
+
+	/// Collect each repo's output like `oneline` does and, once all the repos are done, print
+	/// each distinct output once with the repos that gave it, most common first.
+	pub fn with_grouping(mut self, group: GroupRepos) -> Self {
+		self.group = Some(group);
+		self
+	}
+
+	fn streaming(&self) -> bool {
+		!self.oneline && self.group.is_none() && self.format == OutputFormat::Text
+	}
+
+	fn print_exec_header(&self, repo: &Repo) {
+		println!();
+		println!(
+			"🏢 {}> {}",
+			repo.path,
+			format_args_for_display(&self.exec_args)
+		);
+	}
+
+	fn exec_finished(&self, state: &mut ConsoleState, repo: &Repo, outcome: &ExecOutcome) {
+		if let ExecOutcome::Cancelled { duration: None } = outcome {
+			// never started, so there's nothing to show
+		} else if self.format != OutputFormat::Text {
+			self.write_record(state, repo, outcome);
+		} else if self.group.is_some() {
+			let output = oneline_output(state, outcome);
+			state
+				.groups
+				.entry(output)
+				.or_default()
+				.push(repo.path.clone());
+		} else if self.oneline {
+			print_oneline(state, repo, outcome);
+		} else {
+			print_exec_finished(repo, outcome);
+		}
+		state.stdout.clear();
+		state.stderr.clear();
+	}
+
+	fn write_record(&self, state: &mut ConsoleState, repo: &Repo, outcome: &ExecOutcome) {
+		let (exit_code, duration_ms) = match outcome {
+			ExecOutcome::Exited {
+				exit_code,
+				duration,
+			} => (*exit_code, duration.as_millis()),
+			ExecOutcome::TimedOut { duration }
+			| ExecOutcome::Cancelled {
+				duration: Some(duration),
+			} => (None, duration.as_millis()),
+			_ => (None, 0),
+		};
+		let record = ExecRecord {
+			path: repo.path.clone(),
+			tags: repo.tags.clone(),
+			command: self.exec_args.clone(),
+			skipped: matches!(outcome, ExecOutcome::Missing),
+			timed_out: matches!(outcome, ExecOutcome::TimedOut { .. }),
+			cancelled: matches!(outcome, ExecOutcome::Cancelled { .. }),
+			exit_code,
+			duration_ms,
+			stdout: std::mem::take(&mut state.stdout),
+			stderr: std::mem::take(&mut state.stderr),
+		};
+		if self.format == OutputFormat::Ndjson {
+			print_ndjson_line(&record);
+		} else {
+			state.records.push(record);
+		}
+	}
+}
+
+impl Observer for ConsoleObserver {
+	fn event(&self, event: Event) {
+		let mut state = self.state.lock().expect("console lock poisoned");
+		match event {
+			Event::OperationStarted {
+				operation,
+				repo_count,
+				parallel,
+			} => {
+				state.operation = Some(operation);
+				state.repo_count = repo_count;
+				state.parallel = parallel;
+				state.finished_count = 0;
+			}
+			Event::RepoStarted { repo } => {
+				if state.operation == Some(Operation::Exec) && self.streaming() {
+					if state.parallel > 1 {
+						state.held_headers.insert(repo.path.clone());
+					} else {
+						self.print_exec_header(repo);
+					}
+				}
+			}
+			Event::OutputLine { repo, stream, line } => {
+				if state.held_headers.remove(&repo.path) {
+					self.print_exec_header(repo);
+				}
+				match (self.streaming(), stream) {
+					(true, OutputStream::Stdout) => println!("{line}"),
+					(true, OutputStream::Stderr) => eprintln!("{line}"),
+					(false, OutputStream::Stdout) => {
+						state.stdout.push_str(line);
+						state.stdout.push('\n');
+					}
+					(false, OutputStream::Stderr) => {
+						state.stderr.push_str(line);
+						state.stderr.push('\n');
+					}
+				}
+			}
+			Event::CloneProgress { repo, progress } => {
+				if self.progress_enabled {
+					eprint!(
+						"\r\x1b[2K🏢 [{}/{}] {}> {} {}% ({}/{})",
+						state.finished_count,
+						state.repo_count,
+						repo.path,
+						progress.phase,
+						progress.percent,
+						progress.current,
+						progress.total
+					);
+				}
+			}
+			Event::RepoFinished { repo, outcome } => {
+				match outcome {
+					RepoOutcome::Clone(outcome) => {
+						if self.progress_enabled {
+							eprint!("\r\x1b[2K");
+						}
+						print_clone_outcome(&repo.path, outcome);
+					}
+					RepoOutcome::Sync(Err(error)) => {
+						let action = if state.operation == Some(Operation::SyncReadRemotes) {
+							"read remotes from"
+						} else {
+							"write remotes to"
+						};
+						eprintln!(
+							"Warning: Could not {action} {}: {}",
+							repo.path,
+							error.message()
+						);
+					}
+					RepoOutcome::Sync(Ok(_)) => {}
+					RepoOutcome::Exec(outcome) => {
+						if state.held_headers.remove(&repo.path) {
+							self.print_exec_header(repo);
+						}
+						self.exec_finished(&mut state, repo, outcome)
+					}
+					RepoOutcome::Fetch(Ok(fetched)) => print_fetched(&repo.path, fetched),
+					// summarised once all the repos are done
+					RepoOutcome::Pull(_) | RepoOutcome::Branch(_) => {}
+					RepoOutcome::Fetch(Err(error)) => {
+						eprintln!(
+							"Warning: Could not fetch {}: {}",
+							repo.path,
+							error.message()
+						)
+					}
+				}
+				state.finished_count += 1;
+			}
+			Event::OperationFinished { operation } => {
+				if operation == Operation::Exec && self.format == OutputFormat::Json {
+					print_json(&std::mem::take(&mut state.records));
+				}
+				if let (Operation::Exec, Some(group)) = (operation, self.group) {
+					print_groups(std::mem::take(&mut state.groups), group);
+				}
+			}
+			Event::Warning { message } => eprintln!("Warning: {message}"),
+		}
+	}
+}
+
+fn print_oneline(state: &ConsoleState, repo: &Repo, outcome: &ExecOutcome) {
+	println!("{}\t{}", repo.path, oneline_output(state, outcome));
+}
+
+/// A repo's output on one line, for `oneline` and grouping.
+fn oneline_output(state: &ConsoleState, outcome: &ExecOutcome) -> String {
+	if let ExecOutcome::Missing = outcome {
+		return "Repo folder missing, skipped.".to_string();
+	}
+	// Flatten multi-line output to single line by replacing newlines with spaces
+	let stdout_clean = state.stdout.trim().replace('\n', " ");
+	let stderr_clean = state.stderr.trim().replace('\n', " ");
+
+	// Combine stdout and stderr, with stderr included when command fails
+	let output = if !outcome.success() && !stderr_clean.is_empty() {
+		if stdout_clean.is_empty() {
+			stderr_clean
+		} else {
+			format!("{} {}", stdout_clean, stderr_clean)
+		}
+	} else {
+		stdout_clean
+	};
+	let stopped = match outcome {
+		ExecOutcome::TimedOut { .. } => " (timed out)",
+		ExecOutcome::Cancelled { .. } => " (cancelled)",
+		_ => "",
+	};
+	format!("{output}{stopped}")
+}
+
+/// Each distinct output with the repos that gave it (or how many), most common first.
+fn print_groups(groups: BTreeMap<String, Vec<String>>, group: GroupRepos) {
+	let mut groups: Vec<(String, Vec<String>)> = groups.into_iter().collect();
+	// stable, so outputs given by the same number of repos stay in alphabetical order
+	groups.sort_by_key(|(_, repos)| std::cmp::Reverse(repos.len()));
+	for (output, repos) in groups {
+		match group {
+			GroupRepos::List => println!("{output}\t{}", repos.join(" ")),
+			GroupRepos::Count => println!("{}\t{output}", repos.len()),
+		}
+	}
+}
+
+fn print_exec_finished(repo: &Repo, outcome: &ExecOutcome) {
+	match outcome {
+		ExecOutcome::Missing => {
+			println!();
+			println!("🏢 {}> Repo folder missing, skipped.", repo.path);
+		}
+		ExecOutcome::Failed(error) => {
+			eprintln!("Failed to execute command. {}", error.message());
+			println!();
+		}
+		ExecOutcome::Exited {
+			exit_code: Some(0), ..
+		} => println!(),
+		ExecOutcome::Exited {
+			exit_code: Some(exit_code),
+			..
+		} => {
+			eprintln!("Command exited with code {exit_code}");
+			println!();
+		}
+		ExecOutcome::Exited {
+			exit_code: None, ..
+		} => {
+			eprintln!("Command was terminated by a signal");
+			println!();
+		}
+		ExecOutcome::TimedOut { duration } => {
+			eprintln!(
+				"Command timed out after {} and was killed",
+				humantime::format_duration(Duration::from_secs(duration.as_secs()))
+			);
+			println!();
+		}
+		ExecOutcome::Cancelled { .. } => {
+			eprintln!("Command was cancelled");
+			println!();
+		}
+	}
+}
+
+/// The totals at the end of cloning the repos from the config.
+pub fn format_clone_summary(summary: &CloneSummary) -> String {
+	format!(
+		"{} cloned, {} already existed, {} had no remotes, {} failed",
+		summary.cloned, summary.skipped, summary.no_remotes, summary.failed
+	)
+}
+
+/// What creating, switching to or deleting branch `name` did in one repo, to show after its path.
+pub fn format_branch_outcome(name: &str, outcome: &BranchOutcome) -> String {
+	match outcome {
+		BranchOutcome::Created => format!("Created and switched to {name}"),
+		BranchOutcome::Switched { stashed: false } => format!("Switched to {name}"),
+		BranchOutcome::Switched { stashed: true } => {
+			format!("Stashed changes and switched to {name}")
+		}
+		BranchOutcome::Deleted => format!("Deleted {name}"),
+		BranchOutcome::Exists => format!("Branch {name} already exists, skipped."),
+		BranchOutcome::Missing => format!("No branch {name}, skipped."),
+		BranchOutcome::AlreadyOn => format!("Already on {name}"),
+		BranchOutcome::Current => format!("On {name}, not deleting it, skipped."),
+		BranchOutcome::Dirty => {
+			"Uncommitted changes, skipped. Use --stash to stash them and switch anyway.".to_string()
+		}
+	}
+}
+
+/// The repos an import added or tagged, a line each, then the totals.
+pub fn format_import_outcomes(outcomes: &[(Repo, ImportOutcome)]) -> String {
+	let (mut added, mut tagged, mut unchanged) = (0, 0, 0);
+	let mut text = String::new();
+	for (repo, outcome) in outcomes {
+		match outcome {
+			ImportOutcome::Added => {
+				added += 1;
+				text.push_str(&format!("🏢 {}> Added\n", repo.path));
+			}
+			ImportOutcome::Tagged { tags } => {
+				tagged += 1;
+				text.push_str(&format!("🏢 {}> Tagged {}\n", repo.path, tags.join(",")));
+			}
+			ImportOutcome::Unchanged => unchanged += 1,
+		}
+	}
+	text.push_str(&format!(
+		"{added} added, {tagged} tagged, {unchanged} unchanged\n"
+	));
+	text
+}
+
+fn print_clone_outcome(path: &str, outcome: &CloneOutcome) {
+	match outcome {
+		CloneOutcome::NoRemotes => println!("🏢 {path}> No remotes to clone from, skipped."),
+		CloneOutcome::AlreadyExists => println!("🏢 {path}> Already exists, skipped."),
+		CloneOutcome::Cloned {
+			url,
+			output,
+			remote_errors,
+		} => {
+			print_clone_output(path, url, output);
+			for error in remote_errors {
+				eprintln!("Warning: {}", error.message());
+			}
+		}
+		CloneOutcome::Failed { url, output } => {
+			print_clone_output(path, url, output);
+			eprintln!("Warning: Could not clone {path}");
+		}
+	}
+}
+
+fn print_fetched(path: &str, fetched: &FetchResult) {
+	if fetched.remotes.is_empty() {
+		println!("🏢 {path}> No remotes, skipped.");
+		return;
+	}
+	let remotes = fetched.remotes.join(", ");
+	if fetched.updated_refs.is_empty() {
+		println!("🏢 {path}> Fetched {remotes}, up to date.");
+		return;
+	}
+	println!("🏢 {path}> Fetched {remotes}");
+	for updated in &fetched.updated_refs {
+		let name = short_ref_name(&updated.name);
+		match (&updated.old, &updated.new) {
+			(Some(old), Some(new)) => println!("  {name} {old}..{new}"),
+			(None, Some(new)) => println!("  {name} {new} (new)"),
+			(_, None) => println!("  {name} (pruned)"),
+		}
+	}
+}
+
+/// Ref name as git shows it in fetch output, e.g. "origin/main" for "refs/remotes/origin/main".
+fn short_ref_name(name: &str) -> &str {
+	["refs/remotes/", "refs/tags/", "refs/heads/"]
+		.iter()
+		.find_map(|prefix| name.strip_prefix(prefix))
+		.unwrap_or(name)
+}
+
+fn print_clone_output(path: &str, url: &str, output: &CloneOutput) {
+	println!("🏢 {path}> Cloning {url} ...");
+	println!("{}", output.stdout);
+	println!("{}", output.stderr);
+}
@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Runs `run` for each item (with its index) on a pool of up to `parallel` worker threads,
+/// passing each result to `finished` on the calling thread in the same order as `items`,
+/// regardless of which order they complete in.
+pub fn run_in_order<'a, I, T, R, F>(items: &'a [I], parallel: usize, run: R, mut finished: F)
+where
+	I: Sync,
+	T: Send,
+	R: Fn(usize, &'a I) -> T + Sync,
+	F: FnMut(&'a I, T),
+{
+	let next_item = AtomicUsize::new(0);
+	let (sender, receiver) = mpsc::channel();
+
+	thread::scope(|scope| {
+		for _ in 0..parallel.max(1).min(items.len()) {
+			let sender = sender.clone();
+			let next_item = &next_item;
+			let run = &run;
+			scope.spawn(move || loop {
+				let index = next_item.fetch_add(1, Ordering::SeqCst);
+				let Some(item) = items.get(index) else {
+					break;
+				};
+				if sender.send((index, run(index, item))).is_err() {
+					break;
+				}
+			});
+		}
+		drop(sender); // so the receiver loop ends when all workers are done
+
+		// Hold on to results that finish out of order until it's their turn
+		let mut done: BTreeMap<usize, T> = BTreeMap::new();
+		let mut next_in_order = 0;
+		for (index, result) in receiver {
+			done.insert(index, result);
+			while let Some(result) = done.remove(&next_in_order) {
+				finished(&items[next_in_order], result);
+				next_in_order += 1;
+			}
+		}
+	});
+}
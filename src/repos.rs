@@ -3,12 +3,12 @@ use log::info;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct Repos {
 	repos: Vec<Repo>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RepoInfo {
 	pub path: String,
 	pub tags: Vec<String>,
@@ -18,7 +18,10 @@ pub struct RepoInfo {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Repo {
 	pub path: String,
+	// both optional so that an overlay can add tags to a repo defined in another file
+	#[serde(default)]
 	pub tags: Vec<String>,
+	#[serde(default)]
 	pub remotes: BTreeMap<String, Remote>,
 }
 
@@ -93,12 +96,29 @@ impl Repos {
 
 	fn add_repo(&mut self, repo: Repo) {
 		let repo_folder = repo.path.clone();
-		self.repos.push(repo);
-		self.repos
-			.sort_by(|a, b| a.path.to_lowercase().cmp(&b.path.to_lowercase()));
+		self.insert(repo);
 		info!("Added {repo_folder}");
 	}
 
+	/// The entry for `repo_folder`, adding an empty one (no tags or remotes) if there isn't one,
+	/// e.g. for an overlay to hold tags for a repo defined in another file.
+	pub(crate) fn entry(&mut self, repo_folder: &str) -> &mut Repo {
+		let index = match self.repo_index(repo_folder.to_string()) {
+			Some(index) => index,
+			None => {
+				self.insert(Repo::new(repo_folder.to_string()));
+				self.repo_index(repo_folder.to_string())
+					.expect("repo just inserted")
+			}
+		};
+		&mut self.repos[index]
+	}
+
+	fn insert(&mut self, repo: Repo) {
+		self.repos.push(repo);
+		self.repos.sort_by_key(|a| a.path.to_lowercase());
+	}
+
 	pub fn remove(&mut self, repo_folders: Vec<String>) {
 		for repo_folder in repo_folders {
 			match self.repo_index(repo_folder.to_owned()) {
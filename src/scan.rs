@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Find git working copies in `start` and the folders below it.
+///
+/// A folder is a working copy if it contains `.git`, either a folder (normal clone) or a file
+/// (worktrees and submodules). Once a working copy is found its contents are only searched if
+/// `include_nested` is set. `search_start` searches inside `start` even if it's a working copy,
+/// for when it's the folder holding the state file, so a repo there doesn't hide the ones below
+/// it. `max_depth` limits how many levels below `start` are searched. Symlinks are not followed.
+/// Results are sorted and include `start` as a prefix.
+pub fn find_git_repos(
+	start: &Path,
+	max_depth: Option<usize>,
+	include_nested: bool,
+	search_start: bool,
+) -> Vec<PathBuf> {
+	let mut found = vec![];
+	search(
+		start,
+		0,
+		max_depth,
+		include_nested,
+		search_start,
+		&mut found,
+	);
+	found.sort();
+	found
+}
+
+fn search(
+	folder: &Path,
+	depth: usize,
+	max_depth: Option<usize>,
+	include_nested: bool,
+	search_start: bool,
+	found: &mut Vec<PathBuf>,
+) {
+	if is_working_copy(folder) {
+		found.push(folder.to_path_buf());
+		let search_inside = include_nested || (search_start && depth == 0);
+		if !search_inside {
+			return;
+		}
+	}
+	if max_depth.is_some_and(|max| depth >= max) {
+		return;
+	}
+	let Ok(entries) = fs::read_dir(folder) else {
+		return; // unreadable folders (e.g. permissions) are skipped
+	};
+	for entry in entries.flatten() {
+		let is_folder = entry.file_type().is_ok_and(|t| t.is_dir());
+		if is_folder && entry.file_name() != ".git" {
+			search(
+				&entry.path(),
+				depth + 1,
+				max_depth,
+				include_nested,
+				search_start,
+				found,
+			);
+		}
+	}
+}
+
+fn is_working_copy(folder: &Path) -> bool {
+	folder.join(".git").exists()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finds_nested_layouts_and_stops_at_repos() {
+		let temp = tempfile::tempdir().unwrap();
+		let root = temp.path();
+		fs::create_dir_all(root.join("clients/acme/api/.git")).unwrap();
+		fs::create_dir_all(root.join("clients/acme/api/vendor/lib/.git")).unwrap();
+		fs::create_dir_all(root.join("tools/.git")).unwrap();
+		fs::create_dir_all(root.join("notes")).unwrap();
+		fs::create_dir_all(root.join("worktree")).unwrap();
+		fs::write(
+			root.join("worktree/.git"),
+			"gitdir: ../tools/.git/worktrees/wt",
+		)
+		.unwrap();
+
+		let found = find_git_repos(root, None, false, false);
+		assert_eq!(
+			vec![
+				root.join("clients/acme/api"),
+				root.join("tools"),
+				root.join("worktree"),
+			],
+			found
+		);
+
+		let found = find_git_repos(root, None, true, false);
+		assert!(found.contains(&root.join("clients/acme/api/vendor/lib")));
+
+		let found = find_git_repos(root, Some(2), false, false);
+		assert_eq!(vec![root.join("tools"), root.join("worktree")], found);
+	}
+
+	#[test]
+	fn searches_inside_start_when_asked() {
+		let temp = tempfile::tempdir().unwrap();
+		let root = temp.path();
+		fs::create_dir_all(root.join(".git")).unwrap();
+		fs::create_dir_all(root.join("api/.git")).unwrap();
+		fs::create_dir_all(root.join("api/vendor/.git")).unwrap();
+
+		let found = find_git_repos(root, None, false, true);
+		assert_eq!(vec![root.to_path_buf(), root.join("api")], found);
+	}
+
+	#[test]
+	fn stops_at_start_when_it_is_a_repo() {
+		let temp = tempfile::tempdir().unwrap();
+		let api = temp.path().join("api");
+		fs::create_dir_all(api.join(".git")).unwrap();
+		fs::create_dir_all(api.join("vendor/lib/.git")).unwrap();
+
+		assert_eq!(vec![api.clone()], find_git_repos(&api, None, false, false));
+		assert_eq!(
+			vec![api.clone(), api.join("vendor/lib")],
+			find_git_repos(&api, None, true, false)
+		);
+	}
+}
@@ -0,0 +1,236 @@
+use crate::gitopolis::GitopolisError;
+use crate::gitopolis::GitopolisError::StateError;
+use crate::repos::{Remote, Repo, Repos};
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table, TableLike, Value};
+
+/// Write `repos` into the existing state file contents, editing the document in place so that
+/// comments, blank lines and ordering added by hand survive. Only the repo entries that differ
+/// from `repos` are touched: removed repos are dropped, new repos are inserted in path order and
+/// changed tags/remotes are updated where they are.
+pub fn update(existing: &str, repos: &Repos) -> Result<String, GitopolisError> {
+	let mut document: DocumentMut = existing.parse().map_err(|error| StateError {
+		message: format!("Failed to parse state data as valid TOML. {error}"),
+	})?;
+
+	// taken rather than removed so that `repos` keeps its place among the document's keys when
+	// it's put back, as other tables such as `[aliases]` are laid out around it
+	let mut tables = match document.get_mut("repos").map(std::mem::take) {
+		Some(Item::ArrayOfTables(tables)) => tables,
+		_ => ArrayOfTables::new(),
+	};
+	let wanted: Vec<&Repo> = repos.as_vec().iter().collect();
+
+	let gone: Vec<usize> = (0..tables.len())
+		.filter(|&index| {
+			let path = table_path(&tables, index);
+			!wanted.iter().any(|repo| Some(repo.path.as_str()) == path)
+		})
+		.collect();
+	let new: Vec<&Repo> = wanted
+		.iter()
+		.filter(|repo| {
+			!(0..tables.len()).any(|index| table_path(&tables, index) == Some(&repo.path))
+		})
+		.copied()
+		.collect();
+
+	if let ([moved_from], [moved_to]) = (gone.as_slice(), new.as_slice()) {
+		// Only a move changes a path, so keep the entry where it is rather than re-adding it
+		let table = tables.get_mut(*moved_from).expect("index in range");
+		set_string(table, "path", &moved_to.path);
+	} else {
+		for index in gone.into_iter().rev() {
+			remove_table(&mut tables, index);
+		}
+		for repo in new {
+			insert_table(&mut tables, repo_table(repo));
+		}
+	}
+
+	for table in tables.iter_mut() {
+		let path = table.get("path").and_then(Item::as_str).map(str::to_string);
+		if let Some(repo) = wanted.iter().find(|repo| Some(&repo.path) == path.as_ref()) {
+			update_table(table, repo);
+		}
+	}
+
+	if tables.is_empty() {
+		// an empty array of tables has no headers to write, so it would vanish from the file
+		document.insert("repos", value(Array::new()));
+	} else {
+		document.insert("repos", Item::ArrayOfTables(tables));
+	}
+	Ok(document.to_string())
+}
+
+fn table_path(tables: &ArrayOfTables, index: usize) -> Option<&str> {
+	tables.get(index)?.get("path")?.as_str()
+}
+
+/// Remove a repo's entry along with the comments directly above it. Comments at the top of the
+/// file that are separated from the first entry by a blank line are kept as the file's header.
+fn remove_table(tables: &mut ArrayOfTables, index: usize) {
+	let removed = tables.remove(index);
+	if index != 0 {
+		return;
+	}
+	let prefix = decor_prefix(&removed);
+	let (header, _) = split_header(prefix);
+	if let Some(next) = tables.get_mut(0) {
+		let next_prefix = decor_prefix(next).trim_start_matches('\n').to_string();
+		next.decor_mut()
+			.set_prefix(format!("{header}{next_prefix}"));
+	}
+}
+
+fn decor_prefix(table: &Table) -> &str {
+	table
+		.decor()
+		.prefix()
+		.and_then(|prefix| prefix.as_str())
+		.unwrap_or("")
+}
+
+/// Split the comments above the first entry into the file's header (everything up to the last
+/// blank line) and the part that belongs to the entry itself.
+fn split_header(prefix: &str) -> (&str, &str) {
+	match prefix.rfind("\n\n") {
+		Some(header_end) => prefix.split_at(header_end + 2),
+		None => ("", prefix),
+	}
+}
+
+/// Insert before the first entry with a later path, matching the order repos are added in.
+fn insert_table(tables: &mut ArrayOfTables, mut table: Table) {
+	let path = table
+		.get("path")
+		.and_then(Item::as_str)
+		.unwrap_or("")
+		.to_lowercase();
+	let index = (0..tables.len())
+		.find(|&index| {
+			table_path(tables, index).is_some_and(|existing| existing.to_lowercase() > path)
+		})
+		.unwrap_or(tables.len());
+
+	// Tables are written in order of their position in the original document, and ones without a
+	// position follow the table before them. Sharing the position of the entry it goes in front
+	// of keeps the new entry next to it rather than after any other tables in between.
+	if let Some(position) = tables.get(index).and_then(Table::position) {
+		table.set_position(position);
+	}
+	if index > 0 {
+		table.decor_mut().set_prefix("\n");
+	} else if let Some(first) = tables.get_mut(0) {
+		// the new entry goes above the file's header comments
+		let (header, own) = split_header(decor_prefix(first));
+		table.decor_mut().set_prefix(header.to_string());
+		let own = format!("\n{}", own.trim_start_matches('\n'));
+		first.decor_mut().set_prefix(own);
+	}
+
+	// ArrayOfTables can only be appended to, so rebuild it around the new entry
+	let mut rebuilt = ArrayOfTables::new();
+	let mut existing = std::mem::take(tables).into_iter();
+	for _ in 0..index {
+		rebuilt.push(existing.next().expect("index in range"));
+	}
+	rebuilt.push(table);
+	for table in existing {
+		rebuilt.push(table);
+	}
+	*tables = rebuilt;
+}
+
+/// Bring an existing entry's tags and remotes in line with `repo`, leaving anything that
+/// already matches untouched.
+fn update_table(table: &mut Table, repo: &Repo) {
+	let current_tags: Option<Vec<&str>> = table
+		.get("tags")
+		.and_then(Item::as_array)
+		.map(|tags| tags.iter().filter_map(Value::as_str).collect());
+	if current_tags != Some(repo.tags.iter().map(String::as_str).collect()) {
+		set_value(table, "tags", Value::Array(tags_array(&repo.tags)));
+	}
+
+	let inline = table.get("remotes").is_some_and(Item::is_inline_table);
+	if let Some(Item::Table(remotes)) = table.get_mut("remotes") {
+		if remotes.is_empty() {
+			// the explicit `[repos.remotes]` header is only needed while it's empty
+			remotes.set_implicit(!repo.remotes.is_empty());
+		}
+	}
+	let Some(remotes) = table.get_mut("remotes").and_then(Item::as_table_like_mut) else {
+		// remotes are optional, so an entry written without them stays that way until it has some
+		if !repo.remotes.is_empty() {
+			table.insert("remotes", remotes_item(repo));
+		}
+		return;
+	};
+	let unwanted: Vec<String> = remotes
+		.iter()
+		.map(|(name, _)| name.to_string())
+		.filter(|name| !repo.remotes.contains_key(name))
+		.collect();
+	for name in unwanted {
+		remotes.remove(&name);
+	}
+	for (name, remote) in &repo.remotes {
+		match remotes.get_mut(name).and_then(Item::as_table_like_mut) {
+			Some(existing) => {
+				set_string(existing, "name", &remote.name);
+				set_string(existing, "url", &remote.url);
+			}
+			None if inline => {
+				let remote = remote_table(remote).into_inline_table();
+				remotes.insert(name, value(remote));
+			}
+			None => {
+				remotes.insert(name, Item::Table(remote_table(remote)));
+			}
+		}
+	}
+}
+
+/// Set a string value, keeping the existing value's formatting and comments if it changes.
+fn set_string(table: &mut dyn TableLike, key: &str, new_value: &str) {
+	if table.get(key).and_then(Item::as_str) != Some(new_value) {
+		set_value(table, key, Value::from(new_value));
+	}
+}
+
+fn set_value(table: &mut dyn TableLike, key: &str, mut new_value: Value) {
+	if let Some(old_value) = table.get(key).and_then(Item::as_value) {
+		*new_value.decor_mut() = old_value.decor().clone();
+	}
+	table.insert(key, Item::Value(new_value));
+}
+
+fn repo_table(repo: &Repo) -> Table {
+	let mut table = Table::new();
+	table.insert("path", value(repo.path.as_str()));
+	table.insert("tags", value(tags_array(&repo.tags)));
+	table.insert("remotes", remotes_item(repo));
+	table
+}
+
+fn tags_array(tags: &[String]) -> Array {
+	tags.iter().map(String::as_str).collect()
+}
+
+fn remotes_item(repo: &Repo) -> Item {
+	let mut remotes = Table::new();
+	// with no remotes an explicit empty `[repos.remotes]` header is needed for it to exist
+	remotes.set_implicit(!repo.remotes.is_empty());
+	for (name, remote) in &repo.remotes {
+		remotes.insert(name, Item::Table(remote_table(remote)));
+	}
+	Item::Table(remotes)
+}
+
+fn remote_table(remote: &Remote) -> Table {
+	let mut table = Table::new();
+	table.insert("name", value(remote.name.as_str()));
+	table.insert("url", value(remote.url.as_str()));
+	table
+}
@@ -0,0 +1,122 @@
+use crate::git::{Head, RepoStatus};
+use crate::tag_filter::glob_matches;
+
+/// Selects repos by their git state, as read by `Git::status`. A repo must meet every condition
+/// that's set; the default sets none and matches everything.
+///
+/// ```
+/// use gitopolis::state_filter::StateFilter;
+///
+/// // Repos with uncommitted changes on a feature branch
+/// let filter = StateFilter {
+///     dirty: true,
+///     on_branch: Some("feature/*".to_string()),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateFilter {
+	/// Has uncommitted changes to tracked files or a merge, rebase etc. in progress, the same
+	/// repos pull leaves alone as dirty. Untracked files don't count.
+	pub dirty: bool,
+	/// The opposite of `dirty`.
+	pub clean: bool,
+	/// The current branch has commits its upstream doesn't.
+	pub ahead: bool,
+	/// The current branch's upstream has commits it doesn't, as of the last fetch.
+	pub behind: bool,
+	/// The current branch matches this name, which may contain `*` / `?` wildcards.
+	pub on_branch: Option<String>,
+	/// HEAD is detached or on a branch other than the repo's default branch.
+	pub not_on_default_branch: bool,
+}
+
+impl StateFilter {
+	/// True if no conditions are set, so there's no need to read the state of each repo.
+	pub fn is_all(&self) -> bool {
+		*self == Self::default()
+	}
+
+	/// Whether a repo in `status` meets the conditions. `default_branch` is only needed for
+	/// `not_on_default_branch`, which a repo without a known default branch never meets.
+	pub fn matches(&self, status: &RepoStatus, default_branch: Option<&str>) -> bool {
+		let dirty = status.staged > 0 || status.unstaged > 0 || status.in_progress.is_some();
+		let (ahead, behind) = status.ahead_behind.unwrap_or_default();
+		let branch = match &status.head {
+			Head::Branch(name) | Head::Unborn(name) => Some(name.as_str()),
+			Head::Detached(_) => None,
+		};
+		(!self.dirty || dirty)
+			&& (!self.clean || !dirty)
+			&& (!self.ahead || ahead > 0)
+			&& (!self.behind || behind > 0)
+			&& self
+				.on_branch
+				.as_ref()
+				.is_none_or(|pattern| branch.is_some_and(|branch| glob_matches(pattern, branch)))
+			&& (!self.not_on_default_branch
+				|| default_branch.is_some_and(|default_branch| branch != Some(default_branch)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn status(head: Head, ahead_behind: Option<(usize, usize)>, unstaged: usize) -> RepoStatus {
+		RepoStatus {
+			head,
+			ahead_behind,
+			staged: 0,
+			unstaged,
+			untracked: 1,
+			stashes: 0,
+			in_progress: None,
+		}
+	}
+
+	fn branch(name: &str) -> Head {
+		Head::Branch(name.to_string())
+	}
+
+	#[test]
+	fn matches_all_conditions_set() {
+		let dirty_feature = status(branch("feature/login"), Some((2, 0)), 1);
+		let clean_main = status(branch("main"), Some((0, 3)), 0);
+
+		let filter = StateFilter {
+			dirty: true,
+			ahead: true,
+			on_branch: Some("feature/*".to_string()),
+			..Default::default()
+		};
+		assert!(filter.matches(&dirty_feature, None));
+		assert!(!filter.matches(&clean_main, None));
+
+		let filter = StateFilter {
+			clean: true,
+			behind: true,
+			..Default::default()
+		};
+		assert!(!filter.matches(&dirty_feature, None));
+		assert!(filter.matches(&clean_main, None));
+
+		assert!(StateFilter::default().is_all());
+		assert!(!filter.is_all());
+	}
+
+	#[test]
+	fn not_on_default_branch_needs_to_know_the_default() {
+		let filter = StateFilter {
+			not_on_default_branch: true,
+			..Default::default()
+		};
+		assert!(!filter.matches(&status(branch("main"), None, 0), Some("main")));
+		assert!(filter.matches(&status(branch("develop"), None, 0), Some("main")));
+		assert!(filter.matches(
+			&status(Head::Detached("1a2b3c4".to_string()), None, 0),
+			Some("main")
+		));
+		assert!(!filter.matches(&status(branch("develop"), None, 0), None));
+	}
+}
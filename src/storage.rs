@@ -1,15 +1,112 @@
-use std::fs;
+use crate::gitopolis::GitopolisError;
+use crate::gitopolis::GitopolisError::StateError;
+use crate::layers::Layer;
+use log::info;
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 // Abstract away storage to allow testing via dependency injection
 pub trait Storage {
 	fn exists(&self) -> bool;
-	fn save(&self, state_toml: String);
-	fn read(&self) -> String;
+	fn save(&self, state_toml: String) -> Result<(), GitopolisError>;
+	fn read(&self) -> Result<String, GitopolisError>;
+	/// Read the personal overlay or an included file, None if it doesn't exist. The shared state
+	/// file is read with `read`.
+	fn read_layer(&self, _layer: &Layer) -> Result<Option<String>, GitopolisError> {
+		Ok(None)
+	}
+	/// Save the personal overlay, creating it if needed. The shared state file is saved with
+	/// `save`, and included files are never saved.
+	fn save_layer(&self, layer: &Layer, _state_toml: String) -> Result<(), GitopolisError> {
+		Err(StateError {
+			message: format!("Can't save the {layer} layer of the state"),
+		})
+	}
+	/// Stop other gitopolis processes changing the state until the returned lock is dropped,
+	/// so a load-modify-save doesn't lose their changes or they lose ours.
+	fn lock(&self) -> Result<StateLock, GitopolisError> {
+		Ok(StateLock { _file: None })
+	}
+}
+
+/// Held while the state is being changed. Dropping it releases the lock.
+pub struct StateLock {
+	_file: Option<File>,
 }
 
 // The struct used in production code
-pub struct StorageImpl<'a> {
-	pub path: &'a str,
+pub struct StorageImpl {
+	pub path: PathBuf,
+	/// Copy the previous state to `<state file>.bak` before each save.
+	pub backup: bool,
+}
+
+impl StorageImpl {
+	/// Where a layer of the state is stored: `.gitopolis.local.toml` for the personal overlay and
+	/// included files relative to the state file's folder.
+	fn layer_path(&self, layer: &Layer) -> PathBuf {
+		match layer {
+			Layer::Shared => self.path.clone(),
+			Layer::Include(path) => self.path.parent().unwrap_or(Path::new("")).join(path),
+			Layer::Local => {
+				let stem = self.path.file_stem().unwrap_or_default().to_os_string();
+				let mut name = stem;
+				name.push(".local");
+				if let Some(extension) = self.path.extension() {
+					name.push(".");
+					name.push(extension);
+				}
+				self.path.with_file_name(name)
+			}
+		}
+	}
+
+	/// Write to a temporary file next to `path` then rename it into place, so a crash part way
+	/// through leaves either the old state or the new one, never half of it.
+	fn write(&self, path: &Path, state_toml: String) -> Result<(), GitopolisError> {
+		let temp_path = sibling(path, &format!(".{}.tmp", std::process::id()));
+		let write = || -> std::io::Result<()> {
+			let mut file = File::create(&temp_path)?;
+			file.write_all(state_toml.as_bytes())?;
+			file.sync_all()?;
+			if let Ok(metadata) = fs::metadata(path) {
+				fs::set_permissions(&temp_path, metadata.permissions())?;
+			}
+			Ok(())
+		};
+		if let Err(error) = write() {
+			let _ = fs::remove_file(&temp_path);
+			return Err(self.error("write", &temp_path, error));
+		}
+		if self.backup && path.exists() {
+			let backup_path = sibling(path, ".bak");
+			fs::copy(path, &backup_path)
+				.map_err(|error| self.error("back up state file to", &backup_path, error))?;
+		}
+		fs::rename(&temp_path, path).map_err(|error| {
+			let _ = fs::remove_file(&temp_path);
+			self.error("replace", path, error)
+		})
+	}
+
+	/// The lock file for the state file, the same however the state file's path was given.
+	fn lock_path(&self) -> PathBuf {
+		let folder = match self.path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent,
+			_ => Path::new("."),
+		};
+		let folder = fs::canonicalize(folder).unwrap_or_else(|_| folder.to_path_buf());
+		let path = folder.join(self.path.file_name().unwrap_or_default());
+		env::temp_dir().join(lock_file_name(&path))
+	}
+
+	fn error(&self, action: &str, path: &Path, error: std::io::Error) -> GitopolisError {
+		StateError {
+			message: format!("Failed to {} {}: {}", action, path.display(), error),
+		}
+	}
 }
 
 impl Storage for Box<dyn Storage + 'static> {
@@ -17,27 +114,163 @@ impl Storage for Box<dyn Storage + 'static> {
 		self.as_ref().exists()
 	}
 
-	fn save(&self, state_toml: String) {
+	fn save(&self, state_toml: String) -> Result<(), GitopolisError> {
 		self.as_ref().save(state_toml)
 	}
 
-	fn read(&self) -> String {
+	fn read(&self) -> Result<String, GitopolisError> {
 		self.as_ref().read()
 	}
+
+	fn read_layer(&self, layer: &Layer) -> Result<Option<String>, GitopolisError> {
+		self.as_ref().read_layer(layer)
+	}
+
+	fn save_layer(&self, layer: &Layer, state_toml: String) -> Result<(), GitopolisError> {
+		self.as_ref().save_layer(layer, state_toml)
+	}
+
+	fn lock(&self) -> Result<StateLock, GitopolisError> {
+		self.as_ref().lock()
+	}
 }
 
 // The implementation used in production code
-impl<'a> Storage for StorageImpl<'a> {
+impl Storage for StorageImpl {
 	fn exists(&self) -> bool {
-		std::path::Path::new(self.path).exists()
+		self.path.exists()
+	}
+
+	fn save(&self, state_toml: String) -> Result<(), GitopolisError> {
+		self.write(&self.path, state_toml)
+	}
+
+	fn read(&self) -> Result<String, GitopolisError> {
+		fs::read_to_string(&self.path).map_err(|error| self.error("read", &self.path, error))
 	}
 
-	fn save(&self, state_toml: String) {
-		fs::write(self.path, state_toml)
-			.unwrap_or_else(|_| panic!("Failed to write {}", self.path));
+	fn read_layer(&self, layer: &Layer) -> Result<Option<String>, GitopolisError> {
+		let path = self.layer_path(layer);
+		match fs::read_to_string(&path) {
+			Ok(state_toml) => Ok(Some(state_toml)),
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(error) => Err(self.error("read", &path, error)),
+		}
 	}
 
-	fn read(&self) -> String {
-		fs::read_to_string(self.path).expect("Failed to read state file {}")
+	fn save_layer(&self, layer: &Layer, state_toml: String) -> Result<(), GitopolisError> {
+		match layer {
+			Layer::Local => self.write(&self.layer_path(layer), state_toml),
+			_ => Err(StateError {
+				message: format!("Can't save the {layer} layer of the state"),
+			}),
+		}
+	}
+
+	/// Lock a file in the temp folder named after the state file, rather than the state file
+	/// itself, as saving replaces the state file, and so nothing is left next to a config that's
+	/// usually shared. The lock is advisory: it only keeps out other gitopolis processes.
+	fn lock(&self) -> Result<StateLock, GitopolisError> {
+		let lock_path = self.lock_path();
+		let file = OpenOptions::new()
+			.create(true)
+			.truncate(false)
+			.write(true)
+			.open(&lock_path)
+			// made by another user sharing the temp folder, which can still be locked read-only
+			.or_else(|_| File::open(&lock_path))
+			.map_err(|error| self.error("open lock file", &lock_path, error))?;
+		match file.try_lock() {
+			Ok(()) => {}
+			Err(TryLockError::WouldBlock) => {
+				info!(
+					"Waiting for another gitopolis to finish with {}",
+					self.path.display()
+				);
+				file.lock()
+					.map_err(|error| self.error("lock", &lock_path, error))?;
+			}
+			Err(TryLockError::Error(error)) => return Err(self.error("lock", &lock_path, error)),
+		}
+		Ok(StateLock { _file: Some(file) })
+	}
+}
+
+/// A name for the lock file of the state file at `path`. Hashed with FNV-1a rather than
+/// `DefaultHasher` so every gitopolis build picks the same name.
+fn lock_file_name(path: &Path) -> String {
+	let hash = path
+		.as_os_str()
+		.as_encoded_bytes()
+		.iter()
+		.fold(0xcbf29ce484222325_u64, |hash, byte| {
+			(hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+		});
+	format!("gitopolis-{hash:016x}.lock")
+}
+
+/// Path next to `path` with `suffix` added, e.g. `.gitopolis.toml.bak`.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(suffix);
+	path.with_file_name(name)
+}
+
+/// Find the nearest state file named `file_name` in `start` or any of its parent folders,
+/// the same way git finds the `.git` folder of the repo you are in.
+pub fn find_state_file(start: &Path, file_name: &str) -> Option<PathBuf> {
+	start
+		.ancestors()
+		.map(|folder| folder.join(file_name))
+		.find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn save_replaces_state_and_keeps_backup() {
+		let temp = tempfile::tempdir().unwrap();
+		let storage = StorageImpl {
+			path: temp.path().join(".gitopolis.toml"),
+			backup: true,
+		};
+
+		storage.save("first".to_string()).unwrap();
+		storage.save("second".to_string()).unwrap();
+
+		assert_eq!("second", storage.read().unwrap());
+		let backup = fs::read_to_string(temp.path().join(".gitopolis.toml.bak")).unwrap();
+		assert_eq!("first", backup);
+		let mut names: Vec<String> = fs::read_dir(temp.path())
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+			.collect();
+		names.sort();
+		assert_eq!(vec![".gitopolis.toml", ".gitopolis.toml.bak"], names);
+	}
+
+	#[test]
+	fn lock_is_exclusive_until_dropped() {
+		let temp = tempfile::tempdir().unwrap();
+		let storage = StorageImpl {
+			path: temp.path().join(".gitopolis.toml"),
+			backup: false,
+		};
+		let lock = storage.lock().unwrap();
+		let other = File::open(storage.lock_path()).unwrap();
+		assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+
+		// the same file reached another way shares the lock
+		let same = StorageImpl {
+			path: temp.path().join(".").join(".gitopolis.toml"),
+			backup: false,
+		};
+		assert_eq!(storage.lock_path(), same.lock_path());
+
+		drop(lock);
+		assert!(other.try_lock().is_ok());
+		assert_eq!(0, fs::read_dir(temp.path()).unwrap().count());
 	}
 }
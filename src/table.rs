@@ -0,0 +1,358 @@
+use crate::git::{Branches, Head, RepoStatus};
+use crate::gitopolis::{PullOutcome, RepoBranchesEntry, RepoPullEntry, RepoStatusEntry};
+use crate::output::{format_records, OutputFormat};
+use crate::tag_filter::glob_matches;
+use serde_derive::Serialize;
+
+/// Structured output record for `status`, with either the status fields or an error.
+#[derive(Serialize)]
+struct StatusRecord {
+	path: String,
+	tags: Vec<String>,
+	#[serde(flatten)]
+	status: Option<RepoStatus>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<String>,
+}
+
+/// `status` results as a table with a row per repo, or as records.
+pub fn format_status(entries: Vec<RepoStatusEntry>, output: OutputFormat) -> String {
+	if output != OutputFormat::Text {
+		let records: Vec<StatusRecord> = entries
+			.into_iter()
+			.map(|(repo, result)| {
+				let (status, error) = match result {
+					Ok(status) => (Some(status), None),
+					Err(error) => (None, Some(error.message())),
+				};
+				StatusRecord {
+					path: repo.path,
+					tags: repo.tags,
+					status,
+					error,
+				}
+			})
+			.collect();
+		return format_records(output, &records);
+	}
+	let mut rows = vec![[
+		"REPO",
+		"BRANCH",
+		"AHEAD",
+		"BEHIND",
+		"STAGED",
+		"UNSTAGED",
+		"UNTRACKED",
+		"STASHES",
+		"STATE",
+	]
+	.map(String::from)
+	.to_vec()];
+	for (repo, result) in entries {
+		match result {
+			Ok(repo_status) => rows.push(status_row(repo.path, &repo_status)),
+			Err(error) => rows.push(vec![repo.path, format!("Error: {}", error.message())]),
+		}
+	}
+	format_table(&rows)
+}
+
+/// Structured output record for `pull`, with either the outcome or an error.
+#[derive(Serialize)]
+struct PullRecord {
+	path: String,
+	tags: Vec<String>,
+	#[serde(flatten)]
+	outcome: Option<PullOutcome>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<String>,
+}
+
+/// `pull` results as a table with a row per repo, or as records.
+pub fn format_pull(entries: Vec<RepoPullEntry>, output: OutputFormat) -> String {
+	if output != OutputFormat::Text {
+		let records: Vec<PullRecord> = entries
+			.into_iter()
+			.map(|(repo, result)| {
+				let (outcome, error) = match result {
+					Ok(outcome) => (Some(outcome), None),
+					Err(error) => (None, Some(error.message())),
+				};
+				PullRecord {
+					path: repo.path,
+					tags: repo.tags,
+					outcome,
+					error,
+				}
+			})
+			.collect();
+		return format_records(output, &records);
+	}
+	let mut rows = vec![["REPO", "BRANCH", "OUTCOME", "DETAIL"]
+		.map(String::from)
+		.to_vec()];
+	for (repo, result) in entries {
+		match result {
+			Ok(outcome) => rows.push(pull_row(repo.path, outcome)),
+			Err(error) => rows.push(vec![repo.path, format!("Error: {}", error.message())]),
+		}
+	}
+	format_table(&rows)
+}
+
+/// Structured output record for `branch list`.
+#[derive(Serialize)]
+struct BranchesRecord {
+	path: String,
+	tags: Vec<String>,
+	#[serde(flatten)]
+	branches: Option<Branches>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<String>,
+}
+
+/// `branch list` results, keeping only the branches matching `names` (all if empty), as a
+/// matrix or as records.
+pub fn format_branches(
+	entries: Vec<RepoBranchesEntry>,
+	names: &[String],
+	output: OutputFormat,
+) -> String {
+	let entries = wanted_branches(entries, names);
+	if output != OutputFormat::Text {
+		let records: Vec<BranchesRecord> = entries
+			.into_iter()
+			.map(|(repo, result)| {
+				let (branches, error) = match result {
+					Ok(branches) => (Some(branches), None),
+					Err(error) => (None, Some(error.message())),
+				};
+				BranchesRecord {
+					path: repo.path,
+					tags: repo.tags,
+					branches,
+					error,
+				}
+			})
+			.collect();
+		format_records(output, &records)
+	} else {
+		format_table(&branch_matrix(entries))
+	}
+}
+
+/// Leave out the local and remote branches whose names don't match any of `names`.
+fn wanted_branches(entries: Vec<RepoBranchesEntry>, names: &[String]) -> Vec<RepoBranchesEntry> {
+	let wanted =
+		|branch: &str| names.is_empty() || names.iter().any(|name| glob_matches(name, branch));
+	entries
+		.into_iter()
+		.map(|(repo, result)| {
+			let result = result.map(|branches| Branches {
+				current: branches.current,
+				local: branches.local.into_iter().filter(|b| wanted(b)).collect(),
+				remote: branches
+					.remote
+					.into_iter()
+					.filter(|b| remote_name(b).is_some_and(|name| wanted(&name)))
+					.collect(),
+			});
+			(repo, result)
+		})
+		.collect()
+}
+
+/// The branch name of a remote branch, e.g. `main` for `origin/main`.
+fn remote_name(branch: &str) -> Option<String> {
+	branch.split_once('/').map(|(_, name)| name.to_string())
+}
+
+/// A row per repo and a column per branch name, showing whether each repo has the branch
+/// locally, on a remote or both, with the current branch marked by `*`.
+fn branch_matrix(entries: Vec<RepoBranchesEntry>) -> Vec<Vec<String>> {
+	let mut columns: Vec<String> = entries
+		.iter()
+		.filter_map(|(_, result)| result.as_ref().ok())
+		.flat_map(|branches| {
+			let remote = branches.remote.iter().filter_map(|b| remote_name(b));
+			branches.local.iter().cloned().chain(remote)
+		})
+		.collect();
+	columns.sort();
+	columns.dedup();
+
+	let mut rows = vec![std::iter::once("REPO".to_string())
+		.chain(columns.iter().cloned())
+		.collect::<Vec<_>>()];
+	for (repo, result) in entries {
+		let branches = match result {
+			Ok(branches) => branches,
+			Err(error) => {
+				rows.push(vec![repo.path, format!("Error: {}", error.message())]);
+				continue;
+			}
+		};
+		let mut row = vec![repo.path];
+		for column in &columns {
+			let local = branches.local.contains(column);
+			let remote = branches
+				.remote
+				.iter()
+				.any(|b| remote_name(b).as_ref() == Some(column));
+			let current = if branches.current.as_ref() == Some(column) {
+				"*"
+			} else {
+				""
+			};
+			let cell = match (local, remote) {
+				(true, true) => "both",
+				(true, false) => "local",
+				(false, true) => "remote",
+				(false, false) => "-",
+			};
+			row.push(format!("{current}{cell}"));
+		}
+		rows.push(row);
+	}
+	rows
+}
+
+fn pull_row(path: String, outcome: PullOutcome) -> Vec<String> {
+	let (branch, outcome, detail) = match outcome {
+		PullOutcome::Updated {
+			branch,
+			from,
+			to,
+			commits,
+		} => {
+			let plural = if commits == 1 { "" } else { "s" };
+			let detail = format!("{from}..{to} ({commits} commit{plural})");
+			(branch, "updated", detail)
+		}
+		PullOutcome::UpToDate { branch } => (branch, "up to date", String::new()),
+		PullOutcome::Diverged {
+			branch,
+			ahead,
+			behind,
+		} => (
+			branch,
+			"diverged",
+			format!("{ahead} ahead, {behind} behind"),
+		),
+		PullOutcome::Dirty { branch } => (branch, "dirty", "uncommitted changes".to_string()),
+		PullOutcome::Detached { commit } => {
+			(format!("(detached {commit})"), "detached", String::new())
+		}
+		PullOutcome::NoUpstream { branch } => (branch, "no upstream", String::new()),
+	};
+	vec![path, branch, outcome.to_string(), detail]
+}
+
+fn status_row(path: String, repo_status: &RepoStatus) -> Vec<String> {
+	let branch = match &repo_status.head {
+		Head::Branch(name) => name.to_string(),
+		Head::Unborn(name) => format!("{name} (no commits)"),
+		Head::Detached(commit) => format!("(detached {commit})"),
+	};
+	let (ahead, behind) = match repo_status.ahead_behind {
+		Some((ahead, behind)) => (ahead.to_string(), behind.to_string()),
+		None => ("-".to_string(), "-".to_string()),
+	};
+	vec![
+		path,
+		branch,
+		ahead,
+		behind,
+		repo_status.staged.to_string(),
+		repo_status.unstaged.to_string(),
+		repo_status.untracked.to_string(),
+		repo_status.stashes.to_string(),
+		repo_status.in_progress.clone().unwrap_or_default(),
+	]
+}
+
+/// Lay out rows, a line each, with each column padded to the width of its widest cell. Rows may
+/// have fewer cells than others, e.g. an error message in place of the remaining columns.
+fn format_table(rows: &[Vec<String>]) -> String {
+	let mut widths: Vec<usize> = vec![];
+	for row in rows {
+		for (ix, cell) in row.iter().enumerate() {
+			let width = cell.chars().count();
+			if ix >= widths.len() {
+				widths.push(width);
+			} else if width > widths[ix] {
+				widths[ix] = width;
+			}
+		}
+	}
+	rows.iter()
+		.map(|row| {
+			let line = row
+				.iter()
+				.enumerate()
+				.map(|(ix, cell)| format!("{:<width$}", cell, width = widths[ix]))
+				.collect::<Vec<_>>()
+				.join("  ");
+			format!("{}\n", line.trim_end())
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gitopolis::GitopolisError;
+	use crate::repos::Repo;
+
+	fn row(cells: &[&str]) -> Vec<String> {
+		cells.iter().map(|cell| cell.to_string()).collect()
+	}
+
+	#[test]
+	fn pads_columns_to_the_widest_cell() {
+		let rows = vec![
+			row(&["REPO", "BRANCH"]),
+			row(&["välkommen", "main"]),
+			row(&["b", "Error: can't read"]),
+			row(&["short"]),
+		];
+		assert_eq!(
+			"REPO       BRANCH\nvälkommen  main\nb          Error: can't read\nshort\n",
+			format_table(&rows)
+		);
+	}
+
+	#[test]
+	fn branch_matrix_has_a_column_per_wanted_branch() {
+		let repo = |path: &str| Repo {
+			path: path.to_string(),
+			tags: vec![],
+			remotes: Default::default(),
+		};
+		let entries = vec![
+			(
+				repo("api"),
+				Ok(Branches {
+					current: Some("main".to_string()),
+					local: vec!["main".to_string(), "feature/a".to_string()],
+					remote: vec!["origin/main".to_string(), "origin/old".to_string()],
+				}),
+			),
+			(
+				repo("web"),
+				Err(GitopolisError::GitError {
+					message: "not a repo".to_string(),
+				}),
+			),
+		];
+		let names = vec!["main".to_string(), "feature/*".to_string()];
+		assert_eq!(
+			vec![
+				row(&["REPO", "feature/a", "main"]),
+				row(&["api", "local", "*both"]),
+				row(&["web", "Error: not a repo"]),
+			],
+			branch_matrix(wanted_branches(entries, &names))
+		);
+	}
+}
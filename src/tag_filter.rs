@@ -1,8 +1,15 @@
-/// A filter for repositories based on tag matching with AND/OR logic.
+use crate::gitopolis::GitopolisError;
+
+/// A filter for repositories based on tag matching.
 ///
-/// Tag filtering works as follows:
-/// - Each inner Vec represents tags that must ALL be present (AND logic)
-/// - Different inner Vecs are ORed together
+/// Filters are boolean expressions over tags, built either from `--tag` arguments or by parsing
+/// a `--filter` expression:
+/// - From `--tag`: comma-separated tags within one argument must ALL be present (AND logic),
+///   and different arguments are ORed together
+/// - From `--filter`: `and`, `or`, `not` (or `&`, `|`, `!`) and parentheses, e.g.
+///   `(rust or go) and not archived`. `,` is a shorthand for `and`.
+/// - Tags in an expression may contain `*` and `?` wildcards, e.g. `team-*`. Tags from `--tag`
+///   are matched exactly, so existing tags containing those characters only match themselves
 /// - Empty filter matches all repos
 ///
 /// # Examples
@@ -18,18 +25,47 @@
 ///
 /// // Match repos with (foo AND bar) OR (baz AND boz)
 /// let filter = TagFilter::from_cli_args(&["foo,bar".to_string(), "baz,boz".to_string()]);
+///
+/// // Match backend repos that aren't archived
+/// let filter = TagFilter::parse("backend and not archived").unwrap();
+/// assert!(filter.matches(&["backend".to_string()]));
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TagFilter {
-	tag_groups: Vec<Vec<String>>,
+	/// None matches everything
+	expression: Option<TagExpression>,
+}
+
+/// Parsed tag filter expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TagExpression {
+	/// Tag name, matched exactly
+	Tag(String),
+	/// Tag name that may contain `*` / `?` wildcards
+	Pattern(String),
+	Not(Box<TagExpression>),
+	And(Vec<TagExpression>),
+	Or(Vec<TagExpression>),
+}
+
+impl TagExpression {
+	fn matches(&self, repo_tags: &[String]) -> bool {
+		match self {
+			TagExpression::Tag(name) => repo_tags.contains(name),
+			TagExpression::Pattern(pattern) => {
+				repo_tags.iter().any(|tag| glob_matches(pattern, tag))
+			}
+			TagExpression::Not(inner) => !inner.matches(repo_tags),
+			TagExpression::And(items) => items.iter().all(|e| e.matches(repo_tags)),
+			TagExpression::Or(items) => items.iter().any(|e| e.matches(repo_tags)),
+		}
+	}
 }
 
 impl TagFilter {
 	/// Create a filter that matches all repositories (no filtering)
 	pub fn all() -> Self {
-		Self {
-			tag_groups: Vec::new(),
-		}
+		Self { expression: None }
 	}
 
 	/// Create a filter from CLI tag arguments.
@@ -43,33 +79,192 @@ impl TagFilter {
 		let tag_groups = tag_args
 			.iter()
 			.map(|tag_str| {
-				tag_str
-					.split(',')
-					.map(|s| s.trim().to_string())
-					.collect::<Vec<String>>()
+				TagExpression::And(
+					tag_str
+						.split(',')
+						.map(|s| TagExpression::Tag(s.trim().to_string()))
+						.collect(),
+				)
 			})
 			.collect();
 
-		Self { tag_groups }
+		Self {
+			expression: Some(TagExpression::Or(tag_groups)),
+		}
+	}
+
+	/// Parse a boolean tag expression such as `(rust or go) and team-payments and not archived`.
+	pub fn parse(expression: &str) -> Result<Self, GitopolisError> {
+		let tokens = tokenize(expression);
+		if tokens.is_empty() {
+			return Ok(Self::all());
+		}
+		let mut parser = Parser {
+			source: expression,
+			tokens,
+			position: 0,
+		};
+		let parsed = parser.or()?;
+		if let Some(token) = parser.peek() {
+			return Err(parser.error(&format!("unexpected '{}'", token)));
+		}
+		Ok(Self {
+			expression: Some(parsed),
+		})
+	}
+
+	/// Combine with another filter so that both must match.
+	pub fn and(self, other: TagFilter) -> Self {
+		match (self.expression, other.expression) {
+			(Some(left), Some(right)) => Self {
+				expression: Some(TagExpression::And(vec![left, right])),
+			},
+			(left, right) => Self {
+				expression: left.or(right),
+			},
+		}
 	}
 
 	/// Check if this filter matches a repository with the given tags.
 	pub fn matches(&self, repo_tags: &[String]) -> bool {
-		if self.tag_groups.is_empty() {
-			// No filter, match everything
-			return true;
+		match &self.expression {
+			None => true, // No filter, match everything
+			Some(expression) => expression.matches(repo_tags),
 		}
-
-		// Check if repo matches ANY of the tag groups (OR)
-		self.tag_groups.iter().any(|tag_group| {
-			// Check if repo has ALL tags in this group (AND)
-			tag_group.iter().all(|tag| repo_tags.contains(tag))
-		})
 	}
 
 	/// Returns true if this is an "all" filter (no filtering)
 	pub fn is_all(&self) -> bool {
-		self.tag_groups.is_empty()
+		self.expression.is_none()
+	}
+}
+
+/// Match a name against a pattern where `*` matches any run of characters and `?` any one.
+pub fn glob_matches(pattern: &str, tag: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let tag: Vec<char> = tag.chars().collect();
+	let (mut p, mut t) = (0, 0);
+	// Position of the last `*` seen and the tag position it was tried against, for backtracking
+	let mut star: Option<(usize, usize)> = None;
+	while t < tag.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == tag[t]) {
+			p += 1;
+			t += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			star = Some((p, t));
+			p += 1;
+		} else if let Some((star_p, star_t)) = star {
+			// let the last `*` swallow one more character and try again
+			p = star_p + 1;
+			t = star_t + 1;
+			star = Some((star_p, star_t + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn tokenize(expression: &str) -> Vec<String> {
+	let mut tokens = vec![];
+	let mut word = String::new();
+	for c in expression.chars() {
+		if c.is_whitespace() || "()!&|,".contains(c) {
+			if !word.is_empty() {
+				tokens.push(std::mem::take(&mut word));
+			}
+			if !c.is_whitespace() {
+				tokens.push(c.to_string());
+			}
+		} else {
+			word.push(c);
+		}
+	}
+	if !word.is_empty() {
+		tokens.push(word);
+	}
+	tokens
+}
+
+/// Recursive descent parser, lowest precedence first: or, and, not.
+struct Parser<'a> {
+	source: &'a str,
+	tokens: Vec<String>,
+	position: usize,
+}
+
+impl Parser<'_> {
+	fn peek(&self) -> Option<&str> {
+		self.tokens.get(self.position).map(|t| t.as_str())
+	}
+
+	fn next_is(&self, options: &[&str]) -> bool {
+		self.peek()
+			.is_some_and(|t| options.iter().any(|o| t.eq_ignore_ascii_case(o)))
+	}
+
+	fn error(&self, problem: &str) -> GitopolisError {
+		GitopolisError::FilterError {
+			message: format!(
+				"Invalid filter expression '{}': {}",
+				self.source.trim(),
+				problem
+			),
+		}
+	}
+
+	fn or(&mut self) -> Result<TagExpression, GitopolisError> {
+		let mut items = vec![self.and()?];
+		while self.next_is(&["or", "|"]) {
+			self.position += 1;
+			items.push(self.and()?);
+		}
+		Ok(if items.len() == 1 {
+			items.remove(0)
+		} else {
+			TagExpression::Or(items)
+		})
+	}
+
+	fn and(&mut self) -> Result<TagExpression, GitopolisError> {
+		let mut items = vec![self.not()?];
+		while self.next_is(&["and", "&", ","]) {
+			self.position += 1;
+			items.push(self.not()?);
+		}
+		Ok(if items.len() == 1 {
+			items.remove(0)
+		} else {
+			TagExpression::And(items)
+		})
+	}
+
+	fn not(&mut self) -> Result<TagExpression, GitopolisError> {
+		if self.next_is(&["not", "!"]) {
+			self.position += 1;
+			return Ok(TagExpression::Not(Box::new(self.not()?)));
+		}
+		self.primary()
+	}
+
+	fn primary(&mut self) -> Result<TagExpression, GitopolisError> {
+		let Some(token) = self.peek().map(|t| t.to_string()) else {
+			return Err(self.error("expression ends too soon"));
+		};
+		if token == "(" {
+			self.position += 1;
+			let inner = self.or()?;
+			if self.peek() != Some(")") {
+				return Err(self.error("missing ')'"));
+			}
+			self.position += 1;
+			return Ok(inner);
+		}
+		if token == ")" || self.next_is(&["and", "or", "&", "|", ","]) {
+			return Err(self.error(&format!("expected a tag but found '{}'", token)));
+		}
+		self.position += 1;
+		Ok(TagExpression::Pattern(token))
 	}
 }
 
@@ -132,9 +327,119 @@ mod tests {
 		assert!(!filter.matches(&["baz".to_string()]));
 	}
 
+	#[test]
+	fn cli_tags_with_glob_characters_match_exactly() {
+		let filter = TagFilter::from_cli_args(&["team-*".to_string(), "v?".to_string()]);
+		assert!(filter.matches(&["team-*".to_string()]));
+		assert!(filter.matches(&["v?".to_string()]));
+		assert!(!filter.matches(&["team-payments".to_string()]));
+		assert!(!filter.matches(&["v2".to_string()]));
+	}
+
 	#[test]
 	fn whitespace_is_trimmed() {
 		let filter = TagFilter::from_cli_args(&[" foo , bar ".to_string()]);
 		assert!(filter.matches(&["foo".to_string(), "bar".to_string()]));
 	}
+
+	fn tags(tags: &[&str]) -> Vec<String> {
+		tags.iter().map(|t| t.to_string()).collect()
+	}
+
+	#[test]
+	fn parse_and_not() {
+		let filter = TagFilter::parse("backend and not archived").unwrap();
+		assert!(filter.matches(&tags(&["backend"])));
+		assert!(!filter.matches(&tags(&["backend", "archived"])));
+		assert!(!filter.matches(&tags(&["frontend"])));
+	}
+
+	#[test]
+	fn parse_parentheses_and_precedence() {
+		let filter = TagFilter::parse("(rust or go) and team-payments").unwrap();
+		assert!(filter.matches(&tags(&["rust", "team-payments"])));
+		assert!(filter.matches(&tags(&["go", "team-payments"])));
+		assert!(!filter.matches(&tags(&["rust"])));
+		assert!(!filter.matches(&tags(&["team-payments"])));
+
+		// and binds tighter than or
+		let filter = TagFilter::parse("rust or go and team-payments").unwrap();
+		assert!(filter.matches(&tags(&["rust"])));
+		assert!(!filter.matches(&tags(&["go"])));
+	}
+
+	#[test]
+	fn parse_symbols_and_keyword_case() {
+		let filter = TagFilter::parse("!archived&(rust|go)").unwrap();
+		assert_eq!(
+			TagFilter::parse("NOT archived AND (rust OR go)").unwrap(),
+			filter
+		);
+		assert!(filter.matches(&tags(&["go"])));
+		assert!(!filter.matches(&tags(&["go", "archived"])));
+	}
+
+	#[test]
+	fn parse_comma_is_and() {
+		assert_eq!(
+			TagFilter::parse("foo and bar").unwrap(),
+			TagFilter::parse("foo,bar").unwrap()
+		);
+	}
+
+	#[test]
+	fn parse_glob() {
+		let filter = TagFilter::parse("team-* and not team-legacy").unwrap();
+		assert!(filter.matches(&tags(&["team-payments"])));
+		assert!(!filter.matches(&tags(&["team-legacy"])));
+		assert!(!filter.matches(&tags(&["teams"])));
+	}
+
+	#[test]
+	fn parse_empty_is_all() {
+		assert!(TagFilter::parse("  ").unwrap().is_all());
+	}
+
+	#[test]
+	fn parse_errors() {
+		for expression in [
+			"(rust or go",
+			"rust and",
+			"and rust",
+			"rust go",
+			"rust )",
+			"not",
+		] {
+			let error = TagFilter::parse(expression).expect_err(expression);
+			assert!(
+				error.message().starts_with("Invalid filter expression"),
+				"{}",
+				error.message()
+			);
+		}
+	}
+
+	#[test]
+	fn and_combines_filters() {
+		let filter = TagFilter::from_cli_args(&["backend".to_string()])
+			.and(TagFilter::parse("not archived").unwrap());
+		assert!(filter.matches(&tags(&["backend"])));
+		assert!(!filter.matches(&tags(&["backend", "archived"])));
+		assert!(TagFilter::all().and(TagFilter::all()).is_all());
+		assert!(!TagFilter::all()
+			.and(TagFilter::parse("foo").unwrap())
+			.is_all());
+	}
+
+	#[test]
+	fn glob_matching() {
+		assert!(glob_matches("foo", "foo"));
+		assert!(!glob_matches("foo", "food"));
+		assert!(glob_matches("foo*", "food"));
+		assert!(glob_matches("*", ""));
+		assert!(glob_matches("f?o", "foo"));
+		assert!(glob_matches("*-api-*", "team-api-v2"));
+		assert!(!glob_matches("*-api-*", "team-api"));
+		assert!(glob_matches("a*b*c", "aXXbYYbc"));
+	}
 }
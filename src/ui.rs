@@ -0,0 +1,892 @@
+use crate::exec::{ExecOptions, ExecOutcome, OutputStream};
+use crate::git::{FetchOptions, Head, RepoStatus};
+use crate::gitopolis::GitopolisError::IoError;
+use crate::gitopolis::{Gitopolis, GitopolisError, PullOutcome};
+use crate::observer::{Event, Observer, Operation, RepoOutcome};
+use crate::repos::Repo;
+use crate::tag_filter::TagFilter;
+use ratatui::crossterm::event::{self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Lines of output kept for each repo.
+const OUTPUT_LIMIT: usize = 1000;
+/// How often the screen is redrawn while an operation is sending output.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run the full-screen interface until the user quits. Lists the repos matching `filter`,
+/// reads their status while nothing else is running, and runs fetch, pull and commands on the
+/// selected repos `parallel` at a time, showing each repo's output as it arrives. Operations run
+/// on this thread while keys are read on another, so the screen can be used, and a command
+/// cancelled, while they run.
+pub fn run(gitopolis: Gitopolis, filter: TagFilter, parallel: usize) -> Result<(), GitopolisError> {
+	let repos = gitopolis.list(&TagFilter::all())?;
+	let screen = Arc::new(Screen {
+		app: Mutex::new(App::new(repos, filter)),
+		terminal: Mutex::new(ratatui::init()),
+		last_draw: Mutex::new(Instant::now()),
+	});
+	// log lines would be written over the screen
+	let log_level = log::max_level();
+	log::set_max_level(log::LevelFilter::Off);
+	let gitopolis = gitopolis.with_observer(Box::new(UiObserver(screen.clone())));
+	let cancel = Arc::new(AtomicBool::new(false));
+	let stop = AtomicBool::new(false);
+	let (sender, receiver) = mpsc::channel();
+	let result = thread::scope(|scope| {
+		let keys = scope.spawn(|| read_keys(&screen, sender, &cancel, &stop));
+		let result = run_jobs(&gitopolis, &screen, receiver, &cancel, parallel);
+		stop.store(true, Ordering::SeqCst);
+		let keys_result = keys
+			.join()
+			.unwrap_or_else(|_| Err(io::Error::other("Reading keys failed")));
+		result.and(keys_result)
+	});
+	ratatui::restore();
+	log::set_max_level(log_level);
+	result.map_err(|inner| IoError { inner })
+}
+
+/// Work for the thread running operations, sent by the thread reading keys.
+enum Job {
+	Fetch(Vec<Repo>),
+	Pull(Vec<Repo>),
+	Exec(String, Vec<Repo>),
+	/// Stop once the operation before this one is done.
+	Quit,
+}
+
+/// Run jobs as they arrive, reading the status of each repo waiting for one in between.
+/// Returns on `Job::Quit`, or once the key reader has gone.
+fn run_jobs(
+	gitopolis: &Gitopolis,
+	screen: &Screen,
+	jobs: Receiver<Job>,
+	cancel: &Arc<AtomicBool>,
+	parallel: usize,
+) -> io::Result<()> {
+	loop {
+		screen.draw()?;
+		let job = match jobs.try_recv() {
+			Ok(job) => job,
+			Err(TryRecvError::Disconnected) => return Ok(()),
+			Err(TryRecvError::Empty) => {
+				let next_status = screen.app().next_pending_status();
+				if let Some(repo) = next_status {
+					let status = gitopolis
+						.repo_status(&repo)
+						.map_err(|error| error.message());
+					screen.app().set_status(&repo.path, status);
+					continue;
+				}
+				match jobs.recv_timeout(Duration::from_millis(250)) {
+					Ok(job) => job,
+					Err(RecvTimeoutError::Timeout) => continue,
+					Err(RecvTimeoutError::Disconnected) => return Ok(()),
+				}
+			}
+		};
+		// the app isn't locked while an operation runs, so the observer can update it
+		let repos = match job {
+			Job::Quit => return Ok(()),
+			Job::Fetch(repos) => {
+				gitopolis.fetch(repos.clone(), &FetchOptions::default(), parallel);
+				repos
+			}
+			Job::Pull(repos) => {
+				gitopolis.pull(repos.clone(), parallel);
+				repos
+			}
+			Job::Exec(command, repos) => {
+				let options = ExecOptions {
+					cancel: cancel.clone(),
+					..Default::default()
+				};
+				gitopolis.exec(&[command], repos.clone(), &options, parallel);
+				repos
+			}
+		};
+		cancel.store(false, Ordering::SeqCst);
+		screen.app().operation_finished(&repos);
+	}
+}
+
+/// Handle key presses, passing operations to `run_jobs`, until the user quits or `stop` is set.
+fn read_keys(
+	screen: &Screen,
+	jobs: Sender<Job>,
+	cancel: &AtomicBool,
+	stop: &AtomicBool,
+) -> io::Result<()> {
+	// dropping `jobs` on the way out tells run_jobs to stop too
+	while !stop.load(Ordering::SeqCst) {
+		if !event::poll(Duration::from_millis(100))? {
+			continue;
+		}
+		let event::Event::Key(key) = event::read()? else {
+			screen.draw()?; // e.g. resized
+			continue;
+		};
+		let action = screen.app().handle_key(key);
+		let job = match action {
+			None => None,
+			Some(Action::Quit) => {
+				cancel.store(true, Ordering::SeqCst);
+				Some(Job::Quit)
+			}
+			Some(Action::Cancel) => {
+				cancel.store(true, Ordering::SeqCst);
+				None
+			}
+			Some(Action::Refresh(repos)) => {
+				screen.app().queue_status(&repos);
+				None
+			}
+			Some(Action::Fetch(repos)) => Some(Job::Fetch(repos)),
+			Some(Action::Pull(repos)) => Some(Job::Pull(repos)),
+			Some(Action::Exec(command, repos)) => Some(Job::Exec(command, repos)),
+		};
+		let quit = matches!(job, Some(Job::Quit));
+		if let Some(job) = job {
+			if jobs.send(job).is_err() {
+				break;
+			}
+		}
+		if quit {
+			break;
+		}
+		screen.draw()?;
+	}
+	Ok(())
+}
+
+struct Screen {
+	app: Mutex<App>,
+	terminal: Mutex<DefaultTerminal>,
+	last_draw: Mutex<Instant>,
+}
+
+impl Screen {
+	/// A panic while the app was locked leaves it in whatever state it got to, which is still
+	/// worth showing, so a poisoned lock is used anyway.
+	fn app(&self) -> MutexGuard<'_, App> {
+		self.app.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn draw(&self) -> io::Result<()> {
+		let mut app = self.app();
+		self.terminal
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.draw(|frame| app.render(frame))?;
+		*self
+			.last_draw
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = Instant::now();
+		Ok(())
+	}
+}
+
+/// Records what operations report into the app and redraws, so output appears while the
+/// operation is still running.
+struct UiObserver(Arc<Screen>);
+
+impl Observer for UiObserver {
+	fn event(&self, event: Event) {
+		let finished = matches!(event, Event::OperationFinished { .. });
+		self.0.app().record(event);
+		let last_draw = *self
+			.0
+			.last_draw
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		if finished || last_draw.elapsed() >= REDRAW_INTERVAL {
+			// nowhere to report a failed draw, and the next one will try again
+			let _ = self.0.draw();
+		}
+	}
+}
+
+/// What a key press asks for.
+#[derive(Debug)]
+pub enum Action {
+	/// Quit, once any running operation has stopped.
+	Quit,
+	/// Stop the running command in the repos it hasn't finished.
+	Cancel,
+	Refresh(Vec<Repo>),
+	Fetch(Vec<Repo>),
+	Pull(Vec<Repo>),
+	/// Run a command line with the shell in each repo, as exec does with a single argument.
+	Exec(String, Vec<Repo>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+	Browse,
+	/// Typing a tag expression, which is applied as it changes.
+	Filter,
+	/// Typing a command line to run in the selected repos.
+	Command(String),
+}
+
+enum RepoState {
+	Loading,
+	Loaded(RepoStatus),
+	Error(String),
+}
+
+/// Everything shown on screen, changed by key presses and operation events.
+pub struct App {
+	repos: Vec<Repo>,
+	statuses: BTreeMap<String, RepoState>,
+	/// Filter from the command line, always applied.
+	base_filter: TagFilter,
+	filter_text: String,
+	filter_error: Option<String>,
+	/// Indexes into `repos` of those matching the filter.
+	visible: Vec<usize>,
+	/// Index into `visible` of the highlighted repo.
+	cursor: usize,
+	selected: BTreeSet<String>,
+	mode: Mode,
+	output: BTreeMap<String, Vec<String>>,
+	message: String,
+	pending_status: VecDeque<String>,
+	/// The operation running, if any. Only one runs at a time.
+	running: Option<Operation>,
+}
+
+impl App {
+	pub fn new(repos: Vec<Repo>, base_filter: TagFilter) -> Self {
+		let pending_status = repos.iter().map(|repo| repo.path.clone()).collect();
+		let statuses = repos
+			.iter()
+			.map(|repo| (repo.path.clone(), RepoState::Loading))
+			.collect();
+		let mut app = App {
+			repos,
+			statuses,
+			base_filter,
+			filter_text: String::new(),
+			filter_error: None,
+			visible: vec![],
+			cursor: 0,
+			selected: BTreeSet::new(),
+			mode: Mode::Browse,
+			output: BTreeMap::new(),
+			message: String::new(),
+			pending_status,
+			running: None,
+		};
+		app.apply_filter();
+		app
+	}
+
+	/// Paths of the repos shown, in list order.
+	pub fn visible_paths(&self) -> Vec<&str> {
+		self.visible
+			.iter()
+			.map(|&ix| self.repos[ix].path.as_str())
+			.collect()
+	}
+
+	pub fn selected_paths(&self) -> Vec<&str> {
+		self.selected.iter().map(String::as_str).collect()
+	}
+
+	pub fn mode(&self) -> &Mode {
+		&self.mode
+	}
+
+	pub fn filter_error(&self) -> Option<&str> {
+		self.filter_error.as_deref()
+	}
+
+	pub fn output(&self, path: &str) -> &[String] {
+		self.output.get(path).map(Vec::as_slice).unwrap_or_default()
+	}
+
+	/// What a key press asks for. While an operation is running, ctrl-c and esc cancel it
+	/// rather than quitting, and keys that would start another operation are ignored.
+	pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+		if key.kind != KeyEventKind::Press {
+			return None;
+		}
+		let ctrl_c =
+			key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+		if let Some(operation) = self.running {
+			if ctrl_c || (self.mode == Mode::Browse && key.code == KeyCode::Esc) {
+				self.message = match operation {
+					Operation::Exec => "Cancelling…".to_string(),
+					_ => format!("{operation:?} can't be cancelled, waiting for it to finish…"),
+				};
+				return Some(Action::Cancel);
+			}
+		}
+		if ctrl_c {
+			return Some(Action::Quit);
+		}
+		let action = self.key_action(key);
+		let operation = match &action {
+			Some(Action::Fetch(_)) => Operation::Fetch,
+			Some(Action::Pull(_)) => Operation::Pull,
+			Some(Action::Exec(..)) => Operation::Exec,
+			Some(Action::Quit) if self.running.is_some() => {
+				self.message = "Quitting once the running operation stops…".to_string();
+				return action;
+			}
+			_ => return action,
+		};
+		if self.running.is_some() {
+			self.message =
+				"Wait for the running operation to finish, or esc to cancel it".to_string();
+			return None;
+		}
+		self.running = Some(operation);
+		action
+	}
+
+	/// An operation started by a key press is done, so read the status of its repos again.
+	fn operation_finished(&mut self, repos: &[Repo]) {
+		self.running = None;
+		self.queue_status(repos);
+	}
+
+	fn key_action(&mut self, key: KeyEvent) -> Option<Action> {
+		match self.mode.clone() {
+			Mode::Browse => self.browse_key(key.code),
+			Mode::Filter => {
+				match key.code {
+					KeyCode::Enter => self.mode = Mode::Browse,
+					KeyCode::Esc => {
+						self.mode = Mode::Browse;
+						self.filter_text.clear();
+						self.apply_filter();
+					}
+					KeyCode::Backspace => {
+						self.filter_text.pop();
+						self.apply_filter();
+					}
+					KeyCode::Char(c) => {
+						self.filter_text.push(c);
+						self.apply_filter();
+					}
+					_ => {}
+				}
+				None
+			}
+			Mode::Command(mut command) => {
+				match key.code {
+					KeyCode::Enter => {
+						self.mode = Mode::Browse;
+						if !command.trim().is_empty() {
+							return Some(Action::Exec(command, self.targets()));
+						}
+					}
+					KeyCode::Esc => self.mode = Mode::Browse,
+					KeyCode::Backspace => {
+						command.pop();
+						self.mode = Mode::Command(command);
+					}
+					KeyCode::Char(c) => {
+						command.push(c);
+						self.mode = Mode::Command(command);
+					}
+					_ => {}
+				}
+				None
+			}
+		}
+	}
+
+	fn browse_key(&mut self, code: KeyCode) -> Option<Action> {
+		match code {
+			KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+			KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+			KeyCode::Down | KeyCode::Char('j') => {
+				self.cursor = (self.cursor + 1).min(self.visible.len().saturating_sub(1))
+			}
+			KeyCode::Char(' ') => {
+				if let Some(path) = self.highlighted().map(|repo| repo.path.clone()) {
+					if !self.selected.remove(&path) {
+						self.selected.insert(path);
+					}
+				}
+			}
+			KeyCode::Char('a') => {
+				let visible: BTreeSet<String> = self
+					.visible_paths()
+					.into_iter()
+					.map(str::to_string)
+					.collect();
+				// select all shown, or clear the selection if they already are
+				if visible.is_subset(&self.selected) {
+					self.selected.clear();
+				} else {
+					self.selected.extend(visible);
+				}
+			}
+			KeyCode::Char('/') => self.mode = Mode::Filter,
+			KeyCode::Char('e') => self.mode = Mode::Command(String::new()),
+			KeyCode::Char('f') => return self.with_targets(Action::Fetch),
+			KeyCode::Char('p') => return self.with_targets(Action::Pull),
+			KeyCode::Char('r') => return self.with_targets(Action::Refresh),
+			_ => {}
+		}
+		None
+	}
+
+	fn with_targets(&self, action: fn(Vec<Repo>) -> Action) -> Option<Action> {
+		let targets = self.targets();
+		(!targets.is_empty()).then(|| action(targets))
+	}
+
+	/// The selected repos that are shown, or the highlighted one if none are selected.
+	fn targets(&self) -> Vec<Repo> {
+		let selected: Vec<Repo> = self
+			.visible
+			.iter()
+			.map(|&ix| &self.repos[ix])
+			.filter(|repo| self.selected.contains(&repo.path))
+			.cloned()
+			.collect();
+		if selected.is_empty() {
+			self.highlighted().cloned().into_iter().collect()
+		} else {
+			selected
+		}
+	}
+
+	fn highlighted(&self) -> Option<&Repo> {
+		self.visible.get(self.cursor).map(|&ix| &self.repos[ix])
+	}
+
+	/// Show the repos matching the typed expression and the command line filter. While the
+	/// expression doesn't parse, e.g. half-typed, the last list shown stays.
+	fn apply_filter(&mut self) {
+		let filter = if self.filter_text.trim().is_empty() {
+			Ok(self.base_filter.clone())
+		} else {
+			TagFilter::parse(&self.filter_text).map(|typed| self.base_filter.clone().and(typed))
+		};
+		match filter {
+			Ok(filter) => {
+				self.filter_error = None;
+				self.visible = (0..self.repos.len())
+					.filter(|&ix| filter.matches(&self.repos[ix].tags))
+					.collect();
+				self.cursor = self.cursor.min(self.visible.len().saturating_sub(1));
+			}
+			Err(error) => self.filter_error = Some(error.message()),
+		}
+	}
+
+	fn next_pending_status(&mut self) -> Option<Repo> {
+		let path = self.pending_status.pop_front()?;
+		self.repos.iter().find(|repo| repo.path == path).cloned()
+	}
+
+	fn queue_status(&mut self, repos: &[Repo]) {
+		for repo in repos {
+			if !self.pending_status.contains(&repo.path) {
+				self.pending_status.push_back(repo.path.clone());
+			}
+		}
+	}
+
+	fn set_status(&mut self, path: &str, status: Result<RepoStatus, String>) {
+		let state = match status {
+			Ok(status) => RepoState::Loaded(status),
+			Err(message) => RepoState::Error(message),
+		};
+		self.statuses.insert(path.to_string(), state);
+	}
+
+	/// Keep what an operation reports: each repo's output replaces what it had from the last
+	/// operation, ending with a line saying how it went.
+	pub fn record(&mut self, event: Event) {
+		match event {
+			Event::OperationStarted {
+				operation,
+				repo_count,
+				..
+			} => {
+				let plural = if repo_count == 1 { "" } else { "s" };
+				self.message = format!("{operation:?} in {repo_count} repo{plural}…");
+			}
+			Event::RepoStarted { repo } => {
+				self.output.insert(repo.path.clone(), vec![]);
+			}
+			Event::OutputLine { repo, stream, line } => {
+				let line = match stream {
+					OutputStream::Stdout => line.to_string(),
+					OutputStream::Stderr => format!("! {line}"),
+				};
+				self.push_output(&repo.path, line);
+			}
+			Event::CloneProgress { .. } => {}
+			Event::RepoFinished { repo, outcome } => {
+				if let Some(line) = describe(&outcome) {
+					self.push_output(&repo.path, line);
+				}
+			}
+			Event::OperationFinished { operation } => {
+				self.message = format!("{operation:?} finished");
+			}
+			Event::Warning { message } => self.message = format!("Warning: {message}"),
+		}
+	}
+
+	fn push_output(&mut self, path: &str, line: String) {
+		let lines = self.output.entry(path.to_string()).or_default();
+		lines.push(line);
+		if lines.len() > OUTPUT_LIMIT {
+			lines.remove(0);
+		}
+	}
+
+	pub fn render(&mut self, frame: &mut Frame) {
+		let [filter_area, table_area, output_area, footer_area] = Layout::vertical([
+			Constraint::Length(1),
+			Constraint::Min(3),
+			Constraint::Percentage(40),
+			Constraint::Length(1),
+		])
+		.areas(frame.area());
+
+		let filter_line = match &self.mode {
+			Mode::Command(command) => format!("exec> {command}"),
+			_ => match &self.filter_error {
+				Some(error) => format!("filter> {}  ({error})", self.filter_text),
+				None => format!("filter> {}", self.filter_text),
+			},
+		};
+		frame.render_widget(Paragraph::new(filter_line), filter_area);
+
+		let header = Row::new(["", "Repo", "Branch", "Changes", "Upstream", "Tags"])
+			.style(Style::new().add_modifier(Modifier::BOLD));
+		let rows: Vec<Row> = self
+			.visible
+			.iter()
+			.map(|&ix| {
+				let repo = &self.repos[ix];
+				let mark = if self.selected.contains(&repo.path) {
+					"●"
+				} else {
+					" "
+				};
+				let mut cells = vec![mark.to_string(), repo.path.clone()];
+				cells.extend(status_cells(self.statuses.get(&repo.path)));
+				cells.push(repo.tags.join(","));
+				Row::new(cells)
+			})
+			.collect();
+		let table = Table::new(
+			rows,
+			[
+				Constraint::Length(1),
+				Constraint::Fill(2),
+				Constraint::Fill(1),
+				Constraint::Length(12),
+				Constraint::Length(10),
+				Constraint::Fill(2),
+			],
+		)
+		.header(header)
+		.row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+		let mut table_state =
+			TableState::new().with_selected((!self.visible.is_empty()).then_some(self.cursor));
+		frame.render_stateful_widget(table, table_area, &mut table_state);
+
+		let (title, lines) = match self.highlighted() {
+			Some(repo) => (repo.path.clone(), self.output(&repo.path)),
+			None => (String::new(), &[][..]),
+		};
+		// show the end of the output, like a terminal would
+		let height = output_area.height.saturating_sub(2) as usize;
+		let lines: Vec<Line> = lines[lines.len().saturating_sub(height)..]
+			.iter()
+			.map(|line| Line::raw(line.as_str()))
+			.collect();
+		frame.render_widget(
+			Paragraph::new(lines).block(Block::new().borders(Borders::TOP).title(title)),
+			output_area,
+		);
+
+		let help = match self.mode {
+			Mode::Browse if self.running.is_some() => {
+				"↑↓ move  space select  a all  / filter  r refresh  esc cancel  q quit"
+			}
+			Mode::Browse => {
+				"↑↓ move  space select  a all  / filter  f fetch  p pull  e exec  r refresh  q quit"
+			}
+			Mode::Filter => "type a tag expression  enter done  esc clear",
+			Mode::Command(_) => "type a command  enter run  esc cancel",
+		};
+		let footer = if self.message.is_empty() {
+			help.to_string()
+		} else {
+			format!("{help}  │ {}", self.message)
+		};
+		frame.render_widget(Paragraph::new(footer), footer_area);
+	}
+}
+
+/// The branch, change counts and ahead/behind columns for a repo.
+fn status_cells(state: Option<&RepoState>) -> Vec<String> {
+	match state {
+		None | Some(RepoState::Loading) => vec!["…".to_string(), String::new(), String::new()],
+		Some(RepoState::Error(message)) => vec![message.clone(), String::new(), String::new()],
+		Some(RepoState::Loaded(status)) => {
+			let branch = match &status.head {
+				Head::Branch(name) => name.clone(),
+				Head::Unborn(name) => format!("{name} (no commits)"),
+				Head::Detached(commit) => format!("(detached {commit})"),
+			};
+			let counts = [
+				('+', status.staged),
+				('~', status.unstaged),
+				('?', status.untracked),
+			];
+			let changes: Vec<String> = counts
+				.iter()
+				.filter(|(_, count)| *count > 0)
+				.map(|(symbol, count)| format!("{symbol}{count}"))
+				.collect();
+			let changes = if changes.is_empty() {
+				"clean".to_string()
+			} else {
+				changes.join(" ")
+			};
+			let upstream = match status.ahead_behind {
+				Some((ahead, behind)) => format!("↑{ahead} ↓{behind}"),
+				None => "-".to_string(),
+			};
+			vec![branch, changes, upstream]
+		}
+	}
+}
+
+/// A line saying how an operation went in a repo, for the end of its output.
+fn describe(outcome: &RepoOutcome) -> Option<String> {
+	let line = match outcome {
+		RepoOutcome::Exec(ExecOutcome::Missing) => "Repo folder missing, skipped".to_string(),
+		RepoOutcome::Exec(ExecOutcome::Failed(error)) => format!("Error: {}", error.message()),
+		RepoOutcome::Exec(ExecOutcome::Exited { exit_code, .. }) => match exit_code {
+			Some(code) => format!("Exited with {code}"),
+			None => "Killed by a signal".to_string(),
+		},
+		RepoOutcome::Exec(ExecOutcome::TimedOut { duration }) => {
+			format!("Timed out after {}s, killed", duration.as_secs())
+		}
+		RepoOutcome::Exec(ExecOutcome::Cancelled { .. }) => "Cancelled".to_string(),
+		RepoOutcome::Fetch(Ok(result)) => {
+			let plural = if result.updated_refs.len() == 1 {
+				""
+			} else {
+				"s"
+			};
+			format!(
+				"Fetched {}: {} ref{plural} updated",
+				result.remotes.join(","),
+				result.updated_refs.len()
+			)
+		}
+		RepoOutcome::Pull(Ok(outcome)) => match outcome {
+			PullOutcome::Updated {
+				branch,
+				from,
+				to,
+				commits,
+			} => format!("Updated {branch} {from}..{to} ({commits} commits)"),
+			PullOutcome::UpToDate { branch } => format!("{branch} up to date"),
+			PullOutcome::Diverged {
+				branch,
+				ahead,
+				behind,
+			} => format!("{branch} diverged: {ahead} ahead, {behind} behind"),
+			PullOutcome::Dirty { branch } => format!("{branch} has uncommitted changes, skipped"),
+			PullOutcome::Detached { commit } => format!("Detached at {commit}, skipped"),
+			PullOutcome::NoUpstream { branch } => format!("{branch} has no upstream, skipped"),
+		},
+		RepoOutcome::Fetch(Err(error)) | RepoOutcome::Pull(Err(error)) => {
+			format!("Error: {}", error.message())
+		}
+		_ => return None,
+	};
+	Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ratatui::backend::TestBackend;
+	use ratatui::Terminal;
+
+	fn repo(path: &str, tags: &[&str]) -> Repo {
+		Repo {
+			path: path.to_string(),
+			tags: tags.iter().map(|tag| tag.to_string()).collect(),
+			remotes: Default::default(),
+		}
+	}
+
+	fn app() -> App {
+		App::new(
+			vec![
+				repo("api", &["backend", "rust"]),
+				repo("web", &["frontend"]),
+				repo("worker", &["backend", "go"]),
+			],
+			TagFilter::all(),
+		)
+	}
+
+	fn press(app: &mut App, keys: &str) -> Option<Action> {
+		let mut action = None;
+		for c in keys.chars() {
+			action = app.handle_key(KeyEvent::from(KeyCode::Char(c)));
+		}
+		action
+	}
+
+	#[test]
+	fn filters_as_an_expression_is_typed() {
+		let mut app = app();
+		press(&mut app, "/backend and not g");
+		assert_eq!(vec!["api", "worker"], app.visible_paths());
+		press(&mut app, "o");
+		assert_eq!(vec!["api"], app.visible_paths());
+
+		press(&mut app, " and (");
+		assert!(app.filter_error().is_some());
+		assert_eq!(vec!["api"], app.visible_paths());
+
+		app.handle_key(KeyEvent::from(KeyCode::Esc));
+		assert_eq!(Mode::Browse, *app.mode());
+		assert_eq!(vec!["api", "web", "worker"], app.visible_paths());
+	}
+
+	/// The name of the action and the paths of the repos it's for.
+	fn targets(action: Option<Action>) -> (String, Vec<String>) {
+		let (name, repos) = match action {
+			Some(Action::Fetch(repos)) => ("fetch".to_string(), repos),
+			Some(Action::Pull(repos)) => ("pull".to_string(), repos),
+			Some(Action::Exec(command, repos)) => (command, repos),
+			other => panic!("unexpected action {other:?}"),
+		};
+		(name, repos.into_iter().map(|repo| repo.path).collect())
+	}
+
+	#[test]
+	fn actions_apply_to_the_selection_or_the_highlighted_repo() {
+		let mut app = app();
+		assert_eq!(
+			("pull".to_string(), vec!["api".to_string()]),
+			targets(press(&mut app, "p"))
+		);
+		app.operation_finished(&[]);
+
+		press(&mut app, "j j ");
+		assert_eq!(vec!["web", "worker"], app.selected_paths());
+		assert_eq!(
+			(
+				"fetch".to_string(),
+				vec!["web".to_string(), "worker".to_string()]
+			),
+			targets(press(&mut app, "f"))
+		);
+		app.operation_finished(&[]);
+
+		assert!(press(&mut app, "egit log -1").is_none());
+		assert_eq!(
+			(
+				"git log -1".to_string(),
+				vec!["web".to_string(), "worker".to_string()]
+			),
+			targets(app.handle_key(KeyEvent::from(KeyCode::Enter)))
+		);
+
+		press(&mut app, "a");
+		assert_eq!(vec!["api", "web", "worker"], app.selected_paths());
+		press(&mut app, "a");
+		assert!(app.selected_paths().is_empty());
+	}
+
+	#[test]
+	fn keys_cancel_rather_than_start_operations_while_one_runs() {
+		let mut app = app();
+		press(&mut app, "e");
+		press(&mut app, "sleep 60");
+		targets(app.handle_key(KeyEvent::from(KeyCode::Enter)));
+
+		assert!(press(&mut app, "p").is_none());
+		press(&mut app, "j");
+		assert_eq!(
+			vec!["web"],
+			app.targets()
+				.iter()
+				.map(|repo| &repo.path)
+				.collect::<Vec<_>>()
+		);
+		assert!(matches!(
+			app.handle_key(KeyEvent::from(KeyCode::Esc)),
+			Some(Action::Cancel)
+		));
+		assert!(matches!(
+			app.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+			Some(Action::Cancel)
+		));
+		assert!(matches!(press(&mut app, "q"), Some(Action::Quit)));
+
+		app.operation_finished(&[]);
+		assert_eq!(
+			("pull".to_string(), vec!["web".to_string()]),
+			targets(press(&mut app, "p"))
+		);
+	}
+
+	#[test]
+	fn shows_output_of_the_highlighted_repo() {
+		let mut app = app();
+		let api = repo("api", &[]);
+		app.record(Event::RepoStarted { repo: &api });
+		app.record(Event::OutputLine {
+			repo: &api,
+			stream: OutputStream::Stdout,
+			line: "hello",
+		});
+		app.record(Event::RepoFinished {
+			repo: &api,
+			outcome: RepoOutcome::Exec(&ExecOutcome::Exited {
+				exit_code: Some(0),
+				duration: Duration::ZERO,
+			}),
+		});
+		assert_eq!(vec!["hello", "Exited with 0"], app.output("api"));
+
+		let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+		terminal.draw(|frame| app.render(frame)).unwrap();
+		let screen: String = terminal
+			.backend()
+			.buffer()
+			.content()
+			.iter()
+			.map(|cell| cell.symbol())
+			.collect();
+		assert!(screen.contains("worker"));
+		assert!(screen.contains("backend,go"));
+		assert!(screen.contains("Exited with 0"));
+	}
+}
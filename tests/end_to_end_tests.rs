@@ -1,5 +1,10 @@
 use std::fs;
-use std::process::Command;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use assert_cmd::Command as AssertCommand;
 use predicates::prelude::{predicate, PredicateBooleanExt};
@@ -95,6 +100,161 @@ url = \"git://example.org/test_url2\"
 	assert_eq!(expected_toml, read_gitopolis_state_toml(&temp));
 }
 
+#[test]
+fn add_scan() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "clients/acme/api", "git://example.org/acme_api");
+	create_git_repo(
+		&temp,
+		"clients/acme/api/vendored",
+		"git://example.org/vendored",
+	);
+	create_git_repo(&temp, "tools", "git://example.org/tools");
+	fs::create_dir_all(temp.path().join("notes")).expect("create dir failed");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "--scan", "--tag", "scanned"])
+		.assert()
+		.success()
+		.stderr("Added clients/acme/api\nAdded tools\n");
+
+	let expected_toml = "[[repos]]
+path = \"clients/acme/api\"
+tags = [\"scanned\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/acme_api\"
+
+[[repos]]
+path = \"tools\"
+tags = [\"scanned\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/tools\"
+";
+	assert_eq!(expected_toml, read_gitopolis_state_toml(&temp));
+}
+
+#[test]
+fn add_scan_folder_nested_and_max_depth() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "clients/acme/api", "git://example.org/acme_api");
+	create_git_repo(
+		&temp,
+		"clients/acme/api/vendored",
+		"git://example.org/vendored",
+	);
+	create_git_repo(&temp, "clients/beta", "git://example.org/beta");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "--scan", "clients", "--max-depth", "1"])
+		.assert()
+		.success()
+		.stderr("Added clients/beta\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "--scan", "clients", "--nested"])
+		.assert()
+		.success()
+		.stderr(
+			"Added clients/acme/api\nAdded clients/acme/api/vendored\nclients/beta already added, ignoring.\n",
+		);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list"])
+		.assert()
+		.success()
+		.stdout("clients/acme/api\nclients/acme/api/vendored\nclients/beta\n");
+}
+
+#[test]
+fn add_scan_worktree() {
+	let temp = temp_folder();
+	create_local_repo(&temp, "main_repo");
+	commit_empty(&temp, "main_repo", "first");
+	git(
+		&temp,
+		"main_repo",
+		vec!["worktree", "add", "../linked", "-b", "feature"],
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "--scan"])
+		.assert()
+		.success()
+		.stderr("Added linked\nAdded main_repo\n");
+}
+
+#[test]
+fn add_scan_inside_a_repo() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "", "git://example.org/workspace");
+	create_git_repo(&temp, "api", "git://example.org/api");
+	create_git_repo(&temp, "tools", "git://example.org/tools");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "--scan"])
+		.assert()
+		.success()
+		.stderr("Added api\nAdded tools\n");
+
+	gitopolis_executable()
+		.current_dir(temp.path().join("api"))
+		.args(vec!["add", "--scan", "--tag", "again"])
+		.assert()
+		.success()
+		.stderr("api already added, ignoring.\n");
+}
+
+#[test]
+fn add_scan_from_a_repo_below_the_config_skips_its_nested_repos() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "api", "git://example.org/api");
+	create_git_repo(&temp, "api/vendor/lib", "git://example.org/lib");
+	write_gitopolis_state_toml(&temp, "repos = []\n");
+
+	gitopolis_executable()
+		.current_dir(temp.path().join("api"))
+		.args(vec!["add", "--scan", "."])
+		.assert()
+		.success()
+		.stderr("Added api\n");
+
+	gitopolis_executable()
+		.current_dir(temp.path().join("api"))
+		.args(vec!["add", "--scan", ".", "--nested"])
+		.assert()
+		.success()
+		.stderr("api already added, ignoring.\nAdded api/vendor/lib\n");
+}
+
+#[test]
+fn add_with_tags() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "repo_a", "git://example.org/test_a");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "repo_a", "--tag", "foo,bar"])
+		.assert()
+		.success();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--long"])
+		.assert()
+		.success()
+		.stdout("repo_a\tbar,foo\torigin=git://example.org/test_a\n");
+}
+
 #[test]
 fn remove() {
 	let temp = temp_folder();
@@ -188,6 +348,147 @@ fn list_tag_abbreviated() {
 		.stdout("some_git_folder\n");
 }
 
+#[test]
+fn list_filter() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(&temp, "api", "git://example.org/api", vec!["rust"]);
+	add_a_repo_with_tags(&temp, "web", "git://example.org/web", vec!["go"]);
+	add_a_repo_with_tags(
+		&temp,
+		"old_api",
+		"git://example.org/old_api",
+		vec!["rust", "archived"],
+	);
+	add_a_repo(&temp, "untagged", "git://example.org/untagged");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--filter", "(rust or go) and not archived"])
+		.assert()
+		.success()
+		.stdout("api\nweb\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--filter", "!rust"])
+		.assert()
+		.success()
+		.stdout("untagged\nweb\n");
+}
+
+#[test]
+fn list_filter_glob_and_tag() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"api",
+		"git://example.org/api",
+		vec!["team-a", "rust"],
+	);
+	add_a_repo_with_tags(&temp, "web", "git://example.org/web", vec!["team-b"]);
+	add_a_repo_with_tags(&temp, "tool", "git://example.org/tool", vec!["rust"]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--filter", "team-*"])
+		.assert()
+		.success()
+		.stdout("api\nweb\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--tag", "rust", "--filter", "team-*"])
+		.assert()
+		.success()
+		.stdout("api\n");
+}
+
+#[test]
+fn list_filter_invalid() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(&temp, "api", "git://example.org/api", vec!["rust"]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--filter", "(rust or"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout("")
+		.stderr(predicate::str::contains(
+			"Error: Invalid filter expression '(rust or'",
+		));
+}
+
+#[test]
+fn list_with_unknown_key_in_a_repo() {
+	let temp = temp_folder();
+	write_gitopolis_state_toml(
+		&temp,
+		"[[repos]]
+path = \"api\"
+tags = []
+note = \"added by hand\"
+
+[repos.remotes]
+",
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list"])
+		.assert()
+		.success()
+		.stdout("api\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "rust", "api"])
+		.assert()
+		.success();
+	assert!(read_gitopolis_state_toml(&temp).contains("note = \"added by hand\"\n"));
+}
+
+#[test]
+fn list_with_misspelled_table() {
+	let temp = temp_folder();
+	write_gitopolis_state_toml(
+		&temp,
+		"[[repo]]
+path = \"api\"
+tags = []
+",
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout("")
+		.stderr(predicate::str::contains(
+			"unknown field `repo`, expected one of `include`, `repos`, `aliases`",
+		));
+}
+
+#[test]
+fn list_unreadable_state() {
+	let temp = temp_folder();
+	write_gitopolis_state_toml(&temp, "repos = 1\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout("")
+		.stderr(predicate::str::starts_with(
+			"Error: Failed to parse state data as valid TOML.",
+		));
+}
+
 #[test]
 fn list_long() {
 	let temp = temp_folder();
@@ -321,6 +622,44 @@ git://example.org/test_url
 		.stdout(expected_stdout);
 }
 
+#[test]
+fn exec_filter() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"some_git_folder",
+		"git://example.org/test_url",
+		vec!["some_tag", "another_tag"],
+	);
+	add_a_repo_with_tags(
+		&temp,
+		"some_other_git_folder",
+		"git://example.org/test_url2",
+		vec!["some_tag", "archived"],
+	);
+
+	let expected_stdout = "
+🏢 some_git_folder> git config remote.origin.url
+git://example.org/test_url
+
+";
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec![
+			"exec",
+			"--filter",
+			"some_tag & !archived",
+			"--",
+			"git",
+			"config",
+			"remote.origin.url",
+		])
+		.assert()
+		.success()
+		.stdout(expected_stdout);
+}
+
 #[test]
 fn exec_tag_abbreviated() {
 	let temp = temp_folder();
@@ -445,40 +784,105 @@ fn exec_oneline() {
 }
 
 #[test]
-fn exec_oneline_multiline_output() {
+fn run_alias() {
 	let temp = temp_folder();
-	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
-
-	// Create a test file with multiple lines in the repo
-	let repo_path = temp.path().join("repo_a");
-	fs::write(repo_path.join("test.txt"), "line1\nline2\nline3").unwrap();
+	add_a_repo_with_tags(&temp, "repo_a", "git://example.org/test_url", vec!["web"]);
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+	let state_file = temp.path().join(".gitopolis.toml");
+	let state = fs::read_to_string(&state_file).unwrap();
+	fs::write(
+		&state_file,
+		format!(
+			r#"{state}
+[aliases]
+hello = ["echo", "hello"]
+greet = {{ command = ["echo", "hi {{1}}", "{{@}}"], tag = ["web"], oneline = true, description = "Say hi" }}
+"#
+		),
+	)
+	.unwrap();
 
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["exec", "--oneline", "--", "cat", "test.txt"])
+		.args(vec!["aliases"])
 		.assert()
 		.success()
-		.stdout("repo_a\tline1 line2 line3\n");
-}
-
-#[test]
-fn exec_oneline_non_zero() {
-	let temp = temp_folder();
-	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
-	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+		.stdout("greet\techo 'hi {1}' '{@}'\t--tag web --oneline\t# Say hi\nhello\techo hello\n");
 
-	let expected_stdout = match get_operating_system() {
-		OperatingSystem::MacOSX => {
-			"repo_a\tls: non-existent: No such file or directory\nrepo_b\tls: non-existent: No such file or directory\n"
-		}
-		OperatingSystem::Other => {
-			"repo_a\tls: cannot access 'non-existent': No such file or directory\nrepo_b\tls: cannot access 'non-existent': No such file or directory\n"
-		}
-	};
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["run", "greet", "there", "-x"])
+		.assert()
+		.success()
+		.stdout("repo_a\thi there there -x\n");
 
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["exec", "--oneline", "--", "ls", "non-existent"])
+		.args(vec!["run", "--oneline", "hello", "world"])
+		.assert()
+		.success()
+		.stdout("repo_a\thello world\nrepo_b\thello world\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["run", "--tag", "nope", "greet", "there"])
+		.assert()
+		.success()
+		.stdout("");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["run", "greet"])
+		.assert()
+		.failure()
+		.stderr("Error: Alias 'greet' takes 1 arguments, got 0\n");
+
+	// saving the state leaves the aliases alone
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "more", "repo_b"])
+		.assert()
+		.success();
+	assert!(fs::read_to_string(&state_file)
+		.unwrap()
+		.ends_with("[aliases]\nhello = [\"echo\", \"hello\"]\ngreet = { command = [\"echo\", \"hi {1}\", \"{@}\"], tag = [\"web\"], oneline = true, description = \"Say hi\" }\n"));
+}
+
+#[test]
+fn exec_oneline_multiline_output() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+
+	// Create a test file with multiple lines in the repo
+	let repo_path = temp.path().join("repo_a");
+	fs::write(repo_path.join("test.txt"), "line1\nline2\nline3").unwrap();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--oneline", "--", "cat", "test.txt"])
+		.assert()
+		.success()
+		.stdout("repo_a\tline1 line2 line3\n");
+}
+
+#[test]
+fn exec_oneline_non_zero() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+
+	let expected_stdout = match get_operating_system() {
+		OperatingSystem::MacOSX => {
+			"repo_a\tls: non-existent: No such file or directory\nrepo_b\tls: non-existent: No such file or directory\n"
+		}
+		OperatingSystem::Other => {
+			"repo_a\tls: cannot access 'non-existent': No such file or directory\nrepo_b\tls: cannot access 'non-existent': No such file or directory\n"
+		}
+	};
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--oneline", "--", "ls", "non-existent"])
 		.assert()
 		.failure()
 		.code(1)
@@ -519,6 +923,375 @@ url = \"git://example.org/test_url\"
 		.stderr("1 repos skipped\n");
 }
 
+#[test]
+fn exec_parallel() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+	add_a_repo(&temp, "repo_c", "git://example.org/test_url3");
+
+	// output blocks are printed whole and in repo order regardless of which finishes first
+	let expected_stdout = "
+🏢 repo_a> git config remote.origin.url
+git://example.org/test_url
+
+
+🏢 repo_b> git config remote.origin.url
+git://example.org/test_url2
+
+
+🏢 repo_c> git config remote.origin.url
+git://example.org/test_url3
+
+";
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec![
+			"exec",
+			"--parallel",
+			"2",
+			"--",
+			"git",
+			"config",
+			"remote.origin.url",
+		])
+		.assert()
+		.success()
+		.stdout(expected_stdout);
+}
+
+#[test]
+fn exec_parallel_abbreviated_oneline() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "-j", "4", "--oneline", "--", "echo", "hello"])
+		.assert()
+		.success()
+		.stdout("repo_a\thello\nrepo_b\thello\n");
+}
+
+// uses sh syntax for conditions
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_group() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+	add_a_repo(&temp, "repo_c", "git://example.org/test_url3");
+	add_a_repo(&temp, "repo_d", "git://example.org/test_url4");
+
+	let command = "case $GITOPOLIS_REPO_NAME in repo_b) echo develop;; repo_c) echo; echo  legacy;; *) echo main;; esac";
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--group", "-j", "2", "--", command])
+		.assert()
+		.success()
+		.stdout("main\trepo_a repo_d\ndevelop\trepo_b\nlegacy\trepo_c\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--group", "--count", "--", command])
+		.assert()
+		.success()
+		.stdout("2\tmain\n1\tdevelop\n1\tlegacy\n");
+}
+
+// uses sh syntax for redirecting to stderr and exit codes
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_parallel_non_zero_and_missing() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "repo_a", "git://example.org/test_url");
+	create_git_repo(&temp, "repo_b", "git://example.org/test_url2");
+
+	let initial_state_toml = "[[repos]]
+path = \"missing_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"example_url\"
+
+[[repos]]
+path = \"repo_a\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+[[repos]]
+path = \"repo_b\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url2\"
+";
+	write_gitopolis_state_toml(&temp, initial_state_toml);
+
+	let expected_stdout = "
+🏢 missing_repo> Repo folder missing, skipped.
+
+🏢 repo_a> 'echo out; echo err >&2; exit 3'
+out
+
+
+🏢 repo_b> 'echo out; echo err >&2; exit 3'
+out
+
+";
+	let expected_stderr = "err
+Command exited with code 3
+err
+Command exited with code 3
+2 commands exited with non-zero status code
+1 repos skipped
+";
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec![
+			"exec",
+			"--parallel",
+			"3",
+			"--",
+			"echo out; echo err >&2; exit 3",
+		])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(expected_stdout)
+		.stderr(expected_stderr);
+}
+
+// uses sh syntax for backgrounding a process
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_timeout() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+
+	// the background sleep keeps the output open, so this only finishes if it's killed too
+	let command = "if [ $GITOPOLIS_REPO_NAME = repo_a ]; then sleep 30 & sleep 30; fi; echo done";
+	let expected_stdout = format!(
+		"
+🏢 repo_a> '{command}'
+
+
+🏢 repo_b> '{command}'
+done
+
+"
+	);
+	let expected_stderr = "Command timed out after 1s and was killed
+1 commands timed out
+";
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--timeout", "1s", "--", command])
+		.timeout(Duration::from_secs(20))
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(expected_stdout)
+		.stderr(expected_stderr);
+}
+
+// uses kill to send the SIGINT that Ctrl-C would
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_cancel() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+
+	let (output, finished) = exec_with_ctrl_c(&temp, "echo started; exec sleep 30");
+
+	assert_eq!(Some(130), finished.status.code());
+	assert_eq!(
+		"\n🏢 repo_a> 'echo started; exec sleep 30'\nstarted\n\n",
+		output
+	);
+	assert_eq!(
+		"Command was cancelled\nCancelled, 2 repos not finished\n",
+		String::from_utf8_lossy(&finished.stderr)
+	);
+}
+
+// uses sh traps
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_cancel_interrupts_once() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_url");
+
+	// the trap outlasts any second interrupt, but finishes well within the grace period. The
+	// inner sh says it's started once the outer one is waiting on it, so the trap always runs
+	let (output, finished) = exec_with_ctrl_c(
+		&temp,
+		"trap 'echo interrupted; sleep 0.5' INT; sh -c 'echo started; exec sleep 30'",
+	);
+
+	assert_eq!(Some(130), finished.status.code());
+	assert_eq!(
+		1,
+		output.lines().filter(|line| *line == "interrupted").count(),
+		"{output}"
+	);
+}
+
+/// Run `exec` with `command` and press Ctrl-C once it's started in the first repo, which sends
+/// SIGINT to gitopolis and everything it's running. Returns stdout and how gitopolis finished.
+#[cfg(not(target_os = "windows"))]
+fn exec_with_ctrl_c(temp: &TempDir, command: &str) -> (String, std::process::Output) {
+	use std::os::unix::process::CommandExt;
+
+	let mut gitopolis = Command::new(env!("CARGO_BIN_EXE_gitopolis"))
+		.current_dir(temp)
+		.args(vec!["exec", "--", command])
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		// its own group stands in for the terminal's foreground group
+		.process_group(0)
+		.spawn()
+		.expect("failed to start gitopolis");
+	let mut stdout = BufReader::new(gitopolis.stdout.take().expect("no stdout"));
+	let mut output = String::new();
+	// wait until the command is running in the first repo
+	while !output.ends_with("started\n") {
+		let read = stdout
+			.read_line(&mut output)
+			.expect("failed to read stdout");
+		assert!(read > 0, "exec finished early: {output}");
+	}
+	Command::new("kill")
+		.args(["-INT", "--", &format!("-{}", gitopolis.id())])
+		.status()
+		.expect("failed to send SIGINT");
+	stdout
+		.read_to_string(&mut output)
+		.expect("failed to read stdout");
+	let finished = gitopolis.wait_with_output().expect("gitopolis failed");
+	(output, finished)
+}
+
+// uses sh syntax for redirecting to stderr and exit codes
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_log_dir() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "group/repo_a", "git://example.org/test_url");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_url2");
+	fs::remove_dir_all(temp.path().join("repo_b")).expect("failed to remove repo_b");
+
+	let command = "echo out; echo err >&2; exit 3";
+	let expected_stdout = format!(
+		"
+🏢 group/repo_a> '{command}'
+out
+
+
+🏢 repo_b> Repo folder missing, skipped.
+"
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--log-dir", "logs", "--", command])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(expected_stdout);
+
+	let log =
+		fs::read_to_string(temp.path().join("logs/group/repo_a.log")).expect("failed to read log");
+	let lines: Vec<&str> = log.lines().collect();
+	assert_eq!(
+		vec![
+			"repo: group/repo_a",
+			&format!("command: '{command}'"),
+			"result: exit code 3",
+		],
+		lines[..3]
+	);
+	assert!(lines[3].starts_with("duration: "), "{log}");
+	assert_eq!(
+		vec!["", "--- stdout ---", "out", "--- stderr ---", "err"],
+		lines[4..]
+	);
+
+	let index =
+		fs::read_to_string(temp.path().join("logs/index.tsv")).expect("failed to read index");
+	let lines: Vec<Vec<&str>> = index
+		.lines()
+		.map(|line| line.split('\t').collect())
+		.collect();
+	assert!(lines[0][0].starts_with(&format!("# '{command}' started ")));
+	assert_eq!(vec!["repo", "result", "duration_ms", "log"], lines[1]);
+	assert_eq!(
+		vec!["group/repo_a", "exit code 3", "group/repo_a.log"],
+		vec![lines[2][0], lines[2][1], lines[2][3]]
+	);
+	assert_eq!(vec!["repo_b", "missing", "", ""], lines[3]);
+	assert!(!temp.path().join("logs/repo_b.log").exists());
+}
+
+// uses sh syntax for expanding variables
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn exec_repo_environment_variables() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"group/repo_a",
+		"git://example.org/test_url",
+		vec!["some_tag", "another_tag"],
+	);
+	create_git_repo(&temp, "repo_b", "git://example.org/test_url2");
+	Command::new("git")
+		.current_dir(temp.path().join("repo_b"))
+		.args(vec!["remote", "remove", "origin"])
+		.output()
+		.expect("git command failed");
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "repo_b"])
+		.assert()
+		.success();
+
+	let command = "echo $GITOPOLIS_REPO_INDEX/$GITOPOLIS_REPO_COUNT $GITOPOLIS_REPO_PATH $GITOPOLIS_REPO_NAME [$GITOPOLIS_REPO_TAGS] [$GITOPOLIS_REMOTE_ORIGIN_URL] $GITOPOLIS_ROOT";
+	let root = temp
+		.path()
+		.canonicalize()
+		.expect("canonicalize failed")
+		.to_string_lossy()
+		.to_string();
+	let expected_stdout = format!(
+		"group/repo_a\t1/2 group/repo_a repo_a [another_tag,some_tag] [git://example.org/test_url] {root}
+repo_b\t2/2 repo_b repo_b [] [] {root}
+"
+	);
+
+	for parallel in ["1", "2"] {
+		gitopolis_executable()
+			.current_dir(&temp)
+			.env("GITOPOLIS_REMOTE_ORIGIN_URL", "leaked from parent")
+			.args(vec!["exec", "--oneline", "-j", parallel, "--", command])
+			.assert()
+			.success()
+			.stdout(expected_stdout.clone());
+	}
+}
+
 #[test]
 fn tag() {
 	let temp = temp_folder();
@@ -707,6 +1480,7 @@ Cloning into \'some_git_folder\'...
 warning: You appear to have cloned an empty repository.
 done.
 
+1 cloned, 0 already existed, 0 had no remotes, 0 failed
 ";
 
 	gitopolis_executable()
@@ -786,6 +1560,7 @@ Cloning into \'some_git_folder\'...
 warning: You appear to have cloned an empty repository.
 done.
 
+1 cloned, 0 already existed, 0 had no remotes, 0 failed
 ";
 
 	gitopolis_executable()
@@ -875,8 +1650,139 @@ fn create_git_repo(temp: &TempDir, repo_name: &str, remote_url: &str) {
 		.expect("git command failed");
 }
 
-fn gitopolis_executable() -> AssertCommand {
-	AssertCommand::cargo_bin("gitopolis").expect("failed to find binary")
+/// Bare repo with a commit on main pushed from `seed_name`, a clone to push further changes from.
+/// Returns its absolute path for use as a remote url.
+fn create_bare_remote(temp: &TempDir, bare_name: &str, seed_name: &str) -> String {
+	let output = Command::new("git")
+		.current_dir(temp)
+		.args(vec![
+			"init",
+			"--bare",
+			"--initial-branch",
+			"main",
+			bare_name,
+		])
+		.output()
+		.expect("git init failed");
+	assert!(output.status.success(), "{:?}", output);
+	clone_local_repo(temp, bare_name, seed_name);
+	commit_empty(temp, seed_name, "first");
+	git(temp, seed_name, vec!["push", "origin", "main"]);
+	temp.path().join(bare_name).to_string_lossy().to_string()
+}
+
+fn rev_parse(temp: &TempDir, repo_name: &str) -> String {
+	let output = Command::new("git")
+		.current_dir(temp.path().join(repo_name))
+		.args(vec!["rev-parse", "HEAD"])
+		.output()
+		.expect("git rev-parse failed");
+	String::from_utf8(output.stdout).expect("utf8 conversion failed")
+}
+
+fn add_existing_repo(temp: &TempDir, repo_name: &str) {
+	gitopolis_executable()
+		.current_dir(temp)
+		.args(vec!["add", repo_name])
+		.assert()
+		.success();
+}
+
+fn clone_local_repo(temp: &TempDir, source: &str, target: &str) {
+	Command::new("git")
+		.current_dir(temp)
+		.args(vec!["clone", source, target])
+		.output()
+		.expect("git clone failed");
+}
+
+fn commit_empty(temp: &TempDir, repo_name: &str, message: &str) {
+	git(
+		temp,
+		repo_name,
+		vec![
+			"-c",
+			"user.name=Test",
+			"-c",
+			"user.email=test@example.org",
+			"commit",
+			"--allow-empty",
+			"-m",
+			message,
+		],
+	);
+}
+
+fn git(temp: &TempDir, repo_name: &str, args: Vec<&str>) {
+	let output = Command::new("git")
+		.current_dir(temp.path().join(repo_name))
+		.args(args)
+		.output()
+		.expect("git command failed");
+	assert!(output.status.success(), "{:?}", output);
+}
+
+/// Serve canned API responses on a local port, recording each request's method, path and
+/// Authorization header. Responses are (path, extra header line, body); `{api_url}` in the header
+/// is replaced with the server's URL. Unknown paths get a 404 like GitLab's.
+fn serve_api(
+	responses: Vec<(&'static str, &'static str, &'static str)>,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").expect("bind failed");
+	let api_url = format!("http://{}", listener.local_addr().unwrap());
+	let requests = Arc::new(Mutex::new(vec![]));
+	let recorded = requests.clone();
+	let base = api_url.clone();
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let mut stream = stream.unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut request_line = String::new();
+			reader.read_line(&mut request_line).unwrap();
+			let mut auth = String::new();
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+				if let Some((name, value)) = line.split_once(':') {
+					if name.eq_ignore_ascii_case("authorization") {
+						auth = value.trim().to_string();
+					}
+				}
+			}
+			let mut parts = request_line.split_whitespace();
+			let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+			recorded
+				.lock()
+				.unwrap()
+				.push(format!("{method} {path} {auth}").trim().to_string());
+			let (status, header, body) = match responses.iter().find(|(p, _, _)| *p == path) {
+				Some((_, header, body)) => ("200 OK", header.replace("{api_url}", &base), *body),
+				None => (
+					"404 Not Found",
+					String::new(),
+					r#"{"message":"404 Group Not Found"}"#,
+				),
+			};
+			let header = match header.is_empty() {
+				true => header,
+				false => format!("{header}\r\n"),
+			};
+			write!(
+				stream,
+				"HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{header}\r\n{body}",
+				body.len()
+			)
+			.unwrap();
+		}
+	});
+	(api_url, requests)
+}
+
+fn gitopolis_executable() -> AssertCommand {
+	AssertCommand::cargo_bin("gitopolis").expect("failed to find binary")
 }
 
 fn write_gitopolis_state_toml(temp: &TempDir, initial_state_toml: &str) {
@@ -1070,6 +1976,74 @@ fn list_long_multiple_remotes() {
 		.stdout(expected_output);
 }
 
+#[test]
+fn clone_parallel_summary() {
+	let temp = temp_folder();
+	create_local_repo(&temp, "source_repo");
+	commit_empty(&temp, "source_repo", "first commit");
+	create_local_repo(&temp, "existing_repo");
+	let initial_state_toml = "[[repos]]
+path = \"cloned_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"source_repo\"
+
+[repos.remotes.upstream]
+name = \"upstream\"
+url = \"git://example.org/upstream\"
+
+[[repos]]
+path = \"existing_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"source_repo\"
+
+[[repos]]
+path = \"failed_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"nonexistent_source\"
+";
+	write_gitopolis_state_toml(&temp, initial_state_toml);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["clone", "-j", "3"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(predicate::str::contains(
+			"🏢 cloned_repo> Cloning source_repo ...",
+		))
+		.stdout(predicate::str::contains(
+			"🏢 existing_repo> Already exists, skipped.",
+		))
+		.stdout(predicate::str::contains(
+			"🏢 failed_repo> Cloning nonexistent_source ...",
+		))
+		.stdout(predicate::str::ends_with(
+			"1 cloned, 1 already existed, 0 had no remotes, 1 failed\n",
+		))
+		.stdout(predicate::str::contains("objects:").not())
+		.stderr(predicate::str::contains(
+			"Warning: Could not clone failed_repo",
+		))
+		.stderr(predicate::str::contains("1 repos failed to clone"));
+
+	// other remotes are added after cloning from origin
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--oneline", "--", "git remote"])
+		.assert()
+		.stdout(predicate::str::contains("cloned_repo\torigin upstream"));
+}
+
 #[test]
 fn clone_with_failures_exits_with_error_count() {
 	let temp = temp_folder();
@@ -1119,6 +2093,37 @@ url = \"also_nonexistent\"
 	assert!(!temp.path().join("test_repo2").exists());
 }
 
+#[test]
+fn clone_without_git_on_path_counts_as_failed() {
+	let temp = temp_folder();
+	write_gitopolis_state_toml(
+		&temp,
+		"[[repos]]
+path = \"test_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"source_repo\"
+",
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.env("PATH", "")
+		.args(vec!["clone"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(predicate::str::contains("Error running git clone"))
+		.stdout(predicate::str::contains(
+			"0 cloned, 0 already existed, 0 had no remotes, 1 failed",
+		))
+		.stderr("Warning: Could not clone test_repo\n1 repos failed to clone\n");
+
+	assert!(!temp.path().join("test_repo").exists());
+}
+
 #[test]
 fn clone_multiple_remotes() {
 	let temp = temp_folder();
@@ -1534,147 +2539,1201 @@ url = \"git://example.org/test_url\"
 }
 
 #[test]
-fn show() {
+fn status() {
 	let temp = temp_folder();
-	let path = &temp.path().join("test_repo");
-	fs::create_dir_all(path).expect("create repo dir failed");
-
-	// Initialize git repo
-	Command::new("git")
-		.current_dir(path)
-		.args(vec!["init", "--initial-branch", "main"])
-		.output()
-		.expect("git init failed");
-
-	// Add multiple remotes
-	Command::new("git")
-		.current_dir(path)
-		.args(vec![
-			"remote",
-			"add",
-			"origin",
-			"git://example.org/origin_url",
-		])
-		.output()
-		.expect("git remote add origin failed");
-
-	Command::new("git")
-		.current_dir(path)
-		.args(vec![
-			"remote",
-			"add",
-			"upstream",
-			"git://example.org/upstream_url",
-		])
-		.output()
-		.expect("git remote add upstream failed");
-
-	// Add repo to gitopolis and tag it
+	create_local_repo(&temp, "source_repo");
+	commit_empty(&temp, "source_repo", "first");
+	clone_local_repo(&temp, "source_repo", "cloned_repo");
+	commit_empty(&temp, "source_repo", "upstream change");
+	commit_empty(&temp, "cloned_repo", "local change");
+	git(&temp, "cloned_repo", vec!["fetch"]);
+	fs::write(temp.path().join("cloned_repo").join("untracked.txt"), "").unwrap();
+	fs::write(temp.path().join("cloned_repo").join("staged.txt"), "").unwrap();
+	git(&temp, "cloned_repo", vec!["add", "staged.txt"]);
+	add_a_repo(&temp, "fresh_repo", "git://example.org/test_url");
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["add", "test_repo"])
-		.output()
-		.expect("Failed to add repo");
+		.args(vec!["add", "cloned_repo"])
+		.assert()
+		.success();
+
+	let expected_stdout =
+		"REPO         BRANCH             AHEAD  BEHIND  STAGED  UNSTAGED  UNTRACKED  STASHES  STATE
+cloned_repo  main               1      1       1       0         1          0
+fresh_repo   main (no commits)  -      -       0       0         0          0
+";
 
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["tag", "backend", "test_repo"])
-		.output()
-		.expect("Failed to tag repo");
+		.args(vec!["status"])
+		.assert()
+		.success()
+		.stdout(expected_stdout);
+}
 
+#[test]
+fn state_selectors() {
+	let temp = temp_folder();
+	create_local_repo(&temp, "source_repo");
+	commit_empty(&temp, "source_repo", "first");
+	clone_local_repo(&temp, "source_repo", "behind_repo");
+	clone_local_repo(&temp, "source_repo", "feature_repo");
+	git(&temp, "feature_repo", vec!["switch", "-c", "feature/login"]);
+	commit_empty(&temp, "source_repo", "upstream change");
+	git(&temp, "behind_repo", vec!["fetch"]);
+	add_a_repo(&temp, "fresh_repo", "git://example.org/test_url");
+	fs::write(temp.path().join("fresh_repo").join("staged.txt"), "").unwrap();
+	git(&temp, "fresh_repo", vec!["add", "staged.txt"]);
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["tag", "rust", "test_repo"])
-		.output()
-		.expect("Failed to tag repo");
+		.args(vec!["add", "behind_repo", "feature_repo"])
+		.assert()
+		.success();
 
-	// Run show command
-	let expected_output = "Tags:
-  backend
-  rust
+	for (args, expected_stdout) in [
+		(vec!["--dirty"], "fresh_repo\n"),
+		(vec!["--clean"], "behind_repo\nfeature_repo\n"),
+		(vec!["--behind"], "behind_repo\n"),
+		(vec!["--not-on-default-branch"], "feature_repo\n"),
+		(vec!["--on-branch", "feature/*"], "feature_repo\n"),
+	] {
+		gitopolis_executable()
+			.current_dir(&temp)
+			.arg("list")
+			.args(args)
+			.assert()
+			.success()
+			.stdout(expected_stdout);
+	}
 
-Remotes:
-  origin: git://example.org/origin_url
-  upstream: git://example.org/upstream_url
-";
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--ahead"])
+		.assert()
+		.code(2)
+		.stdout("No repos\n");
 
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["show", "test_repo"])
+		.args(vec![
+			"exec",
+			"--clean",
+			"--on-branch",
+			"main",
+			"--oneline",
+			"--",
+			"echo",
+			"hello",
+		])
 		.assert()
 		.success()
-		.stdout(expected_output);
-}
+		.stdout("behind_repo\thello\n");
 
-#[test]
-fn show_repo_not_found() {
-	let temp = temp_folder();
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["status", "--dirty"])
+		.assert()
+		.success()
+		.stdout(
+			"REPO        BRANCH             AHEAD  BEHIND  STAGED  UNSTAGED  UNTRACKED  STASHES  STATE
+fresh_repo  main (no commits)  -      -       1       0         0          0
+",
+		);
 
-	// Try to show a repo that doesn't exist
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["show", "nonexistent_repo"])
+		.args(vec!["list", "--dirty", "--clean"])
 		.assert()
 		.failure()
-		.code(1)
-		.stderr(predicate::str::contains(
-			"Repo 'nonexistent_repo' not found",
-		));
+		.stderr(predicate::str::contains("cannot be used with"));
 }
 
 #[test]
-fn tag_repo_not_found() {
+fn status_tag() {
 	let temp = temp_folder();
-	add_a_repo(&temp, "existing_repo", "git://example.org/test_url");
+	add_a_repo_with_tags(
+		&temp,
+		"some_git_folder",
+		"git://example.org/test_url",
+		vec!["some_tag"],
+	);
+	add_a_repo(
+		&temp,
+		"some_other_git_folder",
+		"git://example.org/test_url2",
+	);
 
-	// Try to tag a repo that doesn't exist
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["tag", "some_tag", "nonexistent_repo"])
+		.args(vec!["status", "--tag", "some_tag"])
 		.assert()
-		.failure()
-		.code(1)
-		.stderr(predicate::str::contains(
-			"Repo 'nonexistent_repo' not found",
-		));
+		.success()
+		.stdout(predicate::str::contains("some_git_folder"))
+		.stdout(predicate::str::contains("some_other_git_folder").not());
 }
 
 #[test]
-fn tag_remove_repo_not_found() {
+fn status_missing() {
 	let temp = temp_folder();
-	add_a_repo(&temp, "existing_repo", "git://example.org/test_url");
+	let initial_state_toml = "[[repos]]
+path = \"missing_git_folder\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"example_url\"
+";
+	write_gitopolis_state_toml(&temp, initial_state_toml);
 
-	// Try to remove tag from a repo that doesn't exist
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["tag", "--remove", "some_tag", "nonexistent_repo"])
+		.args(vec!["status"])
 		.assert()
 		.failure()
 		.code(1)
-		.stderr(predicate::str::contains(
-			"Repo 'nonexistent_repo' not found",
-		));
+		.stdout(predicate::str::contains(
+			"missing_git_folder  Error: Couldn't open git repo.",
+		))
+		.stderr("1 repos could not be read\n");
 }
 
 #[test]
-fn exec_displays_quoted_args() {
-	// Test that exec command display adds quotes for arguments with spaces
-	// Issue: https://github.com/rustworkshop/gitopolis/issues/86
+fn fetch() {
 	let temp = temp_folder();
-	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+	create_bare_remote(&temp, "upstream.git", "seed");
+	clone_local_repo(&temp, "upstream.git", "work");
+	add_existing_repo(&temp, "work");
+	commit_empty(&temp, "seed", "second");
+	git(&temp, "seed", vec!["push", "origin", "main"]);
 
 	gitopolis_executable()
 		.current_dir(&temp)
-		.args(vec!["exec", "--", "echo", "oh no"])
+		.args(vec!["fetch"])
 		.assert()
 		.success()
-		.stdout(predicate::str::contains("🏢 repo_a> echo 'oh no'"));
+		.stdout(predicate::str::starts_with(
+			"🏢 work> Fetched origin\n  origin/main ",
+		))
+		.stdout(predicate::str::ends_with(
+			"1 fetched, 0 failed, 1 refs updated\n",
+		));
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["fetch"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 work> Fetched origin, up to date.
+1 fetched, 0 failed, 0 refs updated
+",
+		);
 }
 
 #[test]
-fn clone_with_url() {
-	// Test cloning a repository from a URL and automatically adding it to gitopolis
-	// Issue: https://github.com/rustworkshop/gitopolis/issues/193
+fn fetch_prune() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	git(&temp, "seed", vec!["push", "origin", "main:old"]);
+	clone_local_repo(&temp, "upstream.git", "work");
+	add_existing_repo(&temp, "work");
+	git(&temp, "seed", vec!["push", "origin", ":old"]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["fetch"])
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("origin/old").not());
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["fetch", "--prune"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 work> Fetched origin
+  origin/old (pruned)
+1 fetched, 0 failed, 1 refs updated
+",
+		);
+}
+
+#[test]
+fn fetch_all_remotes() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	let other = create_bare_remote(&temp, "other.git", "other_seed");
+	clone_local_repo(&temp, "upstream.git", "work");
+	git(&temp, "work", vec!["remote", "add", "other", &other]);
+	add_existing_repo(&temp, "work");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["fetch"])
+		.assert()
+		.success()
+		.stdout(predicate::str::starts_with(
+			"🏢 work> Fetched origin, up to date.\n",
+		));
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["fetch", "--all-remotes"])
+		.assert()
+		.success()
+		.stdout(predicate::str::starts_with(
+			"🏢 work> Fetched origin, other\n  other/main ",
+		))
+		.stdout(predicate::str::contains(" (new)\n"));
+}
+
+#[test]
+fn fetch_missing_repo() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	clone_local_repo(&temp, "upstream.git", "work");
+	add_existing_repo(&temp, "work");
+	add_existing_repo(&temp, "seed");
+	fs::remove_dir_all(temp.path().join("seed")).unwrap();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["fetch", "-j", "2"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(predicate::str::ends_with(
+			"1 fetched, 1 failed, 0 refs updated\n",
+		))
+		.stderr(predicate::str::contains("Warning: Could not fetch seed"))
+		.stderr(predicate::str::contains("1 repos failed to fetch"));
+}
+
+#[test]
+fn pull() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	for repo_name in ["behind", "dirty", "diverged"] {
+		clone_local_repo(&temp, "upstream.git", repo_name);
+		add_existing_repo(&temp, repo_name);
+	}
+	commit_empty(&temp, "seed", "upstream change");
+	git(&temp, "seed", vec!["push", "origin", "main"]);
+	fs::write(temp.path().join("dirty").join("staged.txt"), "").unwrap();
+	git(&temp, "dirty", vec!["add", "staged.txt"]);
+	commit_empty(&temp, "diverged", "local change");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["pull"])
+		.assert()
+		.success()
+		.stdout(
+			predicate::str::is_match(
+				"^REPO      BRANCH  OUTCOME   DETAIL
+behind    main    updated   [0-9a-f]{7}\\.\\.[0-9a-f]{7} \\(1 commit\\)
+dirty     main    dirty     uncommitted changes
+diverged  main    diverged  1 ahead, 1 behind
+$",
+			)
+			.unwrap(),
+		);
+
+	assert_eq!(rev_parse(&temp, "behind"), rev_parse(&temp, "seed"));
+	assert_ne!(rev_parse(&temp, "dirty"), rev_parse(&temp, "seed"));
+}
+
+#[test]
+fn pull_missing_repo() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	add_existing_repo(&temp, "seed");
+	fs::remove_dir_all(temp.path().join("seed")).unwrap();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["pull", "--output", "ndjson"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(predicate::str::starts_with(
+			"{\"path\":\"seed\",\"tags\":[],\"error\":\"Couldn't open git repo.",
+		))
+		.stderr("1 repos failed to pull\n");
+}
+
+#[test]
+fn branch_create_switch_delete() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	git(&temp, "seed", vec!["push", "origin", "main:shared"]);
+	for repo_name in ["clean", "dirty"] {
+		clone_local_repo(&temp, "upstream.git", repo_name);
+		add_existing_repo(&temp, repo_name);
+	}
+	fs::write(temp.path().join("dirty").join("staged.txt"), "").unwrap();
+	git(&temp, "dirty", vec!["add", "staged.txt"]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "create", "feature"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 clean> Created and switched to feature
+🏢 dirty> Created and switched to feature
+2 changed, 0 skipped, 0 failed
+",
+		);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "switch", "shared"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 clean> Switched to shared
+🏢 dirty> Uncommitted changes, skipped. Use --stash to stash them and switch anyway.
+1 changed, 1 skipped, 0 failed
+",
+		);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "switch", "shared", "--stash"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 clean> Already on shared
+🏢 dirty> Stashed changes and switched to shared
+1 changed, 1 skipped, 0 failed
+",
+		);
+	assert!(!temp.path().join("dirty").join("staged.txt").exists());
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "switch", "nope"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 clean> No branch nope, skipped.
+🏢 dirty> No branch nope, skipped.
+0 changed, 2 skipped, 0 failed
+",
+		);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "delete", "feature"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 clean> Deleted feature
+🏢 dirty> Deleted feature
+2 changed, 0 skipped, 0 failed
+",
+		);
+}
+
+#[test]
+fn branch_list() {
+	let temp = temp_folder();
+	create_bare_remote(&temp, "upstream.git", "seed");
+	git(&temp, "seed", vec!["push", "origin", "main:shared"]);
+	for repo_name in ["first", "second"] {
+		clone_local_repo(&temp, "upstream.git", repo_name);
+		add_existing_repo(&temp, repo_name);
+	}
+	git(&temp, "second", vec!["switch", "-c", "topic"]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "list"])
+		.assert()
+		.success()
+		.stdout(
+			"REPO    main   shared  topic
+first   *both  remote  -
+second  both   remote  *local
+",
+		);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["branch", "list", "t*", "--output", "ndjson"])
+		.assert()
+		.success()
+		.stdout(
+			"{\"path\":\"first\",\"tags\":[],\"current\":\"main\",\"local\":[],\"remote\":[]}
+{\"path\":\"second\",\"tags\":[],\"current\":\"topic\",\"local\":[\"topic\"],\"remote\":[]}
+",
+		);
+}
+
+#[test]
+fn doctor() {
+	let temp = temp_folder();
+	let bare = create_bare_remote(&temp, "upstream.git", "seed");
+	for repo_name in ["drifted", "gone", "healthy"] {
+		clone_local_repo(&temp, "upstream.git", repo_name);
+		add_existing_repo(&temp, repo_name);
+	}
+	fs::remove_dir_all(temp.path().join("seed")).unwrap();
+	fs::remove_dir_all(temp.path().join("gone")).unwrap();
+	git(
+		&temp,
+		"drifted",
+		vec!["remote", "add", "fork", "/example/fork"],
+	);
+	clone_local_repo(&temp, "upstream.git", "nested/orphan");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["doctor"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(
+			"🏢 drifted> Remote fork (/example/fork) is in the repo but not .gitopolis.toml
+🏢 gone> Folder missing
+🏢 nested/orphan> Git repo not in .gitopolis.toml
+3 problems found
+",
+		)
+		.stderr("3 problems left\n");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["doctor", "--output", "ndjson"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(predicate::str::contains(
+			"{\"problem\":\"missing\",\"path\":\"gone\"}\n",
+		));
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["reconcile", "--fix", "clone", "--fix", "add"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout(predicate::str::contains(format!(
+			"🏢 gone> Cloning {bare} ..."
+		)))
+		.stdout(predicate::str::contains(
+			"1 cloned, 0 already existed, 0 had no remotes, 0 failed\n",
+		))
+		.stderr("Added nested/orphan\n1 problems left\n");
+	assert!(temp.path().join("gone").join(".git").exists());
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["sync", "--read-remotes"])
+		.assert()
+		.success();
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["doctor"])
+		.assert()
+		.success()
+		.stdout("No problems found\n");
+}
+
+#[test]
+fn doctor_with_config_in_a_repo() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "", "git://example.org/workspace");
+	add_a_repo(&temp, "a", "git://example.org/a");
+	create_git_repo(&temp, "b", "git://example.org/b");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["doctor"])
+		.assert()
+		.failure()
+		.code(1)
+		.stdout("🏢 b> Git repo not in .gitopolis.toml\n1 problems found\n");
+}
+
+#[test]
+fn doctor_prune() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "gone", "git://example.org/gone");
+	fs::remove_dir_all(temp.path().join("gone")).unwrap();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["doctor", "--fix", "prune"])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 gone> Folder missing
+1 problems found
+Removed 1 missing repos from .gitopolis.toml
+",
+		);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list"])
+		.assert()
+		.failure()
+		.code(2)
+		.stdout("No repos\n");
+}
+
+#[test]
+fn layered_config() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(&temp, "api", "git://example.org/api", vec!["backend"]);
+	create_git_repo(&temp, "tools", "git://example.org/tools");
+	let shared = fs::read_to_string(temp.path().join(".gitopolis.toml")).unwrap();
+	fs::write(
+		temp.path().join(".gitopolis.toml"),
+		format!("include = [\"team/extra.toml\"]\n\n{shared}"),
+	)
+	.unwrap();
+	fs::create_dir_all(temp.path().join("team")).unwrap();
+	fs::write(
+		temp.path().join("team/extra.toml"),
+		"[[repos]]\npath = \"tools\"\ntags = [\"extra\"]\n",
+	)
+	.unwrap();
+	fs::write(
+		temp.path().join(".gitopolis.local.toml"),
+		"[[repos]]\npath = \"tools\"\ntags = [\"mine\"]\n",
+	)
+	.unwrap();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "mine", "api"])
+		.assert()
+		.success();
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["--shared", "tag", "shared", "api"])
+		.assert()
+		.success();
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "-l"])
+		.assert()
+		.success()
+		.stdout(
+			"api\tbackend,mine (local),shared\torigin=git://example.org/api
+tools\textra (team/extra.toml),mine (local)\t
+",
+		);
+	let local = fs::read_to_string(temp.path().join(".gitopolis.local.toml")).unwrap();
+	assert!(local.contains("path = \"api\"\ntags = [\"mine\"]"));
+	let shared = fs::read_to_string(temp.path().join(".gitopolis.toml")).unwrap();
+	assert!(shared.contains("tags = [\"backend\", \"shared\"]"));
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "-r", "extra", "tools"])
+		.assert()
+		.failure()
+		.stderr(
+			"Error: Tag 'extra' on tools comes from team/extra.toml, which gitopolis doesn't change\n",
+		);
+}
+
+#[test]
+fn import_github_org() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(&temp, "api", "git://example.org/api", vec!["backend"]);
+	let page_2 = r#"[{"name": "tools", "clone_url": "https://github.com/acme/tools.git", "ssh_url": "git@github.com:acme/tools.git", "private": true, "archived": true, "language": null, "topics": []}]"#;
+	let page_1 = r#"[{"name": "api", "clone_url": "https://github.com/acme/api.git", "ssh_url": "git@github.com:acme/api.git", "private": false, "archived": false, "language": "Rust", "topics": ["web"]}]"#;
+	let (api_url, requests) = serve_api(vec![
+		(
+			"/orgs/acme/repos?per_page=100",
+			"Link: <{api_url}/organizations/7/repos?per_page=100&page=2>; rel=\"next\"",
+			page_1,
+		),
+		("/organizations/7/repos?per_page=100&page=2", "", page_2),
+	]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.env("GITHUB_TOKEN", "secret")
+		.args(vec![
+			"import",
+			"github",
+			"--org",
+			"acme",
+			"--api-url",
+			&api_url,
+			"--tag",
+			"acme",
+		])
+		.assert()
+		.success()
+		.stdout(
+			"🏢 api> Tagged acme,public,rust,web
+🏢 tools> Added
+1 added, 1 tagged, 0 unchanged
+",
+		);
+	assert_eq!(
+		vec![
+			"GET /orgs/acme/repos?per_page=100 Bearer secret",
+			"GET /organizations/7/repos?per_page=100&page=2 Bearer secret",
+		],
+		*requests.lock().unwrap()
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "-l"])
+		.assert()
+		.success()
+		.stdout(
+			"api\tacme,backend,public,rust,web\torigin=git://example.org/api
+tools\tacme,archived,private\torigin=git@github.com:acme/tools.git
+",
+		);
+}
+
+#[test]
+fn import_reports_api_errors() {
+	let temp = temp_folder();
+	let (api_url, _) = serve_api(vec![]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec![
+			"import", "gitlab", "--group", "team/sub", "--api-url", &api_url,
+		])
+		.assert()
+		.failure()
+		.code(1)
+		.stderr(format!(
+			"Error: {api_url}/groups/team%2Fsub/projects?per_page=100&include_subgroups=true returned 404 Not Found: 404 Group Not Found\n"
+		));
+	assert!(!temp.path().join(".gitopolis.toml").exists());
+}
+
+#[test]
+fn completions() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(&temp, "repo_a", "git://example.org/test_url", vec!["web"]);
+	add_a_repo_with_tags(&temp, "repo_b", "git://example.org/test_url2", vec!["api"]);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["completions", "bash"])
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("GITOPOLIS_COMPLETE=\"bash\""));
+
+	let complete = |args: Vec<&str>, folder: &str| {
+		let output = gitopolis_executable()
+			.current_dir(temp.path().join(folder))
+			.env("GITOPOLIS_COMPLETE", "fish")
+			.arg("--")
+			.arg("gitopolis")
+			.args(args)
+			.output()
+			.expect("completion failed");
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.lines()
+			.filter(|line| !line.starts_with('-'))
+			.map(String::from)
+			.collect::<Vec<String>>()
+	};
+	assert_eq!(vec!["api", "web"], complete(vec!["tag", ""], ""));
+	assert_eq!(vec!["web,api"], complete(vec!["exec", "--tag", "web,"], ""));
+	assert_eq!(vec!["repo_a", "repo_b"], complete(vec!["show", ""], ""));
+	assert_eq!(vec![".", "../repo_b"], complete(vec!["show", ""], "repo_a"));
+}
+
+#[test]
+fn list_json() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"some_git_folder",
+		"git://example.org/test_url",
+		vec!["some_tag"],
+	);
+
+	let expected_stdout = r#"[
+  {
+    "path": "some_git_folder",
+    "tags": [
+      "some_tag"
+    ],
+    "remotes": {
+      "origin": {
+        "name": "origin",
+        "url": "git://example.org/test_url"
+      }
+    }
+  }
+]
+"#;
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--output", "json"])
+		.assert()
+		.success()
+		.stdout(expected_stdout);
+}
+
+#[test]
+fn list_ndjson() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_b");
+
+	let expected_stdout = r#"{"path":"repo_a","tags":[],"remotes":{"origin":{"name":"origin","url":"git://example.org/test_a"}}}
+{"path":"repo_b","tags":[],"remotes":{"origin":{"name":"origin","url":"git://example.org/test_b"}}}
+"#;
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["list", "--output", "ndjson"])
+		.assert()
+		.success()
+		.stdout(expected_stdout);
+}
+
+#[test]
+fn tags_long_ndjson() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"some_git_folder",
+		"git://example.org/test_url",
+		vec!["some_tag"],
+	);
+	add_a_repo_with_tags(
+		&temp,
+		"some_other_git_folder",
+		"git://example.org/test_url2",
+		vec!["some_tag", "another_tag"],
+	);
+
+	let expected_stdout = r#"{"tag":"another_tag","repos":["some_other_git_folder"]}
+{"tag":"some_tag","repos":["some_git_folder","some_other_git_folder"]}
+"#;
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tags", "--long", "--output", "ndjson"])
+		.assert()
+		.success()
+		.stdout(expected_stdout);
+}
+
+#[test]
+fn show_ndjson() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"some_git_folder",
+		"git://example.org/test_url",
+		vec!["some_tag"],
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["show", "some_git_folder", "--output", "ndjson"])
+		.assert()
+		.success()
+		.stdout("{\"path\":\"some_git_folder\",\"tags\":[\"some_tag\"],\"remotes\":{\"origin\":{\"name\":\"origin\",\"url\":\"git://example.org/test_url\"}}}\n");
+}
+
+#[test]
+fn status_ndjson() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "some_git_folder", "git://example.org/test_url");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["status", "--output", "ndjson"])
+		.assert()
+		.success()
+		.stdout("{\"path\":\"some_git_folder\",\"tags\":[],\"head\":{\"type\":\"unborn\",\"name\":\"main\"},\"ahead_behind\":null,\"staged\":0,\"unstaged\":0,\"untracked\":0,\"stashes\":0,\"in_progress\":null}\n");
+}
+
+#[test]
+fn exec_json() {
+	let temp = temp_folder();
+	add_a_repo_with_tags(
+		&temp,
+		"repo_a",
+		"git://example.org/test_a",
+		vec!["some_tag"],
+	);
+	add_a_repo(&temp, "repo_b", "git://example.org/test_b");
+
+	let output = gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec![
+			"exec",
+			"--output",
+			"json",
+			"--",
+			"git",
+			"config",
+			"remote.origin.url",
+		])
+		.output()
+		.expect("failed to run exec");
+	assert!(output.status.success());
+
+	let records: serde_json::Value =
+		serde_json::from_slice(&output.stdout).expect("stdout should be valid json");
+	let records = records.as_array().expect("should be an array");
+	assert_eq!(2, records.len());
+	assert_eq!("repo_a", records[0]["path"]);
+	assert_eq!(serde_json::json!(["some_tag"]), records[0]["tags"]);
+	assert_eq!(
+		serde_json::json!(["git", "config", "remote.origin.url"]),
+		records[0]["command"]
+	);
+	assert_eq!(0, records[0]["exit_code"]);
+	assert_eq!(false, records[0]["skipped"]);
+	assert!(records[0]["duration_ms"].is_number());
+	assert_eq!("git://example.org/test_a\n", records[0]["stdout"]);
+	assert_eq!("", records[0]["stderr"]);
+	assert_eq!("repo_b", records[1]["path"]);
+	assert_eq!("git://example.org/test_b\n", records[1]["stdout"]);
+}
+
+#[test]
+fn exec_ndjson_non_zero_and_missing() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "repo_a", "git://example.org/test_url");
+	let initial_state_toml = "[[repos]]
+path = \"missing_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"example_url\"
+
+[[repos]]
+path = \"repo_a\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+";
+	write_gitopolis_state_toml(&temp, initial_state_toml);
+
+	let output = gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec![
+			"exec",
+			"--output",
+			"ndjson",
+			"--",
+			"git",
+			"config",
+			"not.a.setting",
+		])
+		.output()
+		.expect("failed to run exec");
+	assert_eq!(Some(1), output.status.code());
+	assert_eq!(
+		"1 commands exited with non-zero status code\n1 repos skipped\n",
+		String::from_utf8_lossy(&output.stderr)
+	);
+
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let records: Vec<serde_json::Value> = stdout
+		.lines()
+		.map(|line| serde_json::from_str(line).expect("each line should be valid json"))
+		.collect();
+	assert_eq!(2, records.len());
+	assert_eq!("missing_repo", records[0]["path"]);
+	assert_eq!(true, records[0]["skipped"]);
+	assert!(records[0]["exit_code"].is_null());
+	assert_eq!("repo_a", records[1]["path"]);
+	assert_eq!(1, records[1]["exit_code"]);
+}
+
+#[test]
+fn config_found_in_parent_folder() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+	add_a_repo(&temp, "repo_b", "git://example.org/test_b");
+	let nested_folder = temp.path().join("repo_a").join("src");
+	fs::create_dir_all(&nested_folder).expect("create nested dir failed");
+
+	gitopolis_executable()
+		.current_dir(&nested_folder)
+		.args(vec!["list"])
+		.assert()
+		.success()
+		.stdout("repo_a\nrepo_b\n");
+
+	// commands run in each repo's folder, relative to the config file rather than the current dir
+	gitopolis_executable()
+		.current_dir(&nested_folder)
+		.args(vec![
+			"exec",
+			"--oneline",
+			"--",
+			"git",
+			"config",
+			"remote.origin.url",
+		])
+		.assert()
+		.success()
+		.stdout("repo_a\tgit://example.org/test_a\nrepo_b\tgit://example.org/test_b\n");
+}
+
+#[test]
+fn config_found_in_parent_folder_add_and_tag() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+	create_git_repo(&temp, "group/repo_b", "git://example.org/test_b");
+	let group_folder = temp.path().join("group");
+
+	// folder arguments are relative to the current dir but stored relative to the config file
+	gitopolis_executable()
+		.current_dir(&group_folder)
+		.args(vec!["add", "repo_b"])
+		.assert()
+		.success()
+		.stderr(predicate::str::contains("Added group/repo_b\n"));
+
+	gitopolis_executable()
+		.current_dir(&group_folder)
+		.args(vec!["tag", "some_tag", "repo_b", "../repo_a"])
+		.assert()
+		.success();
+
+	let expected_toml = "[[repos]]
+path = \"group/repo_b\"
+tags = [\"some_tag\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_b\"
+
+[[repos]]
+path = \"repo_a\"
+tags = [\"some_tag\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_a\"
+";
+	assert_eq!(expected_toml, read_gitopolis_state_toml(&temp));
+	assert!(!group_folder.join(".gitopolis.toml").exists());
+}
+
+#[test]
+fn config_option() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+	let elsewhere = temp_folder();
+
+	gitopolis_executable()
+		.current_dir(&elsewhere)
+		.args(vec![
+			"list",
+			"--config",
+			temp.path().join(".gitopolis.toml").to_str().unwrap(),
+		])
+		.assert()
+		.success()
+		.stdout("repo_a\n");
+
+	gitopolis_executable()
+		.current_dir(&elsewhere)
+		.env(
+			"GITOPOLIS_CONFIG",
+			temp.path().join(".gitopolis.toml").to_str().unwrap(),
+		)
+		.args(vec![
+			"exec",
+			"--oneline",
+			"--",
+			"git",
+			"config",
+			"remote.origin.url",
+		])
+		.assert()
+		.success()
+		.stdout("repo_a\tgit://example.org/test_a\n");
+}
+
+#[test]
+fn config_option_outside_folder_errors() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+	let elsewhere = temp_folder();
+	create_git_repo(&elsewhere, "repo_b", "git://example.org/test_b");
+
+	gitopolis_executable()
+		.current_dir(&elsewhere)
+		.env(
+			"GITOPOLIS_CONFIG",
+			temp.path().join(".gitopolis.toml").to_str().unwrap(),
+		)
+		.args(vec!["add", "repo_b"])
+		.assert()
+		.failure()
+		.code(1)
+		.stderr(predicate::str::contains("repo_b is not inside"));
+}
+
+#[test]
+fn show() {
+	let temp = temp_folder();
+	let path = &temp.path().join("test_repo");
+	fs::create_dir_all(path).expect("create repo dir failed");
+
+	// Initialize git repo
+	Command::new("git")
+		.current_dir(path)
+		.args(vec!["init", "--initial-branch", "main"])
+		.output()
+		.expect("git init failed");
+
+	// Add multiple remotes
+	Command::new("git")
+		.current_dir(path)
+		.args(vec![
+			"remote",
+			"add",
+			"origin",
+			"git://example.org/origin_url",
+		])
+		.output()
+		.expect("git remote add origin failed");
+
+	Command::new("git")
+		.current_dir(path)
+		.args(vec![
+			"remote",
+			"add",
+			"upstream",
+			"git://example.org/upstream_url",
+		])
+		.output()
+		.expect("git remote add upstream failed");
+
+	// Add repo to gitopolis and tag it
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["add", "test_repo"])
+		.output()
+		.expect("Failed to add repo");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "backend", "test_repo"])
+		.output()
+		.expect("Failed to tag repo");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "rust", "test_repo"])
+		.output()
+		.expect("Failed to tag repo");
+
+	// Run show command
+	let expected_output = "Tags:
+  backend
+  rust
+
+Remotes:
+  origin: git://example.org/origin_url
+  upstream: git://example.org/upstream_url
+";
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["show", "test_repo"])
+		.assert()
+		.success()
+		.stdout(expected_output);
+}
+
+#[test]
+fn show_repo_not_found() {
+	let temp = temp_folder();
+
+	// Try to show a repo that doesn't exist
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["show", "nonexistent_repo"])
+		.assert()
+		.failure()
+		.code(1)
+		.stderr(predicate::str::contains(
+			"Repo 'nonexistent_repo' not found",
+		));
+}
+
+#[test]
+fn tag_repo_not_found() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "existing_repo", "git://example.org/test_url");
+
+	// Try to tag a repo that doesn't exist
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "some_tag", "nonexistent_repo"])
+		.assert()
+		.failure()
+		.code(1)
+		.stderr(predicate::str::contains(
+			"Repo 'nonexistent_repo' not found",
+		));
+}
+
+#[test]
+fn tag_remove_repo_not_found() {
+	let temp = temp_folder();
+	add_a_repo(&temp, "existing_repo", "git://example.org/test_url");
+
+	// Try to remove tag from a repo that doesn't exist
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["tag", "--remove", "some_tag", "nonexistent_repo"])
+		.assert()
+		.failure()
+		.code(1)
+		.stderr(predicate::str::contains(
+			"Repo 'nonexistent_repo' not found",
+		));
+}
+
+#[test]
+fn exec_displays_quoted_args() {
+	// Test that exec command display adds quotes for arguments with spaces
+	// Issue: https://github.com/rustworkshop/gitopolis/issues/86
+	let temp = temp_folder();
+	add_a_repo(&temp, "repo_a", "git://example.org/test_a");
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["exec", "--", "echo", "oh no"])
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("🏢 repo_a> echo 'oh no'"));
+}
+
+#[test]
+fn clone_with_url() {
+	// Test cloning a repository from a URL and automatically adding it to gitopolis
+	// Issue: https://github.com/rustworkshop/gitopolis/issues/193
 	let temp = temp_folder();
 
 	// Create a source repo in a subdirectory
@@ -1830,6 +3889,62 @@ url = \"git://example.org/test_url\"
 		.stdout("new_location\n");
 }
 
+#[test]
+fn move_repo_preserves_comments() {
+	let temp = temp_folder();
+	create_git_repo(&temp, "old_location", "git://example.org/test_url");
+	create_git_repo(&temp, "other", "git://example.org/other");
+	write_gitopolis_state_toml(
+		&temp,
+		"# Our repos
+
+# moving soon
+[[repos]]
+path = \"old_location\" # was here
+tags = [\"some_tag\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+[[repos]]
+path = \"other\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/other\"
+",
+	);
+
+	gitopolis_executable()
+		.current_dir(&temp)
+		.args(vec!["move", "repo", "old_location", "z/new_location"])
+		.assert()
+		.success();
+
+	let expected_toml = "# Our repos
+
+# moving soon
+[[repos]]
+path = \"z/new_location\" # was here
+tags = [\"some_tag\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+[[repos]]
+path = \"other\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/other\"
+";
+	assert_eq!(expected_toml, read_gitopolis_state_toml(&temp));
+}
+
 #[test]
 fn move_repo_with_nested_path() {
 	// Test move operation with nested source and target paths, creating parent directories
@@ -1,7 +1,22 @@
-use gitopolis::git::Git;
-use gitopolis::gitopolis::{Gitopolis, GitopolisError};
+use gitopolis::doctor::{Problem, RemoteDifference};
+use gitopolis::git::{
+	Branches, CloneOutput, CloneProgress, FastForward, FetchOptions, FetchResult, Git, Head,
+	RepoStatus, UpdatedRef,
+};
+use gitopolis::gitopolis::{
+	BranchOutcome, CloneOutcome, CloneSummary, FetchSummary, Gitopolis, GitopolisError,
+	ImportOutcome, PullOutcome,
+};
+use gitopolis::layers::Layer;
+use gitopolis::observer::{Event, Observer, RepoOutcome};
+use gitopolis::repos::{Remote, Repo};
+use gitopolis::state_filter::StateFilter;
 use gitopolis::storage::Storage;
 use gitopolis::tag_filter::TagFilter;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[test]
 fn add() {
@@ -66,10 +81,33 @@ url = \"git://example.org/test_url\"\
 	let filter = TagFilter::all();
 	let repos_result = gitopolis.list(&filter);
 	let actual_error = repos_result.expect_err("should error");
-	let expected_error = "Failed to parse state data as valid TOML. TOML parse error at line 1, column 1\n  |\n1 | [[NOT_A_repos]]\n  | ^^^^^^^^^^^^^^^\nmissing field `remotes`\n";
+	let expected_error = "Failed to parse state data as valid TOML. TOML parse error at line 1, column 3\n  |\n1 | [[NOT_A_repos]]\n  |   ^^^^^^^^^^^\nunknown field `NOT_A_repos`, expected one of `include`, `repos`, `aliases`\n";
 	assert_eq!(expected_error, actual_error.message())
 }
 
+#[test]
+fn list_ignores_unknown_keys_in_repos() {
+	let starting_state = "[[repos]]
+path = \"test_repo\"
+tags = []
+note = \"added by hand\"
+[repos.remotes]
+";
+
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+
+	let git = FakeGit::new().boxed();
+	let gitopolis = Gitopolis::new(storage, git);
+	let repos = gitopolis
+		.list(&TagFilter::all())
+		.expect("unknown keys in repos should be ignored");
+
+	assert_eq!(1, repos.len());
+	assert_eq!("test_repo", repos[0].path);
+}
+
 #[test]
 fn clone() {
 	// todo: test cloning more than one repo
@@ -101,6 +139,205 @@ url = \"git://example.org/test_url\"\
 		gitopolis
 			.list(&filter)
 			.expect("Failed to list repos for cloning"),
+		1,
+	);
+}
+
+#[test]
+fn clone_sends_events_to_observer() {
+	let starting_state = "[[repos]]
+path = \"repo_a\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/repo_a\"
+
+[[repos]]
+path = \"repo_b\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/broken\"
+";
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+	let events = Arc::new(Mutex::new(vec![]));
+	let seen_by_git = events.clone();
+	let git = FakeGit::new()
+		.with_clone_callback(Box::new(move |path, _| {
+			// hold repo_a until repo_b has started, which only happens if starts are sent live
+			let waiting_since = Instant::now();
+			while path == "repo_a"
+				&& !seen_by_git
+					.lock()
+					.unwrap()
+					.contains(&"started repo_b".to_string())
+				&& waiting_since.elapsed() < Duration::from_secs(5)
+			{
+				thread::sleep(Duration::from_millis(1));
+			}
+		}))
+		.with_failing_clone("git://example.org/broken")
+		.boxed();
+	let gitopolis = Gitopolis::new(storage, git).with_observer(Box::new(RecordingObserver {
+		events: events.clone(),
+	}));
+
+	gitopolis.clone(
+		gitopolis
+			.list(&TagFilter::all())
+			.expect("Failed to list repos for cloning"),
+		2,
+	);
+
+	let events = events.lock().unwrap();
+	let position = |summary: &str| {
+		events
+			.iter()
+			.position(|event| event == summary)
+			.unwrap_or_else(|| panic!("no '{summary}' in {events:?}"))
+	};
+	assert_eq!(
+		events.first().unwrap(),
+		"operation started Clone 2, 2 at a time"
+	);
+	assert_eq!(events.last().unwrap(), "operation finished Clone");
+	assert!(position("started repo_b") < position("finished repo_a cloned"));
+	assert!(position("started repo_a") < position("progress repo_a 100%"));
+	assert!(position("progress repo_a 100%") < position("finished repo_a cloned"));
+	assert!(position("started repo_b") < position("progress repo_b 100%"));
+	assert!(position("progress repo_b 100%") < position("finished repo_b failed"));
+	assert!(position("finished repo_a cloned") < position("finished repo_b failed"));
+}
+
+#[test]
+fn clone_with_root_resolves_repo_paths() {
+	let starting_state = "[[repos]]
+path = \"test_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"\
+";
+
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+
+	let git = FakeGit::new()
+		.with_clone_callback(Box::new(|actual_path, _| {
+			let expected_path = std::path::Path::new("some_root").join("test_repo");
+			assert_eq!(actual_path, expected_path.to_string_lossy());
+		}))
+		.boxed();
+
+	let gitopolis = Gitopolis::new(storage, git).with_root("some_root".into());
+
+	let filter = TagFilter::all();
+	gitopolis.clone(
+		gitopolis
+			.list(&filter)
+			.expect("Failed to list repos for cloning"),
+		1,
+	);
+}
+
+#[test]
+fn clone_parallel_summarises_outcomes() {
+	let starting_state = "[[repos]]
+path = \"repo_a\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/repo_a\"
+
+[[repos]]
+path = \"repo_b\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/broken\"
+
+[[repos]]
+path = \"repo_c\"
+tags = []
+
+[repos.remotes.upstream]
+name = \"upstream\"
+url = \"git://example.org/repo_c\"
+
+[[repos]]
+path = \"repo_d\"
+tags = []
+
+[repos.remotes]
+";
+
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+	let cloned = Arc::new(Mutex::new(vec![]));
+	let cloned_by_git = cloned.clone();
+	let git = FakeGit::new()
+		.with_clone_callback(Box::new(move |path, url| {
+			cloned_by_git.lock().unwrap().push((path, url));
+		}))
+		.with_failing_clone("git://example.org/broken")
+		.boxed();
+
+	let gitopolis = Gitopolis::new(storage, git);
+
+	let report = gitopolis.clone(
+		gitopolis
+			.list(&TagFilter::all())
+			.expect("Failed to list repos for cloning"),
+		3,
+	);
+
+	let failed: Vec<(&str, bool)> = report
+		.repos
+		.iter()
+		.map(|(repo, outcome)| {
+			(
+				repo.path.as_str(),
+				matches!(outcome, CloneOutcome::Failed { .. }),
+			)
+		})
+		.collect();
+	assert_eq!(
+		failed,
+		vec![
+			("repo_a", false),
+			("repo_b", true),
+			("repo_c", false),
+			("repo_d", false)
+		]
+	);
+	assert_eq!(
+		report.summary(),
+		CloneSummary {
+			cloned: 2,
+			skipped: 0,
+			no_remotes: 1,
+			failed: 1,
+		}
+	);
+	let mut cloned = cloned.lock().unwrap().clone();
+	cloned.sort();
+	assert_eq!(
+		cloned,
+		vec![
+			("repo_a".to_string(), "git://example.org/repo_a".to_string()),
+			("repo_b".to_string(), "git://example.org/broken".to_string()),
+			// no origin so falls back to the first remote
+			("repo_c".to_string(), "git://example.org/repo_c".to_string()),
+		]
 	);
 }
 
@@ -137,6 +374,74 @@ url = \"git://example.org/test_url\"
 		.expect("Failed to add tag to repo");
 }
 
+#[test]
+fn import_adds_new_repos_and_only_tags_existing_ones() {
+	let starting_state = "[[repos]]
+path = \"api\"
+tags = [\"backend\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
+";
+
+	let expected_toml = "[[repos]]
+path = \"api\"
+tags = [\"backend\", \"rust\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
+
+[[repos]]
+path = \"tools\"
+tags = [\"archived\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git@example.org:tools.git\"
+";
+
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.with_file_saved_callback(|state| assert_eq!(expected_toml.to_owned(), state))
+		.boxed();
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+	let imported = |path: &str, tags: Vec<&str>| {
+		let mut remotes = BTreeMap::new();
+		remotes.insert(
+			"origin".to_string(),
+			Remote {
+				name: "origin".to_string(),
+				url: format!("git@example.org:{path}.git"),
+			},
+		);
+		Repo {
+			path: path.to_string(),
+			tags: tags.into_iter().map(String::from).collect(),
+			remotes,
+		}
+	};
+
+	let outcomes = gitopolis
+		.import(vec![
+			imported("api", vec!["backend", "rust"]),
+			imported("tools", vec!["archived"]),
+		])
+		.expect("import failed");
+
+	let outcomes: Vec<ImportOutcome> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+	assert_eq!(
+		vec![
+			ImportOutcome::Tagged {
+				tags: vec!["rust".to_string()]
+			},
+			ImportOutcome::Added,
+		],
+		outcomes
+	);
+}
+
 #[test]
 fn remove_tag() {
 	let starting_state = "[[repos]]
@@ -422,86 +727,983 @@ url = \"git://example.org/upstream_url\"\
 	);
 }
 
-struct FakeStorage {
-	exists: bool,
-	contents: String,
-	file_saved_callback: Box<dyn Fn(String)>,
-}
+#[test]
+fn status_reads_each_filtered_repo() {
+	let starting_state = "[[repos]]
+path = \"backend_repo\"
+tags = [\"backend\"]
 
-// fluent interface for building up fake storage (like the "builder pattern")
-impl FakeStorage {
-	fn new() -> Self {
-		Self {
-			exists: false,
-			contents: "".to_string(),
-			file_saved_callback: Box::new(|_| {}),
-		}
-	}
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/backend\"
 
-	fn with_contents(mut self, contents: String) -> Self {
-		self.exists = true;
-		self.contents = contents;
-		self
-	}
+[[repos]]
+path = \"frontend_repo\"
+tags = [\"frontend\"]
 
-	fn with_file_saved_callback<F>(mut self, callback: F) -> Self
-	where
-		F: Fn(String) + 'static, // todo: would it be possible to shrink lifetime from static?
-	{
-		self.file_saved_callback = Box::new(callback);
-		self
-	}
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/frontend\"\
+";
 
-	fn boxed(self) -> Box<dyn Storage> {
-		Box::new(self)
-	}
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+
+	let git = FakeGit::new().boxed();
+	let gitopolis = Gitopolis::new(storage, git);
+
+	let filter = TagFilter::from_cli_args(&["backend".to_string()]);
+	let entries = gitopolis
+		.status(&filter, &StateFilter::default())
+		.expect("Failed to read status");
+
+	assert_eq!(1, entries.len());
+	let (repo, status) = &entries[0];
+	assert_eq!("backend_repo", repo.path);
+	let status = status.as_ref().expect("status should be readable");
+	assert_eq!(Head::Branch("main".to_string()), status.head);
+	assert_eq!(Some((1, 2)), status.ahead_behind);
 }
 
-impl Storage for FakeStorage {
-	fn exists(&self) -> bool {
-		self.exists
-	}
+#[test]
+fn select_filters_by_git_state() {
+	let starting_state = ["dirty_feature", "missing", "on_main", "untracked_only"]
+		.map(|path| format!("[[repos]]\npath = \"{path}\"\ntags = []\n\n[repos.remotes]\n"))
+		.join("\n");
+	let on_main = RepoStatus {
+		head: Head::Branch("main".to_string()),
+		ahead_behind: Some((0, 0)),
+		staged: 0,
+		unstaged: 0,
+		untracked: 0,
+		stashes: 0,
+		in_progress: None,
+	};
+	let git = FakeGit::new()
+		.with_status(
+			"dirty_feature",
+			RepoStatus {
+				head: Head::Branch("feature/login".to_string()),
+				ahead_behind: Some((2, 0)),
+				staged: 1,
+				..on_main.clone()
+			},
+		)
+		.with_failing_status("missing")
+		.with_status("on_main", on_main.clone())
+		.with_status(
+			"untracked_only",
+			RepoStatus {
+				untracked: 3,
+				..on_main.clone()
+			},
+		)
+		.boxed();
+	let storage = FakeStorage::new().with_contents(starting_state).boxed();
+	let gitopolis = Gitopolis::new(storage, git);
+	let paths = |state: StateFilter| -> Vec<String> {
+		gitopolis
+			.select(&TagFilter::all(), &state)
+			.expect("Failed to select repos")
+			.into_iter()
+			.map(|repo| repo.path)
+			.collect()
+	};
 
-	fn save(&self, state_toml: String) {
-		(self.file_saved_callback)(state_toml);
-	}
+	assert_eq!(
+		vec!["dirty_feature", "missing", "on_main", "untracked_only"],
+		paths(StateFilter::default())
+	);
+	assert_eq!(
+		vec!["dirty_feature"],
+		paths(StateFilter {
+			dirty: true,
+			..Default::default()
+		})
+	);
+	assert_eq!(
+		vec!["on_main", "untracked_only"],
+		paths(StateFilter {
+			clean: true,
+			..Default::default()
+		})
+	);
+	assert_eq!(
+		vec!["dirty_feature"],
+		paths(StateFilter {
+			ahead: true,
+			not_on_default_branch: true,
+			..Default::default()
+		})
+	);
+	assert_eq!(
+		vec!["on_main", "untracked_only"],
+		paths(StateFilter {
+			on_branch: Some("main".to_string()),
+			..Default::default()
+		})
+	);
 
-	fn read(&self) -> String {
-		self.contents.to_owned()
-	}
+	let entries = gitopolis
+		.status(
+			&TagFilter::all(),
+			&StateFilter {
+				dirty: true,
+				..Default::default()
+			},
+		)
+		.expect("Failed to read status");
+	assert_eq!(1, entries.len());
+	assert_eq!("dirty_feature", entries[0].0.path);
 }
 
-struct FakeGit {
-	clone_callback: Box<dyn Fn(String, String)>,
-}
+const COMMENTED_STATE: &str = "# Team repos, edit by hand as needed
 
-// fluent interface for building up fake git
-impl FakeGit {
-	fn new() -> Self {
-		Self {
-			clone_callback: Box::new(|_, _| {}),
-		}
-	}
+# the main api
+[[repos]]
+path = \"api\" # keep first
+tags = [\"backend\"] # backend only
 
-	fn with_clone_callback(mut self, callback: Box<dyn Fn(String, String)>) -> Self {
-		self.clone_callback = callback;
-		self
-	}
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
 
-	fn boxed(self) -> Box<Self> {
-		Box::new(self)
-	}
+# the website
+[[repos]]
+path = \"web\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/web\"
+
+# trailing notes
+";
+
+fn assert_saved_state(starting_state: &str, expected_toml: &'static str) -> Box<dyn Storage> {
+	FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.with_file_saved_callback(move |state| assert_eq!(expected_toml, state))
+		.boxed()
 }
 
-impl Git for FakeGit {
-	fn read_url(&self, _path: String, _remote_name: String) -> Result<String, GitopolisError> {
-		Ok("git://example.org/test_url".to_string())
-	}
+#[test]
+fn add_preserves_comments() {
+	let expected_toml = "# Team repos, edit by hand as needed
 
-	fn read_all_remotes(
-		&self,
-		_path: String,
-	) -> Result<std::collections::BTreeMap<String, String>, GitopolisError> {
+# the main api
+[[repos]]
+path = \"api\" # keep first
+tags = [\"backend\"] # backend only
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
+
+[[repos]]
+path = \"cli\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+# the website
+[[repos]]
+path = \"web\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/web\"
+
+# trailing notes
+";
+	let storage = assert_saved_state(COMMENTED_STATE, expected_toml);
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	gitopolis
+		.add("cli".to_string())
+		.expect("Failed to add repo");
+}
+
+#[test]
+fn add_first_keeps_header_comments_at_top() {
+	let expected_toml = "# Team repos, edit by hand as needed
+
+[[repos]]
+path = \"aardvark\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+# the main api
+[[repos]]
+path = \"api\" # keep first
+tags = [\"backend\"] # backend only
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
+
+# the website
+[[repos]]
+path = \"web\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/web\"
+
+# trailing notes
+";
+	let storage = assert_saved_state(COMMENTED_STATE, expected_toml);
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	gitopolis
+		.add("aardvark".to_string())
+		.expect("Failed to add repo");
+}
+
+#[test]
+fn add_keeps_tables_between_repos_in_place() {
+	let starting_state = "# team config
+
+[[repos]]
+path = \"b\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/b\"
+
+# saved commands
+[aliases.st]
+command = [\"git\", \"status\"]
+
+[[repos]]
+path = \"c\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/c\"
+";
+	let expected_toml = "# team config
+
+[[repos]]
+path = \"a\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+[[repos]]
+path = \"b\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/b\"
+
+# saved commands
+[aliases.st]
+command = [\"git\", \"status\"]
+
+[[repos]]
+path = \"c\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/c\"
+
+[[repos]]
+path = \"d\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+";
+	let storage = assert_saved_state(starting_state, expected_toml);
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	gitopolis
+		.add_repos(vec!["a".to_string(), "d".to_string()], &[])
+		.expect("Failed to add repos");
+}
+
+#[test]
+fn tag_preserves_comments() {
+	let expected_toml = "# Team repos, edit by hand as needed
+
+# the main api
+[[repos]]
+path = \"api\" # keep first
+tags = [\"backend\", \"team_a\"] # backend only
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
+
+# the website
+[[repos]]
+path = \"web\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/web\"
+
+# trailing notes
+";
+	let storage = assert_saved_state(COMMENTED_STATE, expected_toml);
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	gitopolis
+		.add_tag("team_a", &["api".to_string()])
+		.expect("Failed to add tag to repo");
+}
+
+#[test]
+fn remove_preserves_comments() {
+	let expected_toml = "# Team repos, edit by hand as needed
+
+# the website
+[[repos]]
+path = \"web\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/web\"
+
+# trailing notes
+";
+	let storage = assert_saved_state(COMMENTED_STATE, expected_toml);
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	gitopolis
+		.remove(&["api".to_string()])
+		.expect("Failed to remove repo");
+}
+
+#[test]
+fn sync_read_remotes_preserves_comments() {
+	let expected_toml = "# Team repos, edit by hand as needed
+
+# the main api
+[[repos]]
+path = \"api\" # keep first
+tags = [\"backend\"] # backend only
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+# the website
+[[repos]]
+path = \"web\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/web\"
+
+# trailing notes
+";
+	let storage = assert_saved_state(COMMENTED_STATE, expected_toml);
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	gitopolis
+		.sync_read_remotes(&TagFilter::from_cli_args(&["backend".to_string()]))
+		.expect("Failed to sync remotes");
+}
+
+#[test]
+fn fetch_summarises_outcomes() {
+	let starting_state = "[[repos]]
+path = \"repo_a\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/repo_a\"
+
+[[repos]]
+path = \"repo_b\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/repo_b\"
+";
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+	let git = FakeGit::new().with_failing_fetch("repo_b").boxed();
+	let gitopolis = Gitopolis::new(storage, git);
+
+	let report = gitopolis.fetch(
+		gitopolis
+			.list(&TagFilter::all())
+			.expect("Failed to list repos for fetching"),
+		&FetchOptions {
+			prune: true,
+			all_remotes: false,
+		},
+		2,
+	);
+
+	assert_eq!(
+		report.summary(),
+		FetchSummary {
+			fetched: 1,
+			failed: 1,
+			updated_refs: 2,
+		}
+	);
+	let (repo, result) = &report.repos[1];
+	assert_eq!(repo.path, "repo_b");
+	assert_eq!(
+		result.as_ref().expect_err("repo_b should fail").message(),
+		"Failed to fetch origin"
+	);
+}
+
+#[test]
+fn pull_only_fast_forwards_clean_repos_with_upstream() {
+	let starting_state = ["clean", "detached", "dirty", "no_upstream"]
+		.map(|path| format!("[[repos]]\npath = \"{path}\"\ntags = []\n\n[repos.remotes]\n"))
+		.join("\n");
+	let clean = RepoStatus {
+		head: Head::Branch("main".to_string()),
+		ahead_behind: Some((0, 2)),
+		staged: 0,
+		unstaged: 0,
+		untracked: 1,
+		stashes: 0,
+		in_progress: None,
+	};
+	let git = FakeGit::new()
+		.with_status("clean", clean.clone())
+		.with_status(
+			"detached",
+			RepoStatus {
+				head: Head::Detached("abcdef1".to_string()),
+				ahead_behind: None,
+				..clean.clone()
+			},
+		)
+		.with_status(
+			"dirty",
+			RepoStatus {
+				unstaged: 1,
+				..clean.clone()
+			},
+		)
+		.with_status(
+			"no_upstream",
+			RepoStatus {
+				ahead_behind: None,
+				..clean.clone()
+			},
+		)
+		.boxed();
+	let storage = FakeStorage::new().with_contents(starting_state).boxed();
+	let gitopolis = Gitopolis::new(storage, git);
+
+	let entries = gitopolis.pull(
+		gitopolis
+			.list(&TagFilter::all())
+			.expect("Failed to list repos for pulling"),
+		2,
+	);
+
+	let outcomes: Vec<(String, PullOutcome)> = entries
+		.into_iter()
+		.map(|(repo, result)| (repo.path, result.expect("pull failed")))
+		.collect();
+	let main = "main".to_string();
+	assert_eq!(
+		outcomes,
+		vec![
+			(
+				"clean".to_string(),
+				PullOutcome::Updated {
+					branch: main.clone(),
+					from: "1111111".to_string(),
+					to: "2222222".to_string(),
+					commits: 2,
+				}
+			),
+			(
+				"detached".to_string(),
+				PullOutcome::Detached {
+					commit: "abcdef1".to_string()
+				}
+			),
+			(
+				"dirty".to_string(),
+				PullOutcome::Dirty {
+					branch: main.clone()
+				}
+			),
+			(
+				"no_upstream".to_string(),
+				PullOutcome::NoUpstream { branch: main }
+			),
+		]
+	);
+}
+
+#[test]
+fn switch_branch_skips_repos_without_branch_or_with_changes() {
+	let starting_state = ["clean", "dirty", "missing", "on_branch", "remote_only"]
+		.map(|path| format!("[[repos]]\npath = \"{path}\"\ntags = []\n\n[repos.remotes]\n"))
+		.join("\n");
+	let has_feature = Branches {
+		current: Some("main".to_string()),
+		local: vec!["feature".to_string(), "main".to_string()],
+		remote: vec!["origin/main".to_string()],
+	};
+	let dirty = RepoStatus {
+		head: Head::Branch("main".to_string()),
+		ahead_behind: None,
+		staged: 1,
+		unstaged: 0,
+		untracked: 0,
+		stashes: 0,
+		in_progress: None,
+	};
+	let clean = RepoStatus {
+		staged: 0,
+		..dirty.clone()
+	};
+	let build_git = || {
+		FakeGit::new()
+			.with_branches("clean", has_feature.clone())
+			.with_status("clean", clean.clone())
+			.with_branches("dirty", has_feature.clone())
+			.with_status("dirty", dirty.clone())
+			.with_branches(
+				"missing",
+				Branches {
+					local: vec!["main".to_string()],
+					..has_feature.clone()
+				},
+			)
+			.with_branches(
+				"on_branch",
+				Branches {
+					current: Some("feature".to_string()),
+					..has_feature.clone()
+				},
+			)
+			.with_status("remote_only", clean.clone())
+			.with_branches(
+				"remote_only",
+				Branches {
+					current: Some("main".to_string()),
+					local: vec!["main".to_string()],
+					remote: vec!["origin/feature".to_string(), "origin/main".to_string()],
+				},
+			)
+	};
+
+	for (stash, dirty_outcome) in [
+		(false, BranchOutcome::Dirty),
+		(true, BranchOutcome::Switched { stashed: true }),
+	] {
+		let storage = FakeStorage::new()
+			.with_contents(starting_state.clone())
+			.boxed();
+		let gitopolis = Gitopolis::new(storage, build_git().boxed());
+
+		let entries = gitopolis.switch_branch(
+			gitopolis
+				.list(&TagFilter::all())
+				.expect("Failed to list repos for switching"),
+			"feature",
+			stash,
+		);
+
+		let outcomes: Vec<(String, BranchOutcome)> = entries
+			.into_iter()
+			.map(|(repo, result)| (repo.path, result.expect("switch failed")))
+			.collect();
+		assert_eq!(
+			outcomes,
+			vec![
+				(
+					"clean".to_string(),
+					BranchOutcome::Switched { stashed: false }
+				),
+				("dirty".to_string(), dirty_outcome),
+				("missing".to_string(), BranchOutcome::Missing),
+				("on_branch".to_string(), BranchOutcome::AlreadyOn),
+				(
+					"remote_only".to_string(),
+					BranchOutcome::Switched { stashed: false }
+				),
+			]
+		);
+	}
+}
+
+#[test]
+fn doctor_compares_config_with_disk() {
+	let starting_state = "[[repos]]
+path = \"drifted\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/old_url\"
+
+[repos.remotes.upstream]
+name = \"upstream\"
+url = \"git://example.org/upstream\"
+
+[[repos]]
+path = \"matching\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+[[repos]]
+path = \"missing\"
+tags = []
+
+[repos.remotes]
+";
+	let root = tempfile::tempdir().unwrap();
+	std::fs::create_dir(root.path().join("drifted")).unwrap();
+	std::fs::create_dir(root.path().join("matching")).unwrap();
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+	let gitopolis =
+		Gitopolis::new(storage, FakeGit::new().boxed()).with_root(root.path().to_path_buf());
+
+	let problems = gitopolis
+		.doctor(&["matching".to_string(), "orphan/".to_string()])
+		.expect("doctor failed");
+
+	assert_eq!(
+		problems,
+		vec![
+			Problem::RemotesDiffer {
+				path: "drifted".to_string(),
+				differences: vec![
+					RemoteDifference::UrlDiffers {
+						name: "origin".to_string(),
+						config_url: "git://example.org/old_url".to_string(),
+						repo_url: "git://example.org/test_url".to_string(),
+					},
+					RemoteDifference::NotInRepo {
+						name: "upstream".to_string(),
+						url: "git://example.org/upstream".to_string(),
+					},
+				],
+			},
+			Problem::Missing {
+				path: "missing".to_string()
+			},
+			Problem::Unmanaged {
+				path: "orphan".to_string()
+			},
+		]
+	);
+}
+
+#[test]
+fn tags_go_to_personal_overlay_when_there_is_one() {
+	let shared = "include = [\"team/extra.toml\"]
+
+[[repos]]
+path = \"api\"
+tags = [\"backend\"]
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/api\"
+";
+	let extra = "[[repos]]
+path = \"tools\"
+tags = [\"extra\"]
+";
+	let local = "# my tags
+[[repos]]
+path = \"tools\"
+tags = [\"mine\"]
+";
+	let saved = Arc::new(Mutex::new(vec![]));
+	let shared_saved = saved.clone();
+	let layer_saved = saved.clone();
+	let storage = FakeStorage::new()
+		.with_contents(shared.to_string())
+		.with_file_saved_callback(move |state| {
+			shared_saved.lock().unwrap().push((Layer::Shared, state))
+		})
+		.with_layer(Layer::Include("team/extra.toml".to_string()), extra)
+		.with_layer(Layer::Local, local)
+		.with_layer_saved_callback(move |layer, state| {
+			layer_saved.lock().unwrap().push((layer, state))
+		})
+		.boxed();
+	let mut gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	let tools = gitopolis.show("tools").expect("show failed");
+	assert_eq!(vec!["extra", "mine"], tools.tags);
+	gitopolis
+		.add_tag("mine", &["api".to_string()])
+		.expect("add_tag failed");
+
+	let expected_local = "[[repos]]
+path = \"api\"
+tags = [\"mine\"]
+
+[repos.remotes]
+
+# my tags
+[[repos]]
+path = \"tools\"
+tags = [\"mine\"]
+";
+	assert_eq!(
+		vec![(Layer::Local, expected_local.to_string())],
+		*saved.lock().unwrap()
+	);
+}
+
+#[test]
+fn sync_write_remotes_reports_added_remotes() {
+	let starting_state = "[[repos]]
+path = \"test_repo\"
+tags = []
+
+[repos.remotes.origin]
+name = \"origin\"
+url = \"git://example.org/test_url\"
+
+[repos.remotes.upstream]
+name = \"upstream\"
+url = \"git://example.org/upstream\"
+";
+	let storage = FakeStorage::new()
+		.with_contents(starting_state.to_string())
+		.boxed();
+	let gitopolis = Gitopolis::new(storage, FakeGit::new().boxed());
+
+	let entries = gitopolis
+		.sync_write_remotes(&TagFilter::all())
+		.expect("Failed to sync remotes");
+
+	assert_eq!(entries.len(), 1);
+	let (repo, result) = &entries[0];
+	assert_eq!(repo.path, "test_repo");
+	assert_eq!(
+		result.as_ref().expect("Failed to write remotes"),
+		&vec!["upstream".to_string()]
+	);
+}
+
+/// Records a summary of each event so tests can check what was sent and in what order.
+struct RecordingObserver {
+	events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Observer for RecordingObserver {
+	fn event(&self, event: Event) {
+		let summary = match event {
+			Event::OperationStarted {
+				operation,
+				repo_count,
+				parallel,
+			} => format!("operation started {operation:?} {repo_count}, {parallel} at a time"),
+			Event::RepoStarted { repo } => format!("started {}", repo.path),
+			Event::OutputLine { repo, line, .. } => format!("output {} {line}", repo.path),
+			Event::CloneProgress { repo, progress } => {
+				format!("progress {} {}%", repo.path, progress.percent)
+			}
+			Event::RepoFinished { repo, outcome } => {
+				let outcome = match outcome {
+					RepoOutcome::Clone(CloneOutcome::Cloned { .. }) => "cloned",
+					RepoOutcome::Clone(CloneOutcome::Failed { .. }) => "failed",
+					RepoOutcome::Clone(_) => "skipped",
+					RepoOutcome::Sync(Ok(_)) => "synced",
+					RepoOutcome::Sync(Err(_)) => "sync failed",
+					RepoOutcome::Exec(_) => "ran",
+					RepoOutcome::Fetch(Ok(_)) => "fetched",
+					RepoOutcome::Fetch(Err(_)) => "fetch failed",
+					RepoOutcome::Pull(Ok(_)) => "pulled",
+					RepoOutcome::Pull(Err(_)) => "pull failed",
+					RepoOutcome::Branch(Ok(_)) => "branched",
+					RepoOutcome::Branch(Err(_)) => "branch failed",
+				};
+				format!("finished {} {outcome}", repo.path)
+			}
+			Event::OperationFinished { operation } => {
+				format!("operation finished {operation:?}")
+			}
+			Event::Warning { message } => format!("warning {message}"),
+		};
+		self.events.lock().unwrap().push(summary);
+	}
+}
+
+struct FakeStorage {
+	exists: bool,
+	contents: String,
+	file_saved_callback: Box<dyn Fn(String)>,
+	layers: BTreeMap<Layer, String>,
+	layer_saved_callback: Box<dyn Fn(Layer, String)>,
+}
+
+// fluent interface for building up fake storage (like the "builder pattern")
+impl FakeStorage {
+	fn new() -> Self {
+		Self {
+			exists: false,
+			contents: "".to_string(),
+			file_saved_callback: Box::new(|_| {}),
+			layers: BTreeMap::new(),
+			layer_saved_callback: Box::new(|_, _| {}),
+		}
+	}
+
+	fn with_contents(mut self, contents: String) -> Self {
+		self.exists = true;
+		self.contents = contents;
+		self
+	}
+
+	fn with_file_saved_callback<F>(mut self, callback: F) -> Self
+	where
+		F: Fn(String) + 'static, // todo: would it be possible to shrink lifetime from static?
+	{
+		self.file_saved_callback = Box::new(callback);
+		self
+	}
+
+	fn with_layer(mut self, layer: Layer, contents: &str) -> Self {
+		self.layers.insert(layer, contents.to_string());
+		self
+	}
+
+	fn with_layer_saved_callback<F>(mut self, callback: F) -> Self
+	where
+		F: Fn(Layer, String) + 'static,
+	{
+		self.layer_saved_callback = Box::new(callback);
+		self
+	}
+
+	fn boxed(self) -> Box<dyn Storage> {
+		Box::new(self)
+	}
+}
+
+impl Storage for FakeStorage {
+	fn exists(&self) -> bool {
+		self.exists
+	}
+
+	fn save(&self, state_toml: String) -> Result<(), GitopolisError> {
+		(self.file_saved_callback)(state_toml);
+		Ok(())
+	}
+
+	fn read(&self) -> Result<String, GitopolisError> {
+		Ok(self.contents.to_owned())
+	}
+
+	fn read_layer(&self, layer: &Layer) -> Result<Option<String>, GitopolisError> {
+		Ok(self.layers.get(layer).cloned())
+	}
+
+	fn save_layer(&self, layer: &Layer, state_toml: String) -> Result<(), GitopolisError> {
+		(self.layer_saved_callback)(layer.clone(), state_toml);
+		Ok(())
+	}
+}
+
+struct FakeGit {
+	clone_callback: Box<dyn Fn(String, String) + Sync>,
+	failing_clone_url: Option<String>,
+	failing_fetch_path: Option<String>,
+	failing_status_path: Option<String>,
+	statuses: BTreeMap<String, RepoStatus>,
+	branches: BTreeMap<String, Branches>,
+	branch_calls: Mutex<Vec<String>>,
+}
+
+// fluent interface for building up fake git
+impl FakeGit {
+	fn new() -> Self {
+		Self {
+			clone_callback: Box::new(|_, _| {}),
+			failing_clone_url: None,
+			failing_fetch_path: None,
+			failing_status_path: None,
+			statuses: BTreeMap::new(),
+			branches: BTreeMap::new(),
+			branch_calls: Mutex::new(vec![]),
+		}
+	}
+
+	fn with_clone_callback(mut self, callback: Box<dyn Fn(String, String) + Sync>) -> Self {
+		self.clone_callback = callback;
+		self
+	}
+
+	fn with_failing_clone(mut self, url: &str) -> Self {
+		self.failing_clone_url = Some(url.to_string());
+		self
+	}
+
+	fn with_failing_fetch(mut self, path: &str) -> Self {
+		self.failing_fetch_path = Some(path.to_string());
+		self
+	}
+
+	fn with_status(mut self, path: &str, status: RepoStatus) -> Self {
+		self.statuses.insert(path.to_string(), status);
+		self
+	}
+
+	fn with_failing_status(mut self, path: &str) -> Self {
+		self.failing_status_path = Some(path.to_string());
+		self
+	}
+
+	fn with_branches(mut self, path: &str, branches: Branches) -> Self {
+		self.branches.insert(path.to_string(), branches);
+		self
+	}
+
+	fn boxed(self) -> Box<Self> {
+		Box::new(self)
+	}
+}
+
+impl Git for FakeGit {
+	fn read_url(&self, _path: String, _remote_name: String) -> Result<String, GitopolisError> {
+		Ok("git://example.org/test_url".to_string())
+	}
+
+	fn read_all_remotes(
+		&self,
+		_path: String,
+	) -> Result<std::collections::BTreeMap<String, String>, GitopolisError> {
 		let mut remotes = std::collections::BTreeMap::new();
 		remotes.insert(
 			"origin".to_string(),
@@ -510,12 +1712,111 @@ impl Git for FakeGit {
 		Ok(remotes)
 	}
 
-	fn add_remote(&self, _path: &str, _remote_name: &str, _url: &str) {
+	fn add_remote(
+		&self,
+		_path: &str,
+		_remote_name: &str,
+		_url: &str,
+	) -> Result<(), GitopolisError> {
 		// No-op for fake implementation
+		Ok(())
 	}
 
-	fn clone(&self, path: &str, url: &str) -> Result<(), GitopolisError> {
+	fn clone(&self, path: &str, url: &str, progress: &dyn Fn(CloneProgress)) -> CloneOutput {
 		(self.clone_callback)(path.to_owned(), url.to_owned());
+		progress(CloneProgress {
+			phase: "Receiving objects".to_string(),
+			percent: 100,
+			current: 1,
+			total: 1,
+		});
+		CloneOutput {
+			success: self.failing_clone_url.as_deref() != Some(url),
+			..Default::default()
+		}
+	}
+
+	fn status(&self, path: &str) -> Result<RepoStatus, GitopolisError> {
+		if self.failing_status_path.as_deref() == Some(path) {
+			return Err(GitopolisError::GitError {
+				message: "Couldn't open git repo.".to_string(),
+			});
+		}
+		if let Some(status) = self.statuses.get(path) {
+			return Ok(status.clone());
+		}
+		Ok(RepoStatus {
+			head: Head::Branch("main".to_string()),
+			ahead_behind: Some((1, 2)),
+			staged: 3,
+			unstaged: 4,
+			untracked: 5,
+			stashes: 0,
+			in_progress: None,
+		})
+	}
+
+	fn default_branch(&self, _path: &str) -> Result<Option<String>, GitopolisError> {
+		Ok(Some("main".to_string()))
+	}
+
+	fn fast_forward(&self, _path: &str) -> Result<FastForward, GitopolisError> {
+		Ok(FastForward::Updated {
+			from: "1111111".to_string(),
+			to: "2222222".to_string(),
+			commits: 2,
+		})
+	}
+
+	fn fetch(&self, path: &str, options: &FetchOptions) -> Result<FetchResult, GitopolisError> {
+		if self.failing_fetch_path.as_deref() == Some(path) {
+			return Err(GitopolisError::GitError {
+				message: "Failed to fetch origin".to_string(),
+			});
+		}
+		let mut updated_refs = vec![UpdatedRef {
+			name: "refs/remotes/origin/main".to_string(),
+			old: Some("1111111".to_string()),
+			new: Some("2222222".to_string()),
+		}];
+		if options.prune {
+			updated_refs.push(UpdatedRef {
+				name: "refs/remotes/origin/old".to_string(),
+				old: Some("3333333".to_string()),
+				new: None,
+			});
+		}
+		Ok(FetchResult {
+			remotes: vec!["origin".to_string()],
+			updated_refs,
+		})
+	}
+
+	fn branches(&self, path: &str) -> Result<Branches, GitopolisError> {
+		Ok(self.branches.get(path).cloned().unwrap_or_default())
+	}
+
+	fn create_branch(&self, path: &str, name: &str) -> Result<(), GitopolisError> {
+		let call = format!("create {path} {name}");
+		self.branch_calls.lock().unwrap().push(call);
+		Ok(())
+	}
+
+	fn switch_branch(&self, path: &str, name: &str) -> Result<(), GitopolisError> {
+		let call = format!("switch {path} {name}");
+		self.branch_calls.lock().unwrap().push(call);
+		Ok(())
+	}
+
+	fn delete_branch(&self, path: &str, name: &str, force: bool) -> Result<(), GitopolisError> {
+		let call = format!("delete {path} {name} force={force}");
+		self.branch_calls.lock().unwrap().push(call);
+		Ok(())
+	}
+
+	fn stash(&self, path: &str, message: &str) -> Result<(), GitopolisError> {
+		let call = format!("stash {path} {message}");
+		self.branch_calls.lock().unwrap().push(call);
 		Ok(())
 	}
 }